@@ -367,18 +367,30 @@ pub async fn delete_model(
 =======
 use axum::{
     extract::State,
+    body::Body,
     response::{IntoResponse, Response, sse::{Event, Sse}},
     Json,
-    http::StatusCode,
+    http::{header, StatusCode},
 };
+use futures::{Stream, StreamExt};
 use std::convert::Infallible;
 use std::sync::Arc;
 use chrono::Utc;
 use uuid::Uuid;
 
 use crate::api::types::*;
+use crate::context::{ContextManager, Session};
 use crate::models::manager::ModelManager;
-use crate::inference::{GenerationConfig, GenerationRequest};
+use crate::models::modelfile;
+use crate::inference::backend::InferenceBackend;
+use crate::inference::json_schema::JsonOutput;
+use crate::inference::reasoning::{self, ReasoningStream};
+use crate::inference::tools::{self, Tool, ToolCall, ToolCallStream};
+use crate::inference::vision::{self, IMAGE_MARKER};
+use crate::inference::{
+    ChatTurn, EmbeddingRequest, EmbeddingResponse, GenerationConfig, GenerationRequest, GenerationResponse,
+    LogitBiasToken, StreamEvent,
+};
 
 pub struct AppState {
     pub model_manager: Arc<ModelManager>,
@@ -391,7 +403,7 @@ pub async fn chat_completions(
     let model_parts: Vec<&str> = req.model.split(':').collect();
     let name = model_parts[0];
     let tag = model_parts.get(1).unwrap_or(&"latest");
-    let safe_name = name.replace('/', "_").replace('\\', "_");
+    let safe_name = name.replace(['/', '\\'], "_");
     
     let engine = state.model_manager.load_model(&safe_name, tag).await
         .map_err(|e| (
@@ -399,122 +411,348 @@ pub async fn chat_completions(
             Json(ErrorResponse { error: e.to_string() })
         ))?;
     
-    let prompt = req.messages.iter()
-        .map(|m| format!("{}: {}", m.role, m.content))
-        .collect::<Vec<_>>()
-        .join("\n");
+    let json_output = req.response_format.as_ref()
+        .map(ResponseFormat::json_output)
+        .transpose()
+        .map_err(|e| (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: e })
+        ))?
+        .flatten();
+    if req.n.is_some_and(|n| !(1..=128).contains(&n)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "n must be between 1 and 128".to_string() })
+        ));
+    }
+    if req.top_logprobs.is_some_and(|n| n > 20) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "top_logprobs must be between 0 and 20".to_string() })
+        ));
+    }
+    
+    let (tools, require_tool) = select_tools(req.tools, req.tool_choice.as_ref())?;
+    let tool_format = engine.tool_format();
+    let messages = req.messages.iter()
+        .map(ChatMessage::to_turn)
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|e| (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    let (mut prompt, turns) = build_chat_prompt(&state, &*engine, req.session_id.as_deref(), messages, &tools, require_tool)?;
+    let images = request_images(&*engine, turns.iter().flat_map(|turn| turn.images.iter().cloned()).collect())?;
     
     let gen_config = GenerationConfig {
         temperature: req.temperature.unwrap_or(0.8),
         top_p: req.top_p.unwrap_or(0.95),
         max_tokens: req.max_tokens.unwrap_or(2048),
         stop_sequences: req.stop.unwrap_or_default(),
+        grammar: request_grammar(&*engine, req.grammar, json_output.as_ref())?,
+        logit_bias: request_logit_bias(&*engine, req.logit_bias.as_ref())?,
+        logprobs: req.logprobs.then(|| req.top_logprobs.unwrap_or(0)),
+        seed: req.seed,
         stream: req.stream,
+        prompt_lookup: req.prompt_lookup,
+        adapter_scale: req.adapter_scale,
         ..Default::default()
     };
     
+    let think = req.reasoning_effort.as_deref() != Some("none") && gen_config.grammar.is_none();
+    let starts_in_thinking = prepare_reasoning(&*engine, &mut prompt, think);
+    
+    let n = req.n.unwrap_or(1);
+    let max_tokens = gen_config.max_tokens;
+    let request = GenerationRequest {
+        prompt,
+        config: gen_config,
+        context: None,
+        images,
+    };
+    
     if req.stream {
-        let mut rx = engine.generate_stream(GenerationRequest {
-            prompt,
-            config: gen_config,
-            context: None,
-        }).await.map_err(|e| (
+        let mut rx = engine.generate_choices_stream(request, n).await.map_err(|e| (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse { error: e.to_string() })
         ))?;
         
         let model = req.model.clone();
+        let fingerprint = engine.system_fingerprint();
+        let sessions = state.model_manager.sessions();
+        let session_id = req.session_id.clone();
         let stream = async_stream::stream! {
             let id = Uuid::new_v4().to_string();
             let created = Utc::now().timestamp();
+            let chunk = |index: usize, delta: ChatMessageDelta, logprobs: Option<ChatLogprobs>, finish_reason: Option<String>| ChatCompletionChunk {
+                id: id.clone(),
+                object: "chat.completion.chunk".to_string(),
+                created,
+                model: model.clone(),
+                system_fingerprint: fingerprint.clone(),
+                choices: vec![ChatChoiceDelta {
+                    index,
+                    delta,
+                    logprobs,
+                    finish_reason,
+                }],
+                timings: None,
+            };
+            let content_delta = |text: String| ChatMessageDelta {
+                role: None,
+                content: Some(text),
+                reasoning_content: None,
+                tool_calls: None,
+            };
+            let reasoning_delta = |text: String| ChatMessageDelta {
+                role: None,
+                content: None,
+                reasoning_content: Some(text),
+                tool_calls: None,
+            };
+            let tool_call_delta = |call: &ToolCall, i: usize| ChatMessageDelta {
+                role: None,
+                content: None,
+                reasoning_content: None,
+                tool_calls: Some(vec![OpenAIToolCall::from_call(call, Some(i))]),
+            };
+            
+            // Thinking of reasoning models is split off first; with tools offered, text from
+            // the first tool-call marker on is then held back and parsed
+            let mut reasoners: Vec<_> = (0..n).map(|_| starts_in_thinking.map(ReasoningStream::new)).collect();
+            let mut parsers: Vec<_> = (0..n).map(|_| (!tools.is_empty()).then(|| ToolCallStream::new(tool_format))).collect();
+            let mut replies = vec![String::new(); n];
+            // Tool calls already streamed per choice; each is sent as soon as it is complete
+            let mut sent_calls = vec![0; n];
+            // Token log probabilities not yet attached to a content chunk
+            let mut pending_logprobs = vec![Vec::new(); n];
+            // The first choice continues the session
+            let mut session_reply = None;
             
             while let Some(result) = rx.recv().await {
                 match result {
-                    Ok(text) => {
-                        let chunk = ChatCompletionChunk {
-                            id: id.clone(),
-                            object: "chat.completion.chunk".to_string(),
-                            created,
-                            model: model.clone(),
-                            choices: vec![ChatChoiceDelta {
-                                index: 0,
-                                delta: ChatMessageDelta {
-                                    role: None,
-                                    content: Some(text),
-                                },
-                                finish_reason: None,
-                            }],
+                    Ok((index, StreamEvent::Token(text))) => {
+                        let (reasoning, text) = match reasoners[index].as_mut() {
+                            Some(reasoner) => reasoner.push(&text),
+                            None => (String::new(), text),
                         };
+                        if !reasoning.is_empty() {
+                            let json = serde_json::to_string(&chunk(index, reasoning_delta(reasoning), None, None)).unwrap();
+                            yield Ok::<_, Infallible>(Event::default().data(json));
+                        }
+                        if text.is_empty() {
+                            continue;
+                        }
                         
-                        let json = serde_json::to_string(&chunk).unwrap();
+                        let content = match parsers[index].as_mut() {
+                            Some(parser) => parser.push(&text),
+                            None => Some(text),
+                        };
+                        if let Some(content) = content {
+                            replies[index].push_str(&content);
+                            let logprobs = (!pending_logprobs[index].is_empty())
+                                .then(|| ChatLogprobs::from_tokens(&std::mem::take(&mut pending_logprobs[index])));
+                            let json = serde_json::to_string(&chunk(index, content_delta(content), logprobs, None)).unwrap();
+                            yield Ok::<_, Infallible>(Event::default().data(json));
+                        }
+                        
+                        let calls = parsers[index].as_mut().map(ToolCallStream::take_calls).unwrap_or_default();
+                        for call in calls {
+                            let json = serde_json::to_string(&chunk(index, tool_call_delta(&call, sent_calls[index]), None, None)).unwrap();
+                            yield Ok::<_, Infallible>(Event::default().data(json));
+                            sent_calls[index] += 1;
+                        }
+                    }
+                    Ok((index, StreamEvent::Logprobs(logprobs))) => pending_logprobs[index].extend(logprobs),
+                    Ok((index, StreamEvent::Done(response))) => {
+                        let (reasoning, text) = reasoners[index].take().map(ReasoningStream::finish).unwrap_or_default();
+                        if !reasoning.is_empty() {
+                            let json = serde_json::to_string(&chunk(index, reasoning_delta(reasoning), None, None)).unwrap();
+                            yield Ok::<_, Infallible>(Event::default().data(json));
+                        }
+                        
+                        let mut rest = match parsers[index].as_mut() {
+                            Some(parser) => parser.push(&text).unwrap_or_default(),
+                            None => text,
+                        };
+                        let (parser_rest, tool_calls) = parsers[index].take().map(ToolCallStream::finish).unwrap_or_default();
+                        rest.push_str(&parser_rest);
+                        if !rest.is_empty() {
+                            replies[index].push_str(&rest);
+                            let logprobs = (!pending_logprobs[index].is_empty())
+                                .then(|| ChatLogprobs::from_tokens(&std::mem::take(&mut pending_logprobs[index])));
+                            let json = serde_json::to_string(&chunk(index, content_delta(rest), logprobs, None)).unwrap();
+                            yield Ok::<_, Infallible>(Event::default().data(json));
+                        }
+                        
+                        // Calls completed by the last tokens or left unterminated
+                        for (i, call) in tool_calls.iter().enumerate().skip(sent_calls[index]) {
+                            let json = serde_json::to_string(&chunk(index, tool_call_delta(call, i), None, None)).unwrap();
+                            yield Ok::<_, Infallible>(Event::default().data(json));
+                        }
+                        let finish_reason = if tool_calls.is_empty() {
+                            completion_finish_reason(response.tokens_generated, max_tokens)
+                        } else {
+                            "tool_calls"
+                        };
+                        
+                        let mut final_chunk = chunk(index, ChatMessageDelta {
+                            role: None,
+                            content: None,
+                            reasoning_content: None,
+                            tool_calls: None,
+                        }, None, Some(finish_reason.to_string()));
+                        final_chunk.timings = response.speculative.map(Timings::from);
+                        let json = serde_json::to_string(&final_chunk).unwrap();
                         yield Ok::<_, Infallible>(Event::default().data(json));
+                        
+                        if index == 0 {
+                            let mut reply = ChatTurn::new("assistant", &replies[0]);
+                            reply.tool_calls = tool_calls;
+                            session_reply = Some((reply, response.context));
+                        }
                     }
                     Err(_) => break,
                 }
             }
             
-            let final_chunk = ChatCompletionChunk {
-                id,
-                object: "chat.completion.chunk".to_string(),
-                created,
-                model,
-                choices: vec![ChatChoiceDelta {
-                    index: 0,
-                    delta: ChatMessageDelta {
-                        role: None,
-                        content: None,
-                    },
-                    finish_reason: Some("stop".to_string()),
-                }],
-            };
+            if let (Some(session_id), Some((reply, context))) = (&session_id, session_reply) {
+                save_chat_session(&sessions, &*engine, session_id, &model, turns, reply, context);
+            }
             
-            let json = serde_json::to_string(&final_chunk).unwrap();
-            yield Ok::<_, Infallible>(Event::default().data(json));
+            yield Ok::<_, Infallible>(Event::default().data("[DONE]"));
         };
         
         Ok(Sse::new(stream).into_response())
     } else {
-        let response = engine.generate(GenerationRequest {
-            prompt: prompt.clone(),
-            config: gen_config,
-            context: None,
-        }).await.map_err(|e| (
+        let responses = engine.generate_choices(request, n).await.map_err(|e| (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse { error: e.to_string() })
         ))?;
         
+        let prompt_tokens = responses.first().map_or(0, |r| r.prompt_tokens);
+        let completion_tokens: usize = responses.iter().map(|r| r.tokens_generated).sum();
+        let timings = responses.first().and_then(|r| r.speculative).map(Timings::from);
+        let mut choices = Vec::with_capacity(n);
+        let mut session_reply = None;
+        
+        for (index, response) in responses.into_iter().enumerate() {
+            let (reasoning, text) = match starts_in_thinking {
+                Some(starts_in_thinking) => reasoning::split_reasoning(&response.text, starts_in_thinking),
+                None => (String::new(), response.text),
+            };
+            let (content, tool_calls) = if tools.is_empty() {
+                (text, Vec::new())
+            } else {
+                tools::parse_tool_calls(tool_format, &text)
+            };
+            // Output that fails to parse, typically because max_tokens cut it off, only
+            // fails its own choice
+            let (parsed, valid) = match json_output.as_ref().map(|o| o.parse(&content)).transpose() {
+                Ok(parsed) => (parsed, true),
+                Err(e) => {
+                    tracing::warn!("Choice {} is not valid JSON output: {}", index, e);
+                    (None, false)
+                }
+            };
+            
+            let message = ChatMessage {
+                role: "assistant".to_string(),
+                content: (tool_calls.is_empty() || !content.is_empty()).then(|| MessageContent::Text(content.clone())),
+                reasoning_content: (!reasoning.is_empty()).then_some(reasoning),
+                tool_calls: (!tool_calls.is_empty()).then(|| tool_calls.iter()
+                    .map(|call| OpenAIToolCall::from_call(call, None))
+                    .collect()),
+                tool_call_id: None,
+                parsed,
+            };
+            let finish_reason = if !valid {
+                "length"
+            } else if tool_calls.is_empty() {
+                completion_finish_reason(response.tokens_generated, max_tokens)
+            } else {
+                "tool_calls"
+            };
+            let logprobs = req.logprobs.then(|| ChatLogprobs::from_tokens(&response.logprobs));
+            
+            choices.push(ChatChoice {
+                index,
+                message,
+                logprobs,
+                finish_reason: finish_reason.to_string(),
+            });
+            
+            if index == 0 {
+                let mut reply = ChatTurn::new("assistant", &content);
+                reply.tool_calls = tool_calls;
+                session_reply = Some((reply, response.context));
+            }
+        }
+        
+        if let (Some(session_id), Some((reply, context))) = (&req.session_id, session_reply) {
+            save_chat_session(&state.model_manager.sessions(), &*engine, session_id, &req.model, turns, reply, context);
+        }
+        
         let completion = ChatCompletionResponse {
             id: Uuid::new_v4().to_string(),
             object: "chat.completion".to_string(),
             created: Utc::now().timestamp(),
             model: req.model,
-            choices: vec![ChatChoice {
-                index: 0,
-                message: ChatMessage {
-                    role: "assistant".to_string(),
-                    content: response.text,
-                },
-                finish_reason: "stop".to_string(),
-            }],
+            choices,
             usage: Usage {
-                prompt_tokens: prompt.split_whitespace().count(),
-                completion_tokens: response.tokens_generated,
-                total_tokens: prompt.split_whitespace().count() + response.tokens_generated,
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
             },
+            system_fingerprint: engine.system_fingerprint(),
+            timings,
         };
         
         Ok(Json(completion).into_response())
     }
 }
 
-pub async fn generate(
+pub async fn completions(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<GenerateRequest>,
+    Json(req): Json<CompletionRequest>,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     let model_parts: Vec<&str> = req.model.split(':').collect();
     let name = model_parts[0];
     let tag = model_parts.get(1).unwrap_or(&"latest");
-    let safe_name = name.replace('/', "_").replace('\\', "_");
+    let safe_name = name.replace(['/', '\\'], "_");
+    
+    if req.n.is_some_and(|n| !(1..=128).contains(&n)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "n must be between 1 and 128".to_string() })
+        ));
+    }
+    if req.best_of.is_some_and(|n| !(1..=128).contains(&n)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "best_of must be between 1 and 128".to_string() })
+        ));
+    }
+    let n = req.n.unwrap_or(1);
+    let best_of = req.best_of.unwrap_or(n);
+    if best_of < n {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "best_of must be greater than or equal to n".to_string() })
+        ));
+    }
+    if req.stream && best_of > n {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "best_of cannot be used when streaming".to_string() })
+        ));
+    }
+    if req.logprobs.is_some_and(|k| k > 5) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "logprobs must be between 0 and 5".to_string() })
+        ));
+    }
     
     let engine = state.model_manager.load_model(&safe_name, tag).await
         .map_err(|e| (
@@ -522,95 +760,331 @@ pub async fn generate(
             Json(ErrorResponse { error: e.to_string() })
         ))?;
     
-    let options = req.options.unwrap_or(GenerateOptions {
-        temperature: Some(0.8),
-        top_p: Some(0.95),
-        top_k: Some(40),
-        repeat_penalty: Some(1.1),
-        num_predict: Some(2048),
-    });
+    // The raw prompt goes to the model as-is; only a suffix changes it into an infill prompt
+    let prompt = match &req.suffix {
+        Some(suffix) if !suffix.is_empty() => engine.infill_prompt(&req.prompt, suffix)
+            .map_err(|e| (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse { error: e.to_string() })
+            ))?,
+        _ => req.prompt.clone(),
+    };
     
-    let gen_config = GenerationConfig {
-        temperature: options.temperature.unwrap_or(0.8),
-        top_p: options.top_p.unwrap_or(0.95),
-        top_k: options.top_k.unwrap_or(40),
-        repeat_penalty: options.repeat_penalty.unwrap_or(1.1),
-        max_tokens: options.num_predict.unwrap_or(2048),
+    let max_tokens = req.max_tokens.unwrap_or(16);
+    let mut gen_config = GenerationConfig {
+        max_tokens,
+        stop_sequences: req.stop.clone().unwrap_or_default(),
+        // Ranking best_of candidates needs the log probability of every token
+        logprobs: req.logprobs.or((best_of > n).then_some(0)),
+        seed: req.seed,
         stream: req.stream,
+        prompt_lookup: req.prompt_lookup,
+        adapter_scale: req.adapter_scale,
         ..Default::default()
     };
+    if let Some(v) = req.temperature { gen_config.temperature = v; }
+    if let Some(v) = req.top_p { gen_config.top_p = v; }
+    if let Some(v) = req.presence_penalty { gen_config.presence_penalty = v; }
+    if let Some(v) = req.frequency_penalty { gen_config.frequency_penalty = v; }
+    gen_config.logit_bias = request_logit_bias(&*engine, req.logit_bias.as_ref())?;
+    
+    let id = format!("cmpl-{}", Uuid::new_v4());
+    let created = Utc::now().timestamp();
+    
+    let request = GenerationRequest {
+        prompt,
+        config: gen_config,
+        context: None,
+        images: Vec::new(),
+    };
+    
+    if req.stream {
+        let mut rx = engine.generate_choices_stream(request, n).await.map_err(|e| (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+        
+        let model = req.model.clone();
+        let fingerprint = engine.system_fingerprint();
+        let echo = req.echo.then(|| req.prompt.clone());
+        let stream = async_stream::stream! {
+            let chunk = |index: usize, text: String, logprobs: Option<CompletionLogprobs>, finish_reason: Option<String>| CompletionChunk {
+                id: id.clone(),
+                object: "text_completion".to_string(),
+                created,
+                model: model.clone(),
+                system_fingerprint: fingerprint.clone(),
+                choices: vec![CompletionChoice {
+                    text,
+                    index,
+                    logprobs,
+                    finish_reason,
+                }],
+                timings: None,
+            };
+            
+            // Characters of each choice's text sent so far, for `text_offset`
+            let mut offsets = vec![0; n];
+            if let Some(echo) = &echo {
+                for (index, offset) in offsets.iter_mut().enumerate() {
+                    *offset = echo.chars().count();
+                    let json = serde_json::to_string(&chunk(index, echo.clone(), None, None)).unwrap();
+                    yield Ok::<_, Infallible>(Event::default().data(json));
+                }
+            }
+            
+            let mut pending_logprobs = vec![None; n];
+            while let Some(result) = rx.recv().await {
+                match result {
+                    Ok((index, StreamEvent::Token(text))) => {
+                        let logprobs = pending_logprobs[index].take()
+                            .map(|tokens: Vec<_>| CompletionLogprobs::from_tokens(&tokens, offsets[index]));
+                        offsets[index] += text.chars().count();
+                        let json = serde_json::to_string(&chunk(index, text, logprobs, None)).unwrap();
+                        yield Ok::<_, Infallible>(Event::default().data(json));
+                    }
+                    Ok((index, StreamEvent::Logprobs(tokens))) => pending_logprobs[index] = Some(tokens),
+                    Ok((index, StreamEvent::Done(response))) => {
+                        let finish_reason = completion_finish_reason(response.tokens_generated, max_tokens);
+                        let mut final_chunk = chunk(index, String::new(), None, Some(finish_reason.to_string()));
+                        final_chunk.timings = response.speculative.map(Timings::from);
+                        let json = serde_json::to_string(&final_chunk).unwrap();
+                        yield Ok::<_, Infallible>(Event::default().data(json));
+                    }
+                    Err(_) => break,
+                }
+            }
+            
+            yield Ok::<_, Infallible>(Event::default().data("[DONE]"));
+        };
+        
+        Ok(Sse::new(stream).into_response())
+    } else {
+        let mut candidates = engine.generate_choices(request, best_of).await.map_err(|e| (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+        
+        let prompt_tokens = candidates.first().map_or(0, |r| r.prompt_tokens);
+        let completion_tokens: usize = candidates.iter().map(|r| r.tokens_generated).sum();
+        let timings = candidates.first().and_then(|r| r.speculative).map(Timings::from);
+        
+        // Keep the n candidates with the highest mean token log probability
+        if best_of > n {
+            let mean_logprob = |response: &GenerationResponse| {
+                response.logprobs.iter().map(|t| t.logprob).sum::<f32>() / response.logprobs.len().max(1) as f32
+            };
+            candidates.sort_by(|a, b| mean_logprob(b).total_cmp(&mean_logprob(a)));
+            candidates.truncate(n);
+        }
+        
+        let echo_len = if req.echo { req.prompt.chars().count() } else { 0 };
+        let choices = candidates.into_iter().enumerate().map(|(index, response)| {
+            let text = if req.echo {
+                format!("{}{}", req.prompt, response.text)
+            } else {
+                response.text
+            };
+            
+            CompletionChoice {
+                text,
+                index,
+                logprobs: req.logprobs.map(|_| CompletionLogprobs::from_tokens(&response.logprobs, echo_len)),
+                finish_reason: Some(completion_finish_reason(response.tokens_generated, max_tokens).to_string()),
+            }
+        }).collect();
+        
+        Ok(Json(CompletionResponse {
+            id,
+            object: "text_completion".to_string(),
+            created,
+            model: req.model,
+            choices,
+            usage: Usage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+            },
+            system_fingerprint: engine.system_fingerprint(),
+            timings,
+        }).into_response())
+    }
+}
+
+pub async fn generate(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<GenerateRequest>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let model_parts: Vec<&str> = req.model.split(':').collect();
+    let name = model_parts[0];
+    let tag = model_parts.get(1).unwrap_or(&"latest");
+    let safe_name = name.replace(['/', '\\'], "_");
+    
+    // Ollama convention: an empty prompt only loads (or, with keep_alive 0, unloads) the model
+    if req.prompt.is_empty() {
+        let done_reason = apply_keep_alive(&state, &safe_name, tag, req.keep_alive.as_ref()).await?;
+        return Ok(Json(GenerateResponse {
+            model: req.model,
+            created_at: Utc::now(),
+            response: String::new(),
+            done: true,
+            done_reason: Some(done_reason.to_string()),
+            context: None,
+            total_duration: None,
+            load_duration: None,
+            prompt_eval_count: None,
+            eval_count: None,
+            timings: None,
+        }).into_response());
+    }
+    
+    let num_gpu = req.options.as_ref().and_then(|o| o.num_gpu);
+    let engine = state.model_manager.load_model_with_gpu(&safe_name, tag, num_gpu).await
+        .map_err(|e| (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    
+    let mut gen_config = generation_config(req.options.as_ref(), req.stream);
+    let json_output = ollama_json_output(req.format.as_ref())
+        .map_err(|e| (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: e })
+        ))?;
+    gen_config.grammar = request_grammar(&*engine, req.grammar, json_output.as_ref())?;
+    gen_config.logit_bias = request_logit_bias(&*engine, req.options.as_ref().and_then(|o| o.logit_bias.as_ref()))?;
+    let max_tokens = gen_config.max_tokens;
+    
+    // Continue from the stored token context of the session, if any
+    let sessions = state.model_manager.sessions();
+    let session = match &req.session_id {
+        Some(session_id) => sessions.get_session(session_id)
+            .map_err(|e| (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse { error: e.to_string() })
+            ))?,
+        None => None,
+    };
+    // An explicit `context` from a previous response takes precedence over the session's,
+    // which is only reused with the model that produced it
+    let history = req.context.clone()
+        .or_else(|| session.as_ref()
+            .filter(|s| split_model_name(&s.model) == split_model_name(&req.model))
+            .map(|s| s.context.clone()))
+        .filter(|c| !c.is_empty());
+    let images = req.images.iter().flatten()
+        .map(|image| vision::decode_base64_image(image))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|e| (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    let images = request_images(&*engine, images)?;
+    let prompt = format!("{}{}", IMAGE_MARKER.repeat(images.len()), req.prompt);
+    
+    let mut turns = session.map(|s| s.messages).unwrap_or_default();
+    let mut turn = ChatTurn::new("user", &prompt);
+    turn.images = images.clone();
+    turns.push(turn);
     
     if req.stream {
         let mut rx = engine.generate_stream(GenerationRequest {
-            prompt: req.prompt,
+            prompt,
             config: gen_config,
-            context: None,
+            context: history,
+            images,
         }).await.map_err(|e| (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse { error: e.to_string() })
         ))?;
         
         let model = req.model.clone();
+        let session_id = req.session_id.clone();
         let stream = async_stream::stream! {
+            let mut reply = String::new();
+            let mut finished = None;
+            
             while let Some(result) = rx.recv().await {
                 match result {
-                    Ok(text) => {
+                    Ok(StreamEvent::Token(text)) => {
+                        reply.push_str(&text);
                         let response = GenerateResponse {
                             model: model.clone(),
                             created_at: Utc::now(),
                             response: text,
                             done: false,
+                            done_reason: None,
                             context: None,
                             total_duration: None,
                             load_duration: None,
                             prompt_eval_count: None,
                             eval_count: None,
+                            timings: None,
                         };
                         
-                        let json = serde_json::to_string(&response).unwrap();
-                        yield Ok::<_, Infallible>(Event::default().data(json));
+                        yield Ok::<_, Infallible>(serde_json::to_string(&response).unwrap());
                     }
+                    Ok(StreamEvent::Logprobs(_)) => {}
+                    Ok(StreamEvent::Done(response)) => finished = Some(response),
                     Err(_) => break,
                 }
             }
             
+            let timings = finished.as_ref().and_then(|r| r.speculative).map(Timings::from);
+            let (context, prompt_eval_count, eval_count) = finished
+                .map(|r| (r.context, r.prompt_tokens, r.tokens_generated))
+                .unwrap_or_default();
+            
+            if let Some(session_id) = &session_id {
+                save_chat_session(&sessions, &*engine, session_id, &model, turns, ChatTurn::new("assistant", &reply), context.clone());
+            }
+            
             let final_response = GenerateResponse {
                 model,
                 created_at: Utc::now(),
                 response: String::new(),
                 done: true,
-                context: Some(vec![]),
+                done_reason: Some(completion_finish_reason(eval_count, max_tokens).to_string()),
+                context: Some(context),
                 total_duration: Some(0),
                 load_duration: Some(0),
-                prompt_eval_count: Some(0),
-                eval_count: Some(0),
+                prompt_eval_count: Some(prompt_eval_count),
+                eval_count: Some(eval_count),
+                timings,
             };
             
-            let json = serde_json::to_string(&final_response).unwrap();
-            yield Ok::<_, Infallible>(Event::default().data(json));
+            yield Ok::<_, Infallible>(serde_json::to_string(&final_response).unwrap());
         };
         
-        Ok(Sse::new(stream).into_response())
+        Ok(ndjson_response(stream))
     } else {
         let response = engine.generate(GenerationRequest {
-            prompt: req.prompt,
+            prompt,
             config: gen_config,
-            context: None,
+            context: history,
+            images,
         }).await.map_err(|e| (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse { error: e.to_string() })
         ))?;
+        parse_json_output(json_output.as_ref(), &response.text)?;
+        
+        if let Some(session_id) = &req.session_id {
+            save_chat_session(&sessions, &*engine, session_id, &req.model, turns, ChatTurn::new("assistant", &response.text), response.context.clone());
+        }
         
         let gen_response = GenerateResponse {
             model: req.model,
             created_at: Utc::now(),
             response: response.text,
             done: true,
+            done_reason: Some(completion_finish_reason(response.tokens_generated, max_tokens).to_string()),
             context: Some(response.context),
             total_duration: Some(0),
             load_duration: Some(0),
-            prompt_eval_count: Some(0),
+            prompt_eval_count: Some(response.prompt_tokens),
             eval_count: Some(response.tokens_generated),
+            timings: response.speculative.map(Timings::from),
         };
         
         Ok(Json(gen_response).into_response())
@@ -633,6 +1107,7 @@ pub async fn list_models(
             size: m.size,
             digest: m.digest,
             details: ModelDetails {
+                parent_model: m.parent_model.unwrap_or_default(),
                 format: m.format,
                 family: m.family,
                 parameter_size: m.parameter_size,
@@ -645,43 +1120,43 @@ pub async fn list_models(
 }
 
 pub async fn pull_model(
-    State(state): State<Arc<AppState>>,
-    Json(req): Json<PullRequest>,
+    State(_state): State<Arc<AppState>>,
+    Json(_req): Json<PullRequest>,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     let stream = async_stream::stream! {
-        yield Ok::<_, Infallible>(Event::default().data(
+        yield Ok::<_, Infallible>(
             serde_json::to_string(&PullResponse {
                 status: "pulling manifest".to_string(),
                 digest: None,
                 total: None,
                 completed: None,
             }).unwrap()
-        ));
+        );
         
         tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
         
-        yield Ok::<_, Infallible>(Event::default().data(
+        yield Ok::<_, Infallible>(
             serde_json::to_string(&PullResponse {
                 status: "downloading".to_string(),
                 digest: Some("sha256:abc123".to_string()),
                 total: Some(1000000),
                 completed: Some(500000),
             }).unwrap()
-        ));
+        );
         
         tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
         
-        yield Ok::<_, Infallible>(Event::default().data(
+        yield Ok::<_, Infallible>(
             serde_json::to_string(&PullResponse {
                 status: "success".to_string(),
                 digest: Some("sha256:abc123".to_string()),
                 total: Some(1000000),
                 completed: Some(1000000),
             }).unwrap()
-        ));
+        );
     };
     
-    Ok(Sse::new(stream).into_response())
+    Ok(ndjson_response(stream))
 }
 
 pub async fn show_model(
@@ -691,7 +1166,7 @@ pub async fn show_model(
     let model_parts: Vec<&str> = req.name.split(':').collect();
     let name = model_parts[0];
     let tag = model_parts.get(1).unwrap_or(&"latest");
-    let safe_name = name.replace('/', "_").replace('\\', "_");
+    let safe_name = name.replace(['/', '\\'], "_");
     
     let metadata = state.model_manager.get_metadata(&safe_name, tag)
         .map_err(|e| (
@@ -704,10 +1179,11 @@ pub async fn show_model(
         ))?;
     
     Ok(Json(ShowResponse {
-        modelfile: format!("FROM {}\nPARAMETER temperature 0.8", metadata.path),
+        modelfile: modelfile::render(&metadata),
         parameters: "temperature 0.8\ntop_p 0.95".to_string(),
         template: "{{ .System }}\n{{ .Prompt }}".to_string(),
         details: ModelDetails {
+            parent_model: metadata.parent_model.unwrap_or_default(),
             format: metadata.format,
             family: metadata.family,
             parameter_size: metadata.parameter_size,
@@ -723,7 +1199,7 @@ pub async fn delete_model(
     let model_parts: Vec<&str> = req.name.split(':').collect();
     let name = model_parts[0];
     let tag = model_parts.get(1).unwrap_or(&"latest");
-    let safe_name = name.replace('/', "_").replace('\\', "_");
+    let safe_name = name.replace(['/', '\\'], "_");
     
     state.model_manager.delete_metadata(&safe_name, tag)
         .map_err(|e| (
@@ -767,120 +1243,769 @@ pub async fn ollama_chat(
     let tag = model_parts.get(1).unwrap_or(&"latest");
     
     // Use safe name for lookup
-    let safe_name = name.replace('/', "_").replace('\\', "_");
+    let safe_name = name.replace(['/', '\\'], "_");
     
-    let engine = state.model_manager.load_model(&safe_name, tag).await
+    if req.messages.is_empty() {
+        let done_reason = apply_keep_alive(&state, &safe_name, tag, req.keep_alive.as_ref()).await?;
+        return Ok(Json(OllamaChatResponse {
+            model: req.model,
+            created_at: Utc::now(),
+            message: OllamaChatMessage::assistant(String::new(), &[]),
+            done: true,
+            done_reason: Some(done_reason.to_string()),
+            total_duration: None,
+            load_duration: None,
+            prompt_eval_count: None,
+            eval_count: None,
+            timings: None,
+        }).into_response());
+    }
+    
+    let num_gpu = req.options.as_ref().and_then(|o| o.num_gpu);
+    let engine = state.model_manager.load_model_with_gpu(&safe_name, tag, num_gpu).await
         .map_err(|e| (
             StatusCode::NOT_FOUND,
             Json(ErrorResponse { error: e.to_string() })
         ))?;
     
-    let prompt = req.messages.iter()
-        .map(|m| format!("{}: {}", m.role, m.content))
-        .collect::<Vec<_>>()
-        .join("\n");
+    let tools = req.tools.unwrap_or_default();
+    let tool_format = engine.tool_format();
+    let messages = req.messages.iter()
+        .map(OllamaChatMessage::to_turn)
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|e| (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    let (mut prompt, turns) = build_chat_prompt(&state, &*engine, req.session_id.as_deref(), messages, &tools, false)?;
+    let images = request_images(&*engine, turns.iter().flat_map(|turn| turn.images.iter().cloned()).collect())?;
     
-    let options = req.options.unwrap_or(GenerateOptions {
-        temperature: Some(0.8),
-        top_p: Some(0.95),
-        top_k: Some(40),
-        repeat_penalty: Some(1.1),
-        num_predict: Some(2048),
-    });
+    let mut gen_config = generation_config(req.options.as_ref(), req.stream);
+    let json_output = ollama_json_output(req.format.as_ref())
+        .map_err(|e| (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: e })
+        ))?;
+    gen_config.grammar = request_grammar(&*engine, req.grammar, json_output.as_ref())?;
+    gen_config.logit_bias = request_logit_bias(&*engine, req.options.as_ref().and_then(|o| o.logit_bias.as_ref()))?;
+    let max_tokens = gen_config.max_tokens;
     
-    let gen_config = GenerationConfig {
-        temperature: options.temperature.unwrap_or(0.8),
-        top_p: options.top_p.unwrap_or(0.95),
-        top_k: options.top_k.unwrap_or(40),
-        repeat_penalty: options.repeat_penalty.unwrap_or(1.1),
-        max_tokens: options.num_predict.unwrap_or(2048),
-        stream: req.stream,
-        ..Default::default()
-    };
+    let think = req.think != Some(false) && gen_config.grammar.is_none();
+    let starts_in_thinking = prepare_reasoning(&*engine, &mut prompt, think);
     
     if req.stream {
         let mut rx = engine.generate_stream(GenerationRequest {
             prompt,
             config: gen_config,
             context: None,
+            images,
         }).await.map_err(|e| (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse { error: e.to_string() })
         ))?;
         
         let model = req.model.clone();
+        let sessions = state.model_manager.sessions();
+        let session_id = req.session_id.clone();
         let stream = async_stream::stream! {
+            let partial = |message: OllamaChatMessage| OllamaChatResponse {
+                model: model.clone(),
+                created_at: Utc::now(),
+                message,
+                done: false,
+                done_reason: None,
+                total_duration: None,
+                load_duration: None,
+                prompt_eval_count: None,
+                eval_count: None,
+                timings: None,
+            };
+            
+            let mut reasoner = starts_in_thinking.map(ReasoningStream::new);
+            let mut parser = (!tools.is_empty()).then(|| ToolCallStream::new(tool_format));
+            let mut reply = String::new();
+            let mut context = Vec::new();
+            let mut timings = None;
+            let mut prompt_eval_count = 0;
+            let mut eval_count = 0;
+            
             while let Some(result) = rx.recv().await {
                 match result {
-                    Ok(text) => {
-                        let response = OllamaChatResponse {
-                            model: model.clone(),
-                            created_at: Utc::now(),
-                            message: OllamaChatMessage {
-                                role: "assistant".to_string(),
-                                content: text,
-                            },
-                            done: false,
-                            total_duration: None,
-                            load_duration: None,
-                            prompt_eval_count: None,
-                            eval_count: None,
+                    Ok(StreamEvent::Token(text)) => {
+                        let (thinking, text) = match reasoner.as_mut() {
+                            Some(reasoner) => reasoner.push(&text),
+                            None => (String::new(), text),
                         };
+                        if !thinking.is_empty() {
+                            let mut message = OllamaChatMessage::assistant(String::new(), &[]);
+                            message.thinking = Some(thinking);
+                            yield Ok::<_, Infallible>(serde_json::to_string(&partial(message)).unwrap());
+                        }
+                        if text.is_empty() {
+                            continue;
+                        }
                         
-                        let json = serde_json::to_string(&response).unwrap();
-                        yield Ok::<_, Infallible>(Event::default().data(json));
+                        let content = match parser.as_mut() {
+                            Some(parser) => parser.push(&text),
+                            None => Some(text),
+                        };
+                        if let Some(content) = content {
+                            reply.push_str(&content);
+                            let response = partial(OllamaChatMessage::assistant(content, &[]));
+                            yield Ok::<_, Infallible>(serde_json::to_string(&response).unwrap());
+                        }
+                    }
+                    Ok(StreamEvent::Logprobs(_)) => {}
+                    Ok(StreamEvent::Done(response)) => {
+                        timings = response.speculative.map(Timings::from);
+                        context = response.context;
+                        prompt_eval_count = response.prompt_tokens;
+                        eval_count = response.tokens_generated;
                     }
                     Err(_) => break,
                 }
             }
             
+            let (thinking, text) = reasoner.map(ReasoningStream::finish).unwrap_or_default();
+            if !thinking.is_empty() {
+                let mut message = OllamaChatMessage::assistant(String::new(), &[]);
+                message.thinking = Some(thinking);
+                yield Ok::<_, Infallible>(serde_json::to_string(&partial(message)).unwrap());
+            }
+            
+            // Ollama sends parsed tool calls in a message of their own
+            let mut rest = match parser.as_mut() {
+                Some(parser) => parser.push(&text).unwrap_or_default(),
+                None => text,
+            };
+            let (parser_rest, tool_calls) = parser.map(ToolCallStream::finish).unwrap_or_default();
+            rest.push_str(&parser_rest);
+            if !rest.is_empty() || !tool_calls.is_empty() {
+                reply.push_str(&rest);
+                let response = partial(OllamaChatMessage::assistant(rest, &tool_calls));
+                yield Ok::<_, Infallible>(serde_json::to_string(&response).unwrap());
+            }
+            
+            if let Some(session_id) = &session_id {
+                let mut reply = ChatTurn::new("assistant", &reply);
+                reply.tool_calls = tool_calls;
+                save_chat_session(&sessions, &*engine, session_id, &model, turns, reply, context);
+            }
+            
             let final_response = OllamaChatResponse {
                 model,
                 created_at: Utc::now(),
-                message: OllamaChatMessage {
-                    role: "assistant".to_string(),
-                    content: String::new(),
-                },
+                message: OllamaChatMessage::assistant(String::new(), &[]),
                 done: true,
+                done_reason: Some(completion_finish_reason(eval_count, max_tokens).to_string()),
                 total_duration: Some(0),
                 load_duration: Some(0),
-                prompt_eval_count: Some(0),
-                eval_count: Some(0),
+                prompt_eval_count: Some(prompt_eval_count),
+                eval_count: Some(eval_count),
+                timings,
             };
             
-            let json = serde_json::to_string(&final_response).unwrap();
-            yield Ok::<_, Infallible>(Event::default().data(json));
+            yield Ok::<_, Infallible>(serde_json::to_string(&final_response).unwrap());
         };
         
-        Ok(Sse::new(stream).into_response())
+        Ok(ndjson_response(stream))
     } else {
         let response = engine.generate(GenerationRequest {
-            prompt: prompt.clone(),
+            prompt,
             config: gen_config,
             context: None,
+            images,
         }).await.map_err(|e| (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse { error: e.to_string() })
         ))?;
         
+        let (thinking, text) = match starts_in_thinking {
+            Some(starts_in_thinking) => reasoning::split_reasoning(&response.text, starts_in_thinking),
+            None => (String::new(), response.text),
+        };
+        let (content, tool_calls) = if tools.is_empty() {
+            (text, Vec::new())
+        } else {
+            tools::parse_tool_calls(tool_format, &text)
+        };
+        parse_json_output(json_output.as_ref(), &content)?;
+        let mut message = OllamaChatMessage::assistant(content.clone(), &tool_calls);
+        message.thinking = (!thinking.is_empty()).then_some(thinking);
+        
+        if let Some(session_id) = &req.session_id {
+            let mut reply = ChatTurn::new("assistant", &content);
+            reply.tool_calls = tool_calls;
+            save_chat_session(&state.model_manager.sessions(), &*engine, session_id, &req.model, turns, reply, response.context.clone());
+        }
+        
         let chat_response = OllamaChatResponse {
             model: req.model,
             created_at: Utc::now(),
-            message: OllamaChatMessage {
-                role: "assistant".to_string(),
-                content: response.text,
-            },
+            message,
             done: true,
+            done_reason: Some(completion_finish_reason(response.tokens_generated, max_tokens).to_string()),
             total_duration: Some(0),
             load_duration: Some(0),
-            prompt_eval_count: Some(prompt.split_whitespace().count()),
+            prompt_eval_count: Some(response.prompt_tokens),
             eval_count: Some(response.tokens_generated),
+            timings: response.speculative.map(Timings::from),
         };
         
         Ok(Json(chat_response).into_response())
     }
 }
 
+pub async fn load_model(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ModelLoadRequest>,
+) -> Result<Json<ModelLoadResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let (name, tag) = split_model_name(&req.model);
+    
+    state.model_manager.load_model(&name, &tag).await
+        .map_err(|e| (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    
+    Ok(Json(ModelLoadResponse {
+        model: req.model,
+        status: "loaded".to_string(),
+    }))
+}
+
+pub async fn unload_model(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ModelLoadRequest>,
+) -> Result<Json<ModelLoadResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let (name, tag) = split_model_name(&req.model);
+    
+    state.model_manager.unload_model(&name, &tag).await
+        .map_err(|e| (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    
+    Ok(Json(ModelLoadResponse {
+        model: req.model,
+        status: "unloaded".to_string(),
+    }))
+}
+
+pub async fn save_prompt_cache(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<PromptCacheRequest>,
+) -> Result<Json<PromptCacheResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let (name, tag) = split_model_name(&req.model);
+    
+    let engine = state.model_manager.load_model(&name, &tag).await
+        .map_err(|e| (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    
+    let info = engine.save_prompt_cache(&req.prompt).await
+        .map_err(|e| (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    
+    Ok(Json(PromptCacheResponse {
+        model: req.model,
+        path: info.path,
+        tokens: info.tokens,
+    }))
+}
+
+pub async fn load_prompt_cache(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<PromptCacheRequest>,
+) -> Result<Json<PromptCacheResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let (name, tag) = split_model_name(&req.model);
+    
+    let engine = state.model_manager.load_model(&name, &tag).await
+        .map_err(|e| (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    
+    let info = engine.load_prompt_cache(&req.prompt).await
+        .map_err(|e| (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?
+        .ok_or_else(|| (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse { error: "No saved cache for this prompt".to_string() })
+        ))?;
+    
+    Ok(Json(PromptCacheResponse {
+        model: req.model,
+        path: info.path,
+        tokens: info.tokens,
+    }))
+}
+
+pub async fn embeddings(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<EmbeddingsRequest>,
+) -> Result<Json<EmbeddingsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let response = run_embedding(&state, &req.model, EmbeddingRequest {
+        inputs: req.input.into_vec(),
+        pooling: req.pooling,
+        normalize: req.normalize.unwrap_or(true),
+        truncate: false,
+    }).await?;
+    
+    let data = response.embeddings.into_iter().enumerate()
+        .map(|(index, embedding)| EmbeddingData {
+            object: "embedding".to_string(),
+            embedding,
+            index,
+        })
+        .collect();
+    
+    Ok(Json(EmbeddingsResponse {
+        object: "list".to_string(),
+        data,
+        model: req.model,
+        usage: EmbeddingUsage {
+            prompt_tokens: response.prompt_tokens,
+            total_tokens: response.prompt_tokens,
+        },
+    }))
+}
+
+/// Jina/Cohere-style reranking: score documents against a query, best first.
+pub async fn rerank(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<RerankRequest>,
+) -> Result<Json<RerankResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let (name, tag) = split_model_name(&req.model);
+    let engine = state.model_manager.load_model(&name, &tag).await
+        .map_err(|e| (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    
+    let documents: Vec<String> = req.documents.into_iter().map(RerankDocument::into_text).collect();
+    let response = engine.rerank(crate::inference::RerankRequest {
+        query: req.query,
+        documents: documents.clone(),
+    }).await.map_err(|e| (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse { error: e.to_string() })
+    ))?;
+    
+    let mut results: Vec<RerankResult> = response.scores.into_iter().enumerate()
+        .map(|(index, relevance_score)| RerankResult {
+            index,
+            relevance_score,
+            document: None,
+        })
+        .collect();
+    results.sort_by(|a, b| b.relevance_score.total_cmp(&a.relevance_score));
+    results.truncate(req.top_n.unwrap_or(results.len()));
+    
+    if req.return_documents {
+        for result in &mut results {
+            result.document = Some(RerankResultDocument { text: documents[result.index].clone() });
+        }
+    }
+    
+    Ok(Json(RerankResponse {
+        model: req.model,
+        results,
+        usage: RerankUsage {
+            total_tokens: response.prompt_tokens,
+        },
+    }))
+}
+
+pub async fn tokenize(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<TokenizeRequest>,
+) -> Result<Json<TokenizeResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let (name, tag) = split_model_name(&req.model);
+    let engine = state.model_manager.load_model(&name, &tag).await
+        .map_err(|e| (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    
+    let to_error = |e: anyhow::Error| (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse { error: e.to_string() })
+    );
+    let ids = engine.tokenize(&req.content, req.add_special).map_err(to_error)?;
+    
+    let tokens = if req.with_pieces {
+        ids.into_iter()
+            .map(|id| Ok(TokenizedToken::Piece { id, piece: engine.token_piece(id, true)? }))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(to_error)?
+    } else {
+        ids.into_iter().map(TokenizedToken::Id).collect()
+    };
+    
+    Ok(Json(TokenizeResponse {
+        model: req.model,
+        tokens,
+    }))
+}
+
+pub async fn detokenize(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<DetokenizeRequest>,
+) -> Result<Json<DetokenizeResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let (name, tag) = split_model_name(&req.model);
+    let engine = state.model_manager.load_model(&name, &tag).await
+        .map_err(|e| (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    
+    let content = engine.detokenize(&req.tokens, req.render_special)
+        .map_err(|e| (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    
+    Ok(Json(DetokenizeResponse {
+        model: req.model,
+        content,
+    }))
+}
+
+pub async fn ollama_embed(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<OllamaEmbedRequest>,
+) -> Result<Json<OllamaEmbedResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let start = std::time::Instant::now();
+    let response = run_embedding(&state, &req.model, EmbeddingRequest {
+        inputs: req.input.into_vec(),
+        pooling: req.pooling,
+        normalize: true,
+        truncate: req.truncate.unwrap_or(true),
+    }).await?;
+    
+    Ok(Json(OllamaEmbedResponse {
+        model: req.model,
+        embeddings: response.embeddings,
+        total_duration: Some(start.elapsed().as_nanos() as u64),
+        load_duration: None,
+        prompt_eval_count: Some(response.prompt_tokens),
+    }))
+}
+
+/// Legacy Ollama endpoint: one prompt, raw (unnormalized) embedding.
+pub async fn ollama_embeddings(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<OllamaEmbeddingsRequest>,
+) -> Result<Json<OllamaEmbeddingsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let response = run_embedding(&state, &req.model, EmbeddingRequest {
+        inputs: vec![req.prompt],
+        pooling: None,
+        normalize: false,
+        truncate: true,
+    }).await?;
+    
+    Ok(Json(OllamaEmbeddingsResponse {
+        embedding: response.embeddings.into_iter().next().unwrap_or_default(),
+    }))
+}
+
+async fn run_embedding(
+    state: &AppState,
+    model: &str,
+    request: EmbeddingRequest,
+) -> Result<EmbeddingResponse, (StatusCode, Json<ErrorResponse>)> {
+    let (name, tag) = split_model_name(model);
+    let engine = state.model_manager.load_model(&name, &tag).await
+        .map_err(|e| (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    
+    engine.embed(request).await
+        .map_err(|e| (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e.to_string() })
+        ))
+}
+
+/// Load or unload a model for an empty generate/chat request, returning the Ollama `done_reason`.
+async fn apply_keep_alive(
+    state: &AppState,
+    name: &str,
+    tag: &str,
+    keep_alive: Option<&KeepAlive>,
+) -> Result<&'static str, (StatusCode, Json<ErrorResponse>)> {
+    if keep_alive.map(KeepAlive::is_zero).unwrap_or(false) {
+        state.model_manager.unload_model(name, tag).await
+            .map_err(|e| (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse { error: e.to_string() })
+            ))?;
+        Ok("unload")
+    } else {
+        state.model_manager.load_model(name, tag).await
+            .map_err(|e| (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse { error: e.to_string() })
+            ))?;
+        Ok("load")
+    }
+}
+
+/// Prepend the stored history of `session_id` to the request messages and render the
+/// conversation, with any offered tools, through the model's chat template.
+/// Returns the prompt and the full conversation.
+fn build_chat_prompt(
+    state: &AppState,
+    engine: &dyn InferenceBackend,
+    session_id: Option<&str>,
+    messages: Vec<ChatTurn>,
+    tools: &[Tool],
+    require_tool: bool,
+) -> Result<(String, Vec<ChatTurn>), (StatusCode, Json<ErrorResponse>)> {
+    let mut turns = match session_id {
+        Some(session_id) => state.model_manager.sessions().get_session(session_id)
+            .map_err(|e| (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse { error: e.to_string() })
+            ))?
+            .map(|s| s.messages)
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+    turns.extend(messages);
+    
+    let rendered = tools::render_messages(engine.tool_format(), &turns, tools, require_tool);
+    let prompt = engine.apply_chat_template(&rendered)
+        .map_err(|e| (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    
+    Ok((prompt, turns))
+}
+
+/// Persist a conversation with the assistant reply appended, trimmed to the context
+/// size. Failures are logged, not returned, since the reply has already been produced.
+fn save_chat_session(
+    sessions: &ContextManager,
+    engine: &dyn InferenceBackend,
+    session_id: &str,
+    model: &str,
+    mut turns: Vec<ChatTurn>,
+    reply: ChatTurn,
+    context: Vec<i32>,
+) {
+    turns.push(reply);
+    let session = Session {
+        model: model.to_string(),
+        messages: turns,
+        context,
+        updated_at: None,
+    };
+    if let Err(e) = sessions.save_session(session_id, session, |turns| engine.chat_tokens(turns)) {
+        tracing::warn!("Failed to save session {}: {}", session_id, e);
+    }
+}
+
+/// Map Ollama `options` onto a generation config, keeping the defaults for anything unset.
+fn generation_config(options: Option<&GenerateOptions>, stream: bool) -> GenerationConfig {
+    let mut config = GenerationConfig {
+        stream,
+        ..Default::default()
+    };
+    let Some(options) = options else {
+        return config;
+    };
+    
+    if let Some(v) = options.temperature { config.temperature = v; }
+    if let Some(v) = options.top_p { config.top_p = v; }
+    if let Some(v) = options.top_k { config.top_k = v; }
+    if let Some(v) = options.min_p { config.min_p = v; }
+    if let Some(v) = options.typical_p { config.typical_p = v; }
+    if let Some(v) = options.repeat_penalty { config.repeat_penalty = v; }
+    if let Some(v) = options.repeat_last_n { config.repeat_last_n = v; }
+    if let Some(v) = options.presence_penalty { config.presence_penalty = v; }
+    if let Some(v) = options.frequency_penalty { config.frequency_penalty = v; }
+    if let Some(v) = options.mirostat { config.mirostat = v; }
+    if let Some(v) = options.mirostat_tau { config.mirostat_tau = v; }
+    if let Some(v) = options.mirostat_eta { config.mirostat_eta = v; }
+    if let Some(v) = options.tfs_z { config.tfs_z = v; }
+    if let Some(v) = options.dry_multiplier { config.dry_multiplier = v; }
+    if let Some(v) = options.dry_base { config.dry_base = v; }
+    if let Some(v) = options.dry_allowed_length { config.dry_allowed_length = v; }
+    if let Some(v) = options.dry_penalty_last_n { config.dry_penalty_last_n = v; }
+    if let Some(v) = options.xtc_probability { config.xtc_probability = v; }
+    if let Some(v) = options.xtc_threshold { config.xtc_threshold = v; }
+    if let Some(v) = options.num_predict { config.max_tokens = v; }
+    if let Some(v) = &options.stop { config.stop_sequences = v.clone(); }
+    config.seed = options.seed;
+    config.num_ctx = options.num_ctx;
+    config.num_batch = options.num_batch;
+    config.num_thread = options.num_thread;
+    config.num_keep = options.num_keep;
+    config.prompt_lookup = options.prompt_lookup;
+    config.adapter_scale = options.adapter_scale;
+    
+    if options.penalize_newline.is_some() {
+        tracing::warn!("Option penalize_newline is deprecated and ignored");
+    }
+    if config.tfs_z != 1.0 {
+        tracing::warn!("Option tfs_z is ignored: llama.cpp no longer implements tail-free sampling");
+    }
+    
+    config
+}
+
+/// OpenAI `finish_reason` and Ollama `done_reason`: "length" when generation hit
+/// `max_tokens`, otherwise "stop".
+fn completion_finish_reason(tokens_generated: usize, max_tokens: usize) -> &'static str {
+    if tokens_generated >= max_tokens {
+        "length"
+    } else {
+        "stop"
+    }
+}
+
+/// Stream serialized objects as `application/x-ndjson`, one JSON object per line,
+/// which is what Ollama clients expect from the /api/* routes.
+fn ndjson_response<S>(stream: S) -> Response
+where
+    S: Stream<Item = Result<String, Infallible>> + Send + 'static,
+{
+    let lines = stream.map(|line| line.map(|line| line + "\n"));
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    ).into_response()
+}
+
+/// Check that the model can take the images of a request, which are passed on in prompt order.
+fn request_images(
+    engine: &dyn InferenceBackend,
+    images: Vec<Vec<u8>>,
+) -> Result<Vec<Vec<u8>>, (StatusCode, Json<ErrorResponse>)> {
+    if !images.is_empty() && !engine.supports_images() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "Model has no vision projector (mmproj); images are not supported".to_string() })
+        ));
+    }
+    Ok(images)
+}
+
+/// Turn thinking of a reasoning model off in the prompt when `think` is false. Returns
+/// whether the output starts inside the thinking block, or `None` for other models.
+fn prepare_reasoning(engine: &dyn InferenceBackend, prompt: &mut String, think: bool) -> Option<bool> {
+    let format = engine.reasoning_format()?;
+    if !think {
+        format.disable_thinking(prompt);
+    }
+    Some(format.starts_in_thinking(prompt))
+}
+
+/// Convert a request's `logit_bias` and check it against the vocabulary.
+#[allow(clippy::type_complexity)]
+fn request_logit_bias(
+    engine: &dyn InferenceBackend,
+    logit_bias: Option<&LogitBias>,
+) -> Result<Vec<(LogitBiasToken, f32)>, (StatusCode, Json<ErrorResponse>)> {
+    let entries = logit_bias.map(LogitBias::entries).unwrap_or_default();
+    if entries.iter().any(|(_, bias)| !(-100.0..=100.0).contains(bias)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "logit_bias values must be between -100 and 100".to_string() })
+        ));
+    }
+    
+    engine.validate_logit_bias(&entries)
+        .map_err(|e| (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: e.to_string() })
+        ))?;
+    Ok(entries)
+}
+
+/// Resolve the grammar of a request, from `grammar` or a JSON output format, and check it
+/// up front so a malformed one is a 400, not a failed generation.
+fn request_grammar(
+    engine: &dyn InferenceBackend,
+    grammar: Option<String>,
+    json_output: Option<&JsonOutput>,
+) -> Result<Option<String>, (StatusCode, Json<ErrorResponse>)> {
+    let grammar = match (grammar, json_output) {
+        (Some(_), Some(_)) => return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: "grammar cannot be combined with a JSON format".to_string() })
+        )),
+        (grammar, None) => grammar,
+        (None, Some(json_output)) => Some(json_output.grammar()
+            .map_err(|e| (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse { error: e.to_string() })
+            ))?),
+    };
+    
+    if let Some(grammar) = &grammar {
+        engine.validate_grammar(grammar)
+            .map_err(|e| (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse { error: e.to_string() })
+            ))?;
+    }
+    Ok(grammar)
+}
+
+/// Parse and validate the reply when JSON output was requested.
+fn parse_json_output(
+    json_output: Option<&JsonOutput>,
+    text: &str,
+) -> Result<Option<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    json_output.map(|o| o.parse(text)).transpose()
+        .map_err(|e| (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse { error: e.to_string() })
+        ))
+}
+
+/// Apply `tool_choice` to the offered tools. Returns the tools shown to the model and
+/// whether it has to call one of them.
+fn select_tools(
+    tools: Option<Vec<Tool>>,
+    choice: Option<&ToolChoice>,
+) -> Result<(Vec<Tool>, bool), (StatusCode, Json<ErrorResponse>)> {
+    let tools = tools.unwrap_or_default();
+    Ok(match choice {
+        Some(ToolChoice::Mode(mode)) if mode == "none" => (Vec::new(), false),
+        Some(ToolChoice::Mode(mode)) if mode == "required" => (tools, true),
+        Some(ToolChoice::Function { function }) => {
+            let chosen: Vec<Tool> = tools.into_iter().filter(|t| t.function.name == function.name).collect();
+            if chosen.is_empty() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse { error: format!("unknown function in tool_choice: {}", function.name) })
+                ));
+            }
+            (chosen, true)
+        }
+        _ => (tools, false),
+    })
+}
+
+/// Split `name:tag` into the sanitized name used for metadata lookup and its tag.
+pub(crate) fn split_model_name(model: &str) -> (String, String) {
+    let model_parts: Vec<&str> = model.split(':').collect();
+    let name = model_parts[0];
+    let tag = model_parts.get(1).unwrap_or(&"latest");
+    (name.replace(['/', '\\'], "_"), tag.to_string())
+}
+
 pub async fn version() -> Json<VersionResponse> {
     Json(VersionResponse {
         version: env!("CARGO_PKG_VERSION").to_string(),
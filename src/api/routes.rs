@@ -46,14 +46,25 @@ pub fn create_router(state: Arc<AppState>) -> Router {
         .route("/", get(|| async { "Rust LLM Runner is running" }))
         // OpenAI compatible endpoints
         .route("/v1/chat/completions", post(handlers::chat_completions))
+        .route("/v1/completions", post(handlers::completions))
+        .route("/v1/embeddings", post(handlers::embeddings))
+        .route("/v1/rerank", post(handlers::rerank))
         .route("/v1/models", get(handlers::list_openai_models))
         // Ollama compatible endpoints
         .route("/api/generate", post(handlers::generate))
         .route("/api/chat", post(handlers::ollama_chat))
+        .route("/api/embed", post(handlers::ollama_embed))
+        .route("/api/embeddings", post(handlers::ollama_embeddings))
+        .route("/api/tokenize", post(handlers::tokenize))
+        .route("/api/detokenize", post(handlers::detokenize))
         .route("/api/tags", get(handlers::list_models))
         .route("/api/pull", post(handlers::pull_model))
         .route("/api/show", post(handlers::show_model))
         .route("/api/delete", delete(handlers::delete_model))
+        .route("/api/load", post(handlers::load_model))
+        .route("/api/unload", post(handlers::unload_model))
+        .route("/api/cache/save", post(handlers::save_prompt_cache))
+        .route("/api/cache/load", post(handlers::load_prompt_cache))
         .route("/api/version", get(handlers::version))
         // Health check
         .route("/health", get(|| async { "OK" }))
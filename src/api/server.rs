@@ -1,14 +1,22 @@
 use anyhow::Result;
 use std::sync::Arc;
-use crate::api::handlers::AppState;
+use crate::api::handlers::{split_model_name, AppState};
 use crate::api::routes::create_router;
 use crate::config::Config;
 use crate::models::manager::ModelManager;
 
-pub async fn start_server(host: &str, port: u16) -> Result<()> {
+pub async fn start_server(host: &str, port: u16, preload: &[String]) -> Result<()> {
     let config = Arc::new(Config::load()?);
     let model_manager = Arc::new(ModelManager::new(config.clone())?);
     
+    // Warm up requested models before accepting traffic
+    for model_name in preload {
+        let (name, tag) = split_model_name(model_name);
+        
+        tracing::info!("Preloading model: {}", model_name);
+        model_manager.load_model(&name, &tag).await?;
+    }
+    
     let state = Arc::new(AppState {
         model_manager,
     });
@@ -179,6 +179,13 @@ pub struct ErrorResponse {
 =======
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use crate::inference::json_schema::JsonOutput;
+use crate::inference::speculative::SpeculativeStats;
+use crate::inference::tools::{Tool, ToolCall};
+use crate::inference::vision::{self, IMAGE_MARKER};
+use crate::inference::{request_seed, ChatTurn, EmbeddingPooling, LogitBiasToken, TokenLogprob};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatCompletionRequest {
@@ -194,12 +201,232 @@ pub struct ChatCompletionRequest {
     pub stream: bool,
     #[serde(default)]
     pub stop: Option<Vec<String>>,
+    /// Number of choices to generate from the prompt
+    #[serde(default)]
+    pub n: Option<usize>,
+    /// Sample with this seed; same seed, prompt and parameters give the same reply
+    /// as long as `system_fingerprint` is unchanged
+    #[serde(default, deserialize_with = "deserialize_seed")]
+    pub seed: Option<u32>,
+    #[serde(default)]
+    pub logit_bias: Option<LogitBias>,
+    /// "none" turns thinking off for reasoning models; other levels leave it on
+    #[serde(default)]
+    pub reasoning_effort: Option<String>,
+    /// Return the log probability of each output token
+    #[serde(default)]
+    pub logprobs: bool,
+    /// Alternatives reported per token (0-20); requires `logprobs`
+    #[serde(default)]
+    pub top_logprobs: Option<usize>,
+    #[serde(default)]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(default)]
+    pub tool_choice: Option<ToolChoice>,
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+    /// Extension: GBNF grammar constraining the reply
+    #[serde(default)]
+    pub grammar: Option<String>,
+    /// Extension: persist the conversation server-side under this id
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Extension: prompt lookup decoding n-gram size (0 = off)
+    #[serde(default)]
+    pub prompt_lookup: Option<usize>,
+    /// Extension: scale for the model's LoRA adapters (0 = base model)
+    #[serde(default)]
+    pub adapter_scale: Option<f32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
     pub role: String,
-    pub content: String,
+    /// Null for assistant messages that only call tools
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+    /// Thinking of a reasoning model, kept out of `content`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<OpenAIToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Extension: the reply as JSON when a JSON `response_format` was requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parsed: Option<serde_json::Value>,
+}
+
+impl ChatMessage {
+    /// Fails when an image part can't be read.
+    pub fn to_turn(&self) -> anyhow::Result<ChatTurn> {
+        let mut turn = ChatTurn::new(&self.role, "");
+        match &self.content {
+            Some(MessageContent::Text(text)) => turn.content = text.clone(),
+            Some(MessageContent::Parts(parts)) => {
+                for part in parts {
+                    match part {
+                        ContentPart::Text { text } => turn.content.push_str(text),
+                        ContentPart::ImageUrl { image_url } => {
+                            turn.images.push(vision::decode_image_url(&image_url.url)?);
+                            turn.content.push_str(IMAGE_MARKER);
+                        }
+                    }
+                }
+            }
+            None => {}
+        }
+        turn.tool_calls = self.tool_calls.iter().flatten().map(OpenAIToolCall::to_call).collect();
+        turn.tool_call_id = self.tool_call_id.clone();
+        Ok(turn)
+    }
+}
+
+/// Message content: a plain string, or an array of text and image parts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageUrl {
+    /// A base64 `data:` URL; remote URLs and file paths are rejected
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// OpenAI `logit_bias`: a map from token id to bias (-100 to 100). As an extension, keys
+/// that are not ids are tokenized server-side, and llama.cpp-style `[[token or text, bias]]`
+/// pairs are accepted too.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LogitBias {
+    Map(HashMap<String, f32>),
+    Pairs(Vec<(LogitBiasToken, f32)>),
+}
+
+impl LogitBias {
+    pub fn entries(&self) -> Vec<(LogitBiasToken, f32)> {
+        match self {
+            LogitBias::Map(map) => map.iter().map(|(key, bias)| (key.as_str().into(), *bias)).collect(),
+            LogitBias::Pairs(pairs) => pairs.clone(),
+        }
+    }
+}
+
+/// OpenAI `response_format`: `text`, `json_object` or `json_schema`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseFormat {
+    #[serde(rename = "type")]
+    pub format_type: String,
+    #[serde(default)]
+    pub json_schema: Option<JsonSchemaFormat>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonSchemaFormat {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub schema: serde_json::Value,
+    #[serde(default)]
+    pub strict: Option<bool>,
+}
+
+impl ResponseFormat {
+    pub fn json_output(&self) -> Result<Option<JsonOutput>, String> {
+        match self.format_type.as_str() {
+            "text" => Ok(None),
+            "json_object" => Ok(Some(JsonOutput::Any)),
+            "json_schema" => self.json_schema.as_ref()
+                .map(|f| Some(JsonOutput::Schema(f.schema.clone())))
+                .ok_or_else(|| "response_format json_schema requires a schema".to_string()),
+            other => Err(format!("Unsupported response_format type: {}", other)),
+        }
+    }
+}
+
+/// Ollama `format`: "json" or a JSON Schema object.
+pub fn ollama_json_output(format: Option<&serde_json::Value>) -> Result<Option<JsonOutput>, String> {
+    match format {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(serde_json::Value::String(s)) if s.is_empty() => Ok(None),
+        Some(serde_json::Value::String(s)) if s == "json" => Ok(Some(JsonOutput::Any)),
+        Some(schema @ serde_json::Value::Object(_)) => Ok(Some(JsonOutput::Schema(schema.clone()))),
+        Some(other) => Err(format!("Unsupported format: {}", other)),
+    }
+}
+
+/// `tool_choice`: "none", "auto", "required" or a specific function.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ToolChoice {
+    Mode(String),
+    Function { function: ToolChoiceFunction },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolChoiceFunction {
+    pub name: String,
+}
+
+/// OpenAI tool call; `arguments` is a JSON-encoded string.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAIToolCall {
+    /// Position of the call, only set in streamed deltas
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    pub id: String,
+    #[serde(rename = "type", default = "default_function_type")]
+    pub call_type: String,
+    pub function: OpenAIFunctionCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAIFunctionCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+fn deserialize_seed<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    Ok(Option::<i64>::deserialize(deserializer)?.and_then(request_seed))
+}
+
+fn default_function_type() -> String {
+    "function".to_string()
+}
+
+impl OpenAIToolCall {
+    pub fn from_call(call: &ToolCall, index: Option<usize>) -> Self {
+        Self {
+            index,
+            id: call.id.clone(),
+            call_type: default_function_type(),
+            function: OpenAIFunctionCall {
+                name: call.name.clone(),
+                arguments: call.arguments.to_string(),
+            },
+        }
+    }
+
+    pub fn to_call(&self) -> ToolCall {
+        let arguments = serde_json::from_str(&self.function.arguments)
+            .unwrap_or_else(|_| serde_json::Value::String(self.function.arguments.clone()));
+        ToolCall {
+            id: self.id.clone(),
+            name: self.function.name.clone(),
+            arguments,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -210,15 +437,64 @@ pub struct ChatCompletionResponse {
     pub model: String,
     pub choices: Vec<ChatChoice>,
     pub usage: Usage,
+    /// Identifies the model weights and backend build; seeded outputs match only under the same one
+    pub system_fingerprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatChoice {
     pub index: usize,
     pub message: ChatMessage,
+    pub logprobs: Option<ChatLogprobs>,
     pub finish_reason: String,
 }
 
+/// Per-token log probabilities of a chat choice.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatLogprobs {
+    pub content: Vec<ChatTokenLogprob>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatTokenLogprob {
+    pub token: String,
+    pub logprob: f32,
+    pub bytes: Vec<u8>,
+    pub top_logprobs: Vec<ChatTopLogprob>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatTopLogprob {
+    pub token: String,
+    pub logprob: f32,
+    pub bytes: Vec<u8>,
+}
+
+impl ChatLogprobs {
+    pub fn from_tokens(tokens: &[TokenLogprob]) -> Self {
+        let content = tokens
+            .iter()
+            .map(|t| ChatTokenLogprob {
+                token: t.token.clone(),
+                logprob: t.logprob,
+                bytes: t.token.as_bytes().to_vec(),
+                top_logprobs: t
+                    .top_logprobs
+                    .iter()
+                    .map(|top| ChatTopLogprob {
+                        token: top.token.clone(),
+                        logprob: top.logprob,
+                        bytes: top.token.as_bytes().to_vec(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self { content }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: usize,
@@ -226,19 +502,44 @@ pub struct Usage {
     pub total_tokens: usize,
 }
 
+/// Speculative decoding counters, named after the `timings` of llama.cpp's server.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Timings {
+    pub draft_n: usize,
+    pub draft_n_accepted: usize,
+    /// Share of drafted tokens the target model accepted
+    pub draft_acceptance_rate: f32,
+}
+
+impl From<SpeculativeStats> for Timings {
+    fn from(stats: SpeculativeStats) -> Self {
+        Self {
+            draft_n: stats.drafted,
+            draft_n_accepted: stats.accepted,
+            draft_acceptance_rate: stats.acceptance_rate(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatCompletionChunk {
     pub id: String,
     pub object: String,
     pub created: i64,
     pub model: String,
+    pub system_fingerprint: String,
     pub choices: Vec<ChatChoiceDelta>,
+    /// Sent with the chunk that finishes a choice
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatChoiceDelta {
     pub index: usize,
     pub delta: ChatMessageDelta,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<ChatLogprobs>,
     pub finish_reason: Option<String>,
 }
 
@@ -248,16 +549,168 @@ pub struct ChatMessageDelta {
     pub role: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<OpenAIToolCall>>,
+}
+
+/// OpenAI legacy `/v1/completions` request: raw prompt, no chat template.
+#[derive(Debug, Deserialize)]
+pub struct CompletionRequest {
+    pub model: String,
+    #[serde(default)]
+    pub prompt: String,
+    /// Text after the completion; turns the request into fill-in-the-middle
+    #[serde(default)]
+    pub suffix: Option<String>,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub n: Option<usize>,
+    #[serde(default)]
+    pub best_of: Option<usize>,
+    #[serde(default, deserialize_with = "deserialize_seed")]
+    pub seed: Option<u32>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub logprobs: Option<usize>,
+    /// Include the prompt in the returned text
+    #[serde(default)]
+    pub echo: bool,
+    #[serde(default)]
+    pub stop: Option<Vec<String>>,
+    #[serde(default)]
+    pub presence_penalty: Option<f32>,
+    #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default)]
+    pub logit_bias: Option<LogitBias>,
+    /// Extension: prompt lookup decoding n-gram size (0 = off)
+    #[serde(default)]
+    pub prompt_lookup: Option<usize>,
+    /// Extension: scale for the model's LoRA adapters (0 = base model)
+    #[serde(default)]
+    pub adapter_scale: Option<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompletionResponse {
+    pub id: String,
+    pub object: String,
+    pub created: i64,
+    pub model: String,
+    pub choices: Vec<CompletionChoice>,
+    pub usage: Usage,
+    pub system_fingerprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompletionChoice {
+    pub text: String,
+    pub index: usize,
+    pub logprobs: Option<CompletionLogprobs>,
+    pub finish_reason: Option<String>,
+}
+
+/// Legacy completions log probabilities, one entry per token in each list.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompletionLogprobs {
+    pub tokens: Vec<String>,
+    pub token_logprobs: Vec<f32>,
+    pub top_logprobs: Vec<HashMap<String, f32>>,
+    /// Character offset of each token in the choice text
+    pub text_offset: Vec<usize>,
+}
+
+impl CompletionLogprobs {
+    /// `offset` is where the first token starts in the choice text (after an echoed prompt).
+    pub fn from_tokens(tokens: &[TokenLogprob], mut offset: usize) -> Self {
+        let mut logprobs = Self {
+            tokens: Vec::with_capacity(tokens.len()),
+            token_logprobs: Vec::with_capacity(tokens.len()),
+            top_logprobs: Vec::with_capacity(tokens.len()),
+            text_offset: Vec::with_capacity(tokens.len()),
+        };
+        for t in tokens {
+            logprobs.tokens.push(t.token.clone());
+            logprobs.token_logprobs.push(t.logprob);
+            logprobs.top_logprobs.push(t.top_logprobs.iter().map(|top| (top.token.clone(), top.logprob)).collect());
+            logprobs.text_offset.push(offset);
+            offset += t.token.chars().count();
+        }
+        logprobs
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompletionChunk {
+    pub id: String,
+    pub object: String,
+    pub created: i64,
+    pub model: String,
+    pub system_fingerprint: String,
+    pub choices: Vec<CompletionChoice>,
+    /// Sent with the chunk that finishes a choice
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GenerateRequest {
     pub model: String,
+    #[serde(default)]
     pub prompt: String,
     #[serde(default)]
     pub stream: bool,
     #[serde(default)]
     pub options: Option<GenerateOptions>,
+    /// Token context returned by a previous response, to continue from it
+    #[serde(default)]
+    pub context: Option<Vec<i32>>,
+    #[serde(default)]
+    pub keep_alive: Option<KeepAlive>,
+    /// "json" or a JSON Schema the response must follow
+    #[serde(default)]
+    pub format: Option<serde_json::Value>,
+    /// GBNF grammar constraining the response
+    #[serde(default)]
+    pub grammar: Option<String>,
+    /// Base64-encoded images, placed before the prompt
+    #[serde(default)]
+    pub images: Option<Vec<String>>,
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+/// Ollama `keep_alive`: a number of seconds or a duration string such as "5m".
+/// An empty prompt with `keep_alive: 0` unloads the model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeepAlive {
+    Seconds(f64),
+    Duration(String),
+}
+
+impl KeepAlive {
+    pub fn is_zero(&self) -> bool {
+        match self {
+            KeepAlive::Seconds(secs) => *secs == 0.0,
+            KeepAlive::Duration(d) => d
+                .trim()
+                .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+                .parse::<f64>()
+                .map(|v| v == 0.0)
+                .unwrap_or(false),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -272,6 +725,65 @@ pub struct GenerateOptions {
     pub repeat_penalty: Option<f32>,
     #[serde(default)]
     pub num_predict: Option<usize>,
+    #[serde(default)]
+    pub num_ctx: Option<usize>,
+    /// Negative (Ollama's -1) samples without a seed
+    #[serde(default, deserialize_with = "deserialize_seed")]
+    pub seed: Option<u32>,
+    #[serde(default)]
+    pub stop: Option<Vec<String>>,
+    #[serde(default)]
+    pub min_p: Option<f32>,
+    #[serde(default)]
+    pub typical_p: Option<f32>,
+    #[serde(default)]
+    pub repeat_last_n: Option<i32>,
+    #[serde(default)]
+    pub presence_penalty: Option<f32>,
+    #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default)]
+    pub mirostat: Option<u8>,
+    #[serde(default)]
+    pub mirostat_tau: Option<f32>,
+    #[serde(default)]
+    pub mirostat_eta: Option<f32>,
+    #[serde(default)]
+    pub tfs_z: Option<f32>,
+    #[serde(default)]
+    pub dry_multiplier: Option<f32>,
+    #[serde(default)]
+    pub dry_base: Option<f32>,
+    #[serde(default)]
+    pub dry_allowed_length: Option<usize>,
+    #[serde(default)]
+    pub dry_penalty_last_n: Option<i32>,
+    #[serde(default)]
+    pub xtc_probability: Option<f32>,
+    #[serde(default)]
+    pub xtc_threshold: Option<f32>,
+    #[serde(default)]
+    pub num_batch: Option<usize>,
+    #[serde(default)]
+    pub num_thread: Option<usize>,
+    /// Number of layers to offload to the GPU; reloads the model when it changes
+    #[serde(default)]
+    pub num_gpu: Option<u32>,
+    /// Prompt tokens kept when the prompt has to be truncated
+    #[serde(default)]
+    pub num_keep: Option<usize>,
+    /// Deprecated in Ollama; accepted and ignored
+    #[serde(default)]
+    pub penalize_newline: Option<bool>,
+    /// Extension: same as OpenAI `logit_bias`
+    #[serde(default)]
+    pub logit_bias: Option<LogitBias>,
+    /// Extension: prompt lookup decoding n-gram size (0 = off)
+    #[serde(default)]
+    pub prompt_lookup: Option<usize>,
+    /// Extension: scale for the model's LoRA adapters (0 = base model)
+    #[serde(default)]
+    pub adapter_scale: Option<f32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -281,6 +793,8 @@ pub struct GenerateResponse {
     pub response: String,
     pub done: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub done_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub context: Option<Vec<i32>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub total_duration: Option<u64>,
@@ -290,6 +804,8 @@ pub struct GenerateResponse {
     pub prompt_eval_count: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub eval_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -308,6 +824,8 @@ pub struct ModelListItem {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ModelDetails {
+    /// Base model of a fine-tune, empty otherwise
+    pub parent_model: String,
     pub format: String,
     pub family: String,
     pub parameter_size: String,
@@ -374,17 +892,85 @@ pub struct OpenAIModel {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OllamaChatRequest {
     pub model: String,
+    #[serde(default)]
     pub messages: Vec<OllamaChatMessage>,
     #[serde(default)]
     pub stream: bool,
     #[serde(default)]
     pub options: Option<GenerateOptions>,
+    #[serde(default)]
+    pub keep_alive: Option<KeepAlive>,
+    #[serde(default)]
+    pub tools: Option<Vec<Tool>>,
+    /// "json" or a JSON Schema the reply must follow
+    #[serde(default)]
+    pub format: Option<serde_json::Value>,
+    /// GBNF grammar constraining the reply
+    #[serde(default)]
+    pub grammar: Option<String>,
+    /// Set to false to skip the thinking of reasoning models
+    #[serde(default)]
+    pub think: Option<bool>,
+    #[serde(default)]
+    pub session_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OllamaChatMessage {
     pub role: String,
+    #[serde(default)]
     pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+    /// Base64-encoded images shown to the model with this message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<OllamaToolCall>>,
+}
+
+impl OllamaChatMessage {
+    pub fn assistant(content: String, tool_calls: &[ToolCall]) -> Self {
+        Self {
+            role: "assistant".to_string(),
+            content,
+            thinking: None,
+            images: None,
+            tool_calls: (!tool_calls.is_empty()).then(|| tool_calls.iter()
+                .map(|call| OllamaToolCall {
+                    function: OllamaFunctionCall {
+                        name: call.name.clone(),
+                        arguments: call.arguments.clone(),
+                    },
+                })
+                .collect()),
+        }
+    }
+
+    /// Images go before the text, as Ollama does. Fails on invalid base64.
+    pub fn to_turn(&self) -> anyhow::Result<ChatTurn> {
+        let images = self.images.iter().flatten()
+            .map(|image| vision::decode_base64_image(image))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut turn = ChatTurn::new(&self.role, &format!("{}{}", IMAGE_MARKER.repeat(images.len()), self.content));
+        turn.images = images;
+        turn.tool_calls = self.tool_calls.iter().flatten()
+            .map(|call| ToolCall::new(&call.function.name, call.function.arguments.clone()))
+            .collect();
+        Ok(turn)
+    }
+}
+
+/// Ollama tool call; unlike OpenAI, `arguments` is a JSON object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaToolCall {
+    pub function: OllamaFunctionCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaFunctionCall {
+    pub name: String,
+    pub arguments: serde_json::Value,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -394,6 +980,8 @@ pub struct OllamaChatResponse {
     pub message: OllamaChatMessage,
     pub done: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub done_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub total_duration: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub load_duration: Option<u64>,
@@ -401,6 +989,232 @@ pub struct OllamaChatResponse {
     pub prompt_eval_count: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub eval_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+}
+
+// /api/load and /api/unload request/response
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelLoadRequest {
+    pub model: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelLoadResponse {
+    pub model: String,
+    pub status: String,
+}
+
+// /api/cache/save and /api/cache/load request/response
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PromptCacheRequest {
+    pub model: String,
+    pub prompt: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PromptCacheResponse {
+    pub model: String,
+    pub path: String,
+    pub tokens: usize,
+}
+
+/// A single string or a batch of strings to embed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Single(String),
+    Batch(Vec<String>),
+}
+
+impl EmbeddingInput {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            EmbeddingInput::Single(input) => vec![input],
+            EmbeddingInput::Batch(inputs) => inputs,
+        }
+    }
+}
+
+// OpenAI /v1/embeddings request/response
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingsRequest {
+    pub model: String,
+    pub input: EmbeddingInput,
+    /// Extension: mean, cls or last
+    #[serde(default)]
+    pub pooling: Option<EmbeddingPooling>,
+    /// Extension: set to false to get raw (unnormalized) vectors
+    #[serde(default)]
+    pub normalize: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmbeddingsResponse {
+    pub object: String,
+    pub data: Vec<EmbeddingData>,
+    pub model: String,
+    pub usage: EmbeddingUsage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmbeddingData {
+    pub object: String,
+    pub embedding: Vec<f32>,
+    pub index: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmbeddingUsage {
+    pub prompt_tokens: usize,
+    pub total_tokens: usize,
+}
+
+// Ollama /api/embed request/response
+#[derive(Debug, Deserialize)]
+pub struct OllamaEmbedRequest {
+    pub model: String,
+    pub input: EmbeddingInput,
+    /// Cut inputs that exceed the context instead of failing (default true)
+    #[serde(default)]
+    pub truncate: Option<bool>,
+    #[serde(default)]
+    pub pooling: Option<EmbeddingPooling>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaEmbedResponse {
+    pub model: String,
+    pub embeddings: Vec<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_duration: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_duration: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_eval_count: Option<usize>,
+}
+
+// Ollama legacy /api/embeddings request/response
+#[derive(Debug, Deserialize)]
+pub struct OllamaEmbeddingsRequest {
+    pub model: String,
+    pub prompt: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaEmbeddingsResponse {
+    pub embedding: Vec<f32>,
+}
+
+// Jina/Cohere-compatible /v1/rerank request/response
+#[derive(Debug, Deserialize)]
+pub struct RerankRequest {
+    pub model: String,
+    pub query: String,
+    pub documents: Vec<RerankDocument>,
+    /// Return only the best `top_n` results
+    #[serde(default)]
+    pub top_n: Option<usize>,
+    /// Echo each document's text in its result (default true)
+    #[serde(default = "default_return_documents")]
+    pub return_documents: bool,
+}
+
+fn default_return_documents() -> bool {
+    true
+}
+
+/// A document given as plain text or as `{"text": ...}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RerankDocument {
+    Text(String),
+    Object { text: String },
+}
+
+impl RerankDocument {
+    pub fn into_text(self) -> String {
+        match self {
+            RerankDocument::Text(text) | RerankDocument::Object { text } => text,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RerankResponse {
+    pub model: String,
+    pub results: Vec<RerankResult>,
+    pub usage: RerankUsage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RerankResult {
+    /// Position of the document in the request
+    pub index: usize,
+    pub relevance_score: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document: Option<RerankResultDocument>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RerankResultDocument {
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RerankUsage {
+    pub total_tokens: usize,
+}
+
+// /api/tokenize and /api/detokenize request/response
+#[derive(Debug, Deserialize)]
+pub struct TokenizeRequest {
+    pub model: String,
+    #[serde(alias = "prompt", alias = "text")]
+    pub content: String,
+    /// Add the BOS token like a prompt would (default true)
+    #[serde(default = "default_add_special")]
+    pub add_special: bool,
+    /// Return `{id, piece}` objects instead of bare ids
+    #[serde(default)]
+    pub with_pieces: bool,
+}
+
+fn default_add_special() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenizeResponse {
+    pub model: String,
+    pub tokens: Vec<TokenizedToken>,
+}
+
+/// A token id, or an id with its text when pieces were requested.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TokenizedToken {
+    Id(i32),
+    Piece { id: i32, piece: String },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DetokenizeRequest {
+    pub model: String,
+    pub tokens: Vec<i32>,
+    /// Render special tokens such as `<|im_end|>` as text (default true)
+    #[serde(default = "default_render_special")]
+    pub render_special: bool,
+}
+
+fn default_render_special() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DetokenizeResponse {
+    pub model: String,
+    pub content: String,
 }
 
 // Ollama /api/version response
@@ -204,16 +204,21 @@ use anyhow::Result;
 use std::sync::Arc;
 use std::io::{Write, stdout};
 use chrono::Utc;
-use dialoguer::{Input, theme::ColorfulTheme};
 
 use crate::config::Config;
 use crate::models::manager::ModelManager;
 use crate::models::registry::ModelRegistry;
-use crate::models::metadata::ModelMetadata;
+use crate::models::metadata::{LoraAdapter, ModelMetadata};
+use crate::models::modelfile::Modelfile;
 use crate::download::Downloader;
-use crate::inference::{GenerationConfig, GenerationRequest};
+use crate::inference::{EmbeddingPooling, EmbeddingRequest, GenerationConfig, GenerationRequest, RerankRequest, StreamEvent};
 
-pub async fn pull_model(model_name: &str) -> Result<()> {
+pub async fn pull_model(
+    model_name: &str,
+    mmproj: Option<&str>,
+    draft_model: Option<String>,
+    prompt_lookup: Option<usize>,
+) -> Result<()> {
     println!("Pulling model: {}", model_name);
     
     let config = Arc::new(Config::load()?);
@@ -229,7 +234,7 @@ pub async fn pull_model(model_name: &str) -> Result<()> {
     let url = registry.resolve_model_url(model_name).await?;
     
     // Generate a clean filename from model name
-    let safe_name = name.replace('/', "_").replace('\\', "_");
+    let safe_name = name.replace(['/', '\\'], "_");
     let model_path = config.get_model_path(&format!("{}_{}.gguf", safe_name, tag));
     
     println!("Downloading from: {}", url);
@@ -237,6 +242,25 @@ pub async fn pull_model(model_name: &str) -> Result<()> {
     
     let file_size = tokio::fs::metadata(&model_path).await?.len();
     
+    // Multimodal models need their projector; a missing one only costs image support
+    let projector_url = match mmproj {
+        Some(source) => Some(source.to_string()),
+        None => registry.resolve_projector_url(&url).await.unwrap_or_else(|e| {
+            eprintln!("Warning: could not look for a vision projector: {}", e);
+            None
+        }),
+    };
+    let projector = match projector_url {
+        Some(source) if std::path::Path::new(&source).exists() => Some(source),
+        Some(source) => {
+            let projector_path = config.get_model_path(&format!("{}_{}.mmproj.gguf", safe_name, tag));
+            println!("Downloading vision projector from: {}", source);
+            downloader.download_file(&source, &projector_path).await?;
+            Some(projector_path.to_string_lossy().to_string())
+        }
+        None => None,
+    };
+    
     let metadata = ModelMetadata {
         name: safe_name.clone(),
         tag: tag.to_string(),
@@ -249,6 +273,11 @@ pub async fn pull_model(model_name: &str) -> Result<()> {
         created_at: Utc::now(),
         modified_at: Utc::now(),
         path: model_path.to_string_lossy().to_string(),
+        projector,
+        draft_model,
+        prompt_lookup,
+        parent_model: None,
+        adapters: Vec::new(),
     };
     
     model_manager.save_metadata(&metadata)?;
@@ -258,6 +287,84 @@ pub async fn pull_model(model_name: &str) -> Result<()> {
     Ok(())
 }
 
+pub async fn create_model(model_name: &str, modelfile_path: &std::path::Path) -> Result<()> {
+    let modelfile = Modelfile::load(modelfile_path)?;
+    let scale = modelfile.adapter_scale.unwrap_or(1.0);
+    let adapters: Vec<(std::path::PathBuf, f32)> = modelfile.adapters.iter()
+        .map(|path| (path.into(), scale))
+        .collect();
+    
+    register_fine_tune(model_name, &modelfile.from, &adapters, modelfile.prompt_lookup).await
+}
+
+pub async fn import_adapter(model_name: &str, adapter: &std::path::Path, base: &str, scale: f32) -> Result<()> {
+    register_fine_tune(model_name, base, &[(adapter.to_path_buf(), scale)], None).await
+}
+
+/// Register `model_name` as the pulled model `base` plus LoRA `adapters`, which are
+/// copied into the models directory.
+async fn register_fine_tune(
+    model_name: &str,
+    base: &str,
+    adapters: &[(std::path::PathBuf, f32)],
+    prompt_lookup: Option<usize>,
+) -> Result<()> {
+    let config = Arc::new(Config::load()?);
+    let model_manager = ModelManager::new(config.clone())?;
+    
+    let base_parts: Vec<&str> = base.split(':').collect();
+    let base_name = base_parts[0].replace(['/', '\\'], "_");
+    let base_tag = base_parts.get(1).unwrap_or(&"latest");
+    let base_metadata = model_manager.get_metadata(&base_name, base_tag)?
+        .ok_or_else(|| anyhow::anyhow!("Base model not found: {} (pull it first)", base))?;
+    if base_metadata.parent_model.is_some() && !base_metadata.adapters.is_empty() {
+        anyhow::bail!("{} is itself a fine-tune; use its base model instead", base);
+    }
+    
+    let model_parts: Vec<&str> = model_name.split(':').collect();
+    let name = model_parts[0];
+    let tag = model_parts.get(1).unwrap_or(&"latest");
+    let safe_name = name.replace(['/', '\\'], "_");
+    
+    let mut lora_adapters = Vec::with_capacity(adapters.len());
+    let mut size = 0;
+    for (i, (source, scale)) in adapters.iter().enumerate() {
+        let adapter_path = config.get_model_path(&format!("{}_{}.lora{}.gguf", safe_name, tag, i));
+        println!("Copying adapter: {}", source.display());
+        size += tokio::fs::copy(source, &adapter_path).await
+            .map_err(|e| anyhow::anyhow!("Failed to copy adapter {}: {}", source.display(), e))?;
+        lora_adapters.push(LoraAdapter {
+            path: adapter_path.to_string_lossy().to_string(),
+            scale: *scale,
+        });
+    }
+    
+    let metadata = ModelMetadata {
+        name: safe_name,
+        tag: tag.to_string(),
+        size,
+        digest: format!("sha256:{}", uuid::Uuid::new_v4()),
+        format: "gguf".to_string(),
+        family: base_metadata.family,
+        parameter_size: base_metadata.parameter_size,
+        quantization_level: base_metadata.quantization_level,
+        created_at: Utc::now(),
+        modified_at: Utc::now(),
+        path: base_metadata.path,
+        projector: base_metadata.projector,
+        draft_model: base_metadata.draft_model,
+        prompt_lookup: prompt_lookup.or(base_metadata.prompt_lookup),
+        parent_model: Some(format!("{}:{}", base_metadata.name, base_metadata.tag)),
+        adapters: lora_adapters,
+    };
+    
+    model_manager.save_metadata(&metadata)?;
+    
+    println!("✓ Created {} from {} with {} adapter(s)", model_name, base, adapters.len());
+    
+    Ok(())
+}
+
 pub async fn list_models() -> Result<()> {
     let config = Arc::new(Config::load()?);
     let model_manager = ModelManager::new(config)?;
@@ -291,7 +398,7 @@ pub async fn list_models() -> Result<()> {
     Ok(())
 }
 
-pub async fn run_model(model_name: &str, prompt: Option<String>) -> Result<()> {
+pub async fn run_model(model_name: &str, prompt: Option<String>, session_id: Option<String>) -> Result<()> {
     let config = Arc::new(Config::load()?);
     let model_manager = ModelManager::new(config.clone())?;
     let stream_mode = config.stream_mode;
@@ -301,7 +408,7 @@ pub async fn run_model(model_name: &str, prompt: Option<String>) -> Result<()> {
     let tag = model_parts.get(1).unwrap_or(&"latest");
     
     // Use safe name for lookup (consistent with pull)
-    let safe_name = name.replace('/', "_").replace('\\', "_");
+    let safe_name = name.replace(['/', '\\'], "_");
     
     println!("Loading model: {}...", model_name);
     println!("Stream mode: {}", if stream_mode { "enabled" } else { "disabled" });
@@ -312,21 +419,23 @@ pub async fn run_model(model_name: &str, prompt: Option<String>) -> Result<()> {
         
         if stream_mode {
             // Stream mode - print tokens as they arrive
-            print!("\n");
+            println!();
             let mut rx = engine.generate_stream(GenerationRequest {
                 prompt: p,
                 config: GenerationConfig::default(),
                 context: None,
+                images: Vec::new(),
             }).await?;
             
             let mut token_count = 0;
             while let Some(result) = rx.recv().await {
                 match result {
-                    Ok(token) => {
+                    Ok(StreamEvent::Token(token)) => {
                         print!("{}", token);
                         stdout().flush()?;
                         token_count += 1;
                     }
+                    Ok(StreamEvent::Logprobs(_)) | Ok(StreamEvent::Done(_)) => {}
                     Err(e) => eprintln!("\nError: {}", e),
                 }
             }
@@ -342,6 +451,7 @@ pub async fn run_model(model_name: &str, prompt: Option<String>) -> Result<()> {
                 prompt: p,
                 config: GenerationConfig::default(),
                 context: None,
+                images: Vec::new(),
             }).await?;
             let elapsed = start.elapsed();
             
@@ -353,62 +463,14 @@ pub async fn run_model(model_name: &str, prompt: Option<String>) -> Result<()> {
             );
         }
     } else {
-        println!("\nInteractive mode. Type 'exit' to quit.\n");
-        
-        loop {
-            let input: String = Input::with_theme(&ColorfulTheme::default())
-                .with_prompt(">>>")
-                .interact_text()?;
-            
-            if input.trim().eq_ignore_ascii_case("exit") {
-                break;
-            }
-            
-            let start = std::time::Instant::now();
-            
-            if stream_mode {
-                // Stream mode
-                print!("\n");
-                let mut rx = engine.generate_stream(GenerationRequest {
-                    prompt: input,
-                    config: GenerationConfig::default(),
-                    context: None,
-                }).await?;
-                
-                let mut token_count = 0;
-                while let Some(result) = rx.recv().await {
-                    match result {
-                        Ok(token) => {
-                            print!("{}", token);
-                            stdout().flush()?;
-                            token_count += 1;
-                        }
-                        Err(e) => eprintln!("\nError: {}", e),
-                    }
-                }
-                let elapsed = start.elapsed();
-                println!("\n\n[⏱ {:.2}s | {} tokens | {:.1} t/s]\n", 
-                    elapsed.as_secs_f64(),
-                    token_count,
-                    token_count as f64 / elapsed.as_secs_f64()
-                );
-            } else {
-                // Non-stream mode
-                let response = engine.generate(GenerationRequest {
-                    prompt: input,
-                    config: GenerationConfig::default(),
-                    context: None,
-                }).await?;
-                let elapsed = start.elapsed();
-                
-                println!("\n{}", response.text);
-                println!("\n[⏱ {:.2}s | {} tokens | {:.1} t/s]\n", 
-                    elapsed.as_secs_f64(),
-                    response.tokens_generated,
-                    response.tokens_generated as f64 / elapsed.as_secs_f64()
-                );
-            }
-        }
+        crate::cli::repl::run(
+            engine,
+            model_name,
+            &config,
+            stream_mode,
+            model_manager.sessions(),
+            session_id,
+        ).await?;
     }
     
     Ok(())
@@ -421,13 +483,25 @@ pub async fn remove_model(model_name: &str) -> Result<()> {
     let model_parts: Vec<&str> = model_name.split(':').collect();
     let name = model_parts[0];
     let tag = model_parts.get(1).unwrap_or(&"latest");
-    let safe_name = name.replace('/', "_").replace('\\', "_");
+    let safe_name = name.replace(['/', '\\'], "_");
     
     if let Some(metadata) = model_manager.get_metadata(&safe_name, tag)? {
+        // A fine-tune only owns its adapters; the weights belong to the base model
         let model_path = std::path::Path::new(&metadata.path);
-        if model_path.exists() {
+        if metadata.parent_model.is_none() && model_path.exists() {
             tokio::fs::remove_file(model_path).await?;
         }
+        for adapter in &metadata.adapters {
+            if std::path::Path::new(&adapter.path).exists() {
+                tokio::fs::remove_file(&adapter.path).await?;
+            }
+        }
+        if let Some(projector) = &metadata.projector {
+            // Only delete projectors we downloaded, not user-supplied paths
+            if projector.ends_with(".mmproj.gguf") && std::path::Path::new(projector).exists() {
+                tokio::fs::remove_file(projector).await?;
+            }
+        }
         
         model_manager.delete_metadata(&safe_name, tag)?;
         println!("✓ Removed model: {}", model_name);
@@ -445,7 +519,7 @@ pub async fn show_model(model_name: &str) -> Result<()> {
     let model_parts: Vec<&str> = model_name.split(':').collect();
     let name = model_parts[0];
     let tag = model_parts.get(1).unwrap_or(&"latest");
-    let safe_name = name.replace('/', "_").replace('\\', "_");
+    let safe_name = name.replace(['/', '\\'], "_");
     
     if let Some(metadata) = model_manager.get_metadata(&safe_name, tag)? {
         println!("\nModel: {}:{}", metadata.name, metadata.tag);
@@ -456,6 +530,21 @@ pub async fn show_model(model_name: &str) -> Result<()> {
         println!("Quantization:        {}", metadata.quantization_level);
         println!("Size:                {:.2} MB", metadata.size as f64 / 1024.0 / 1024.0);
         println!("Path:                {}", metadata.path);
+        if let Some(projector) = &metadata.projector {
+            println!("Projector:           {}", projector);
+        }
+        if let Some(draft) = &metadata.draft_model {
+            println!("Draft Model:         {}", draft);
+        }
+        if let Some(parent) = &metadata.parent_model {
+            println!("Parent Model:        {}", parent);
+        }
+        for adapter in &metadata.adapters {
+            println!("Adapter:             {} (scale {})", adapter.path, adapter.scale);
+        }
+        if let Some(ngram) = metadata.prompt_lookup {
+            println!("Prompt Lookup:       {}-gram", ngram);
+        }
         println!("Created:             {}", metadata.created_at.format("%Y-%m-%d %H:%M:%S"));
         println!("Modified:            {}", metadata.modified_at.format("%Y-%m-%d %H:%M:%S"));
         println!("{:-<60}", "");
@@ -485,4 +574,120 @@ pub async fn list_running() -> Result<()> {
     
     Ok(())
 }
+
+pub async fn stop_model(model_name: &str) -> Result<()> {
+    let config = Config::load()?;
+    
+    // Loaded models live in the server process, so ask it to unload
+    let url = format!("http://{}:{}/api/unload", config.server_host, config.server_port);
+    let response = reqwest::Client::new()
+        .post(&url)
+        .json(&serde_json::json!({ "model": model_name }))
+        .send()
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not reach server at {}: {}", url, e))?;
+    
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        anyhow::bail!("Failed to stop {}: HTTP {}: {}", model_name, status, body);
+    }
+    
+    println!("✓ Stopped model: {}", model_name);
+    Ok(())
+}
+
+/// Embed every line read from stdin and print one JSON array per line.
+pub async fn embed_lines(model_name: &str, pooling: Option<EmbeddingPooling>, normalize: bool) -> Result<()> {
+    let config = Arc::new(Config::load()?);
+    let model_manager = ModelManager::new(config.clone())?;
+    
+    let model_parts: Vec<&str> = model_name.split(':').collect();
+    let name = model_parts[0];
+    let tag = model_parts.get(1).unwrap_or(&"latest");
+    let safe_name = name.replace(['/', '\\'], "_");
+    
+    // Status goes to stderr so stdout stays machine-readable
+    eprintln!("Loading model: {}...", model_name);
+    let engine = model_manager.load_model(&safe_name, tag).await?;
+    
+    let inputs = std::io::stdin().lines().collect::<std::io::Result<Vec<String>>>()?;
+    let response = engine.embed(EmbeddingRequest {
+        inputs,
+        pooling,
+        normalize,
+        truncate: true,
+    }).await?;
+    
+    let mut out = stdout().lock();
+    for embedding in &response.embeddings {
+        writeln!(out, "{}", serde_json::to_string(embedding)?)?;
+    }
+    eprintln!("Embedded {} lines ({} tokens)", response.embeddings.len(), response.prompt_tokens);
+    
+    Ok(())
+}
+
+/// Score every line read from stdin against `query` and print them best first.
+pub async fn rerank_lines(model_name: &str, query: &str, top_n: Option<usize>) -> Result<()> {
+    let config = Arc::new(Config::load()?);
+    let model_manager = ModelManager::new(config.clone())?;
+    
+    let model_parts: Vec<&str> = model_name.split(':').collect();
+    let name = model_parts[0];
+    let tag = model_parts.get(1).unwrap_or(&"latest");
+    let safe_name = name.replace(['/', '\\'], "_");
+    
+    eprintln!("Loading model: {}...", model_name);
+    let engine = model_manager.load_model(&safe_name, tag).await?;
+    
+    let documents = std::io::stdin().lines().collect::<std::io::Result<Vec<String>>>()?;
+    let response = engine.rerank(RerankRequest {
+        query: query.to_string(),
+        documents: documents.clone(),
+    }).await?;
+    
+    let mut ranked: Vec<(f32, &String)> = response.scores.into_iter().zip(&documents).collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    
+    let mut out = stdout().lock();
+    for (score, document) in ranked.into_iter().take(top_n.unwrap_or(usize::MAX)) {
+        writeln!(out, "{:.4}\t{}", score, document)?;
+    }
+    eprintln!("Ranked {} lines ({} tokens)", documents.len(), response.prompt_tokens);
+    
+    Ok(())
+}
+
+pub async fn tokenize(model_name: &str, text: Option<String>, pieces: bool, add_special: bool) -> Result<()> {
+    let config = Arc::new(Config::load()?);
+    let model_manager = ModelManager::new(config.clone())?;
+    
+    let model_parts: Vec<&str> = model_name.split(':').collect();
+    let name = model_parts[0];
+    let tag = model_parts.get(1).unwrap_or(&"latest");
+    let safe_name = name.replace(['/', '\\'], "_");
+    
+    let text = match text {
+        Some(text) => text,
+        None => std::io::read_to_string(std::io::stdin())?,
+    };
+    
+    eprintln!("Loading model: {}...", model_name);
+    let engine = model_manager.load_model(&safe_name, tag).await?;
+    let tokens = engine.tokenize(&text, add_special)?;
+    
+    let mut out = stdout().lock();
+    if pieces {
+        for &token in &tokens {
+            writeln!(out, "{}\t{:?}", token, engine.token_piece(token, true)?)?;
+        }
+    } else {
+        let ids: Vec<String> = tokens.iter().map(|t| t.to_string()).collect();
+        writeln!(out, "{}", ids.join(" "))?;
+    }
+    eprintln!("{} tokens", tokens.len());
+    
+    Ok(())
+}
 >>>>>>> bb9577f (20260204_220651)
@@ -0,0 +1,398 @@
+use anyhow::Result;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::io::{Write, stdout};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::context::{ContextManager, Session};
+use crate::inference::backend::InferenceBackend;
+use crate::inference::{request_seed, ChatTurn, GenerationConfig, GenerationRequest, StreamEvent};
+
+const HELP: &str = "\
+Available commands:
+  /system <text>          Set the system prompt
+  /set <parameter> <val>  Set a generation parameter (temperature, top_p, top_k,
+                          min_p, typical_p, tfs_z, repeat_penalty, repeat_last_n,
+                          presence_penalty, frequency_penalty, dry_multiplier,
+                          dry_base, dry_allowed_length, dry_penalty_last_n,
+                          xtc_probability, xtc_threshold, mirostat,
+                          mirostat_tau, mirostat_eta, seed, stop, num_ctx,
+                          num_predict, prompt_lookup, adapter_scale,
+                          logit_bias <token> <bias>)
+  /clear                  Clear the conversation history
+  /save <name>            Save the conversation as a session
+  /load <name>            Resume a saved session
+  /show                   Show the current session settings
+  /bye                    Exit
+Use \"\"\" to begin and end a multi-line message.";
+
+/// Conversation state of an interactive `run` session.
+#[derive(Debug, Clone)]
+pub struct ChatSession {
+    /// Id under which the conversation is persisted, if any
+    pub id: Option<String>,
+    pub model: String,
+    pub system: Option<String>,
+    pub messages: Vec<ChatTurn>,
+    /// Token context of the last reply
+    pub context: Vec<i32>,
+    pub config: GenerationConfig,
+}
+
+impl ChatSession {
+    pub fn new(model: &str) -> Self {
+        Self {
+            id: None,
+            model: model.to_string(),
+            system: None,
+            messages: Vec::new(),
+            context: Vec::new(),
+            config: GenerationConfig::default(),
+        }
+    }
+
+    /// Full message list sent to the chat template, system prompt first.
+    pub fn turns(&self) -> Vec<ChatTurn> {
+        let mut turns = Vec::with_capacity(self.messages.len() + 1);
+        if let Some(system) = &self.system {
+            turns.push(ChatTurn::new("system", system));
+        }
+        turns.extend(self.messages.iter().cloned());
+        turns
+    }
+
+    /// Restore the conversation stored under `id`, keeping the current parameters.
+    fn restore(&mut self, id: &str, stored: Session) {
+        // Token contexts only make sense to the model that produced them
+        self.context = if stored.model == self.model {
+            stored.context
+        } else {
+            if !stored.model.is_empty() {
+                println!("Note: session was saved with model {}", stored.model);
+            }
+            Vec::new()
+        };
+        let mut messages = stored.messages;
+        self.system = match messages.first() {
+            Some(first) if first.role == "system" => Some(messages.remove(0).content),
+            _ => None,
+        };
+        self.messages = messages;
+        self.id = Some(id.to_string());
+    }
+
+    fn persist(&self, engine: &dyn InferenceBackend, sessions: &ContextManager) -> Result<()> {
+        let Some(id) = &self.id else {
+            return Ok(());
+        };
+        sessions.save_session(id, Session {
+            model: self.model.clone(),
+            messages: self.turns(),
+            context: self.context.clone(),
+            updated_at: None,
+        }, |turns| engine.chat_tokens(turns))
+    }
+
+    fn set_parameter(&mut self, name: &str, value: &str) -> Result<()> {
+        match name {
+            "temperature" => self.config.temperature = value.parse()?,
+            "top_p" => self.config.top_p = value.parse()?,
+            "top_k" => self.config.top_k = value.parse()?,
+            "min_p" => self.config.min_p = value.parse()?,
+            "typical_p" => self.config.typical_p = value.parse()?,
+            "tfs_z" => {
+                self.config.tfs_z = value.parse()?;
+                if self.config.tfs_z != 1.0 {
+                    println!("Note: tfs_z is ignored, llama.cpp no longer implements tail-free sampling");
+                }
+            }
+            "repeat_penalty" => self.config.repeat_penalty = value.parse()?,
+            "repeat_last_n" => self.config.repeat_last_n = value.parse()?,
+            "presence_penalty" => self.config.presence_penalty = value.parse()?,
+            "frequency_penalty" => self.config.frequency_penalty = value.parse()?,
+            "dry_multiplier" => self.config.dry_multiplier = value.parse()?,
+            "dry_base" => self.config.dry_base = value.parse()?,
+            "dry_allowed_length" => self.config.dry_allowed_length = value.parse()?,
+            "dry_penalty_last_n" => self.config.dry_penalty_last_n = value.parse()?,
+            "xtc_probability" => self.config.xtc_probability = value.parse()?,
+            "xtc_threshold" => self.config.xtc_threshold = value.parse()?,
+            "mirostat" => self.config.mirostat = value.parse()?,
+            "mirostat_tau" => self.config.mirostat_tau = value.parse()?,
+            "mirostat_eta" => self.config.mirostat_eta = value.parse()?,
+            "seed" => self.config.seed = request_seed(value.parse()?),
+            "stop" => self.config.stop_sequences.push(value.to_string()),
+            "num_ctx" => self.config.num_ctx = Some(value.parse()?),
+            "num_predict" | "max_tokens" => self.config.max_tokens = value.parse()?,
+            "prompt_lookup" => self.config.prompt_lookup = Some(value.parse()?),
+            "adapter_scale" => self.config.adapter_scale = Some(value.parse()?),
+            "logit_bias" => {
+                let (token, bias) = value.rsplit_once(char::is_whitespace)
+                    .ok_or_else(|| anyhow::anyhow!("Usage: /set logit_bias <token id or text> <bias>"))?;
+                self.config.logit_bias.push((token.trim().into(), bias.parse()?));
+            }
+            _ => anyhow::bail!("Unknown parameter: {}", name),
+        }
+        Ok(())
+    }
+}
+
+enum Flow {
+    Continue,
+    Exit,
+}
+
+pub async fn run(
+    engine: Arc<dyn InferenceBackend>,
+    model_name: &str,
+    config: &Config,
+    stream_mode: bool,
+    sessions: Arc<ContextManager>,
+    session_id: Option<String>,
+) -> Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history_path = config.cache_dir.join("repl_history");
+    let _ = editor.load_history(&history_path);
+
+    let mut session = ChatSession::new(model_name);
+    if let Some(id) = session_id {
+        match sessions.get_session(&id)? {
+            Some(stored) => {
+                session.restore(&id, stored);
+                println!("Resumed session '{}' ({} messages)", id, session.messages.len());
+            }
+            None => session.id = Some(id),
+        }
+    }
+
+    println!("\nChat mode. Type /? for help, /bye to exit.\n");
+
+    while let Some(input) = read_input(&mut editor)? {
+        let trimmed = input.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        if trimmed.starts_with('/') {
+            match handle_command(trimmed, &mut session, &*engine, &sessions) {
+                Ok(Flow::Continue) => continue,
+                Ok(Flow::Exit) => break,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    continue;
+                }
+            }
+        }
+
+        session.messages.push(ChatTurn::new("user", &input));
+        let reply = match engine.apply_chat_template(&session.turns()) {
+            Ok(prompt) => generate_reply(&*engine, prompt, session.config.clone(), stream_mode).await,
+            Err(e) => Err(e),
+        };
+
+        match reply {
+            Ok((reply, context)) if !reply.is_empty() => {
+                session.messages.push(ChatTurn::new("assistant", &reply));
+                // An interrupted reply has no context; one from an earlier turn would be stale
+                session.context = context.unwrap_or_default();
+            }
+            // Interrupted before any output: drop the unanswered message
+            Ok(_) => {
+                session.messages.pop();
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                session.messages.pop();
+                continue;
+            }
+        }
+
+        if let Err(e) = session.persist(&*engine, &sessions) {
+            eprintln!("Failed to save session: {}", e);
+        }
+    }
+
+    let _ = editor.save_history(&history_path);
+
+    Ok(())
+}
+
+/// Read one message, joining `"""` blocks. Returns `None` on Ctrl-D.
+fn read_input(editor: &mut DefaultEditor) -> Result<Option<String>> {
+    let line = match editor.readline(">>> ") {
+        Ok(line) => line,
+        Err(ReadlineError::Interrupted) => {
+            println!("Use Ctrl-D or /bye to exit.");
+            return Ok(Some(String::new()));
+        }
+        Err(ReadlineError::Eof) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let Some(first) = line.trim_start().strip_prefix("\"\"\"") else {
+        let _ = editor.add_history_entry(line.as_str());
+        return Ok(Some(line));
+    };
+
+    if let Some(body) = first.strip_suffix("\"\"\"") {
+        let _ = editor.add_history_entry(line.as_str());
+        return Ok(Some(body.to_string()));
+    }
+
+    let mut lines = vec![first.to_string()];
+    loop {
+        match editor.readline("... ") {
+            Ok(next) => {
+                if let Some(last) = next.trim_end().strip_suffix("\"\"\"") {
+                    lines.push(last.to_string());
+                    break;
+                }
+                lines.push(next);
+            }
+            Err(ReadlineError::Interrupted) => return Ok(Some(String::new())),
+            Err(ReadlineError::Eof) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let message = lines.join("\n");
+    let _ = editor.add_history_entry(format!("\"\"\"{}\"\"\"", message));
+    Ok(Some(message))
+}
+
+fn handle_command(
+    input: &str,
+    session: &mut ChatSession,
+    engine: &dyn InferenceBackend,
+    sessions: &ContextManager,
+) -> Result<Flow> {
+    let (command, args) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    let args = args.trim();
+
+    match command {
+        "/bye" | "/exit" => return Ok(Flow::Exit),
+        "/?" | "/help" => println!("{}", HELP),
+        "/system" => {
+            if args.is_empty() {
+                session.system = None;
+                println!("Cleared system prompt.");
+            } else {
+                session.system = Some(args.to_string());
+                println!("Set system prompt.");
+            }
+        }
+        "/set" => {
+            let args = args.strip_prefix("parameter").map(str::trim).unwrap_or(args);
+            let (name, value) = args.split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow::anyhow!("Usage: /set <parameter> <value>"))?;
+            session.set_parameter(name, value.trim())?;
+            println!("Set parameter '{}' to '{}'", name, value.trim());
+        }
+        "/clear" => {
+            session.messages.clear();
+            session.context.clear();
+            println!("Cleared session context.");
+        }
+        "/save" => {
+            if args.is_empty() {
+                anyhow::bail!("Usage: /save <name>");
+            }
+            session.id = Some(args.to_string());
+            session.persist(engine, sessions)?;
+            println!("Saved session '{}'", args);
+        }
+        "/load" => {
+            let stored = sessions.get_session(args)?
+                .ok_or_else(|| anyhow::anyhow!("No saved session named '{}'", args))?;
+            session.restore(args, stored);
+            println!("Loaded session '{}' ({} messages)", args, session.messages.len());
+        }
+        "/show" => {
+            println!("Model:            {}", session.model);
+            println!("Session:          {}", session.id.as_deref().unwrap_or("(unsaved)"));
+            println!("System:           {}", session.system.as_deref().unwrap_or("(none)"));
+            println!("Messages:         {}", session.messages.len());
+            println!("temperature       {}", session.config.temperature);
+            println!("top_p             {}", session.config.top_p);
+            println!("top_k             {}", session.config.top_k);
+            println!("repeat_penalty    {}", session.config.repeat_penalty);
+            println!("num_predict       {}", session.config.max_tokens);
+        }
+        _ => println!("Unknown command '{}'. Type /? for help.", command),
+    }
+
+    Ok(Flow::Continue)
+}
+
+/// Generate the assistant reply, printing it as it arrives, along with its token
+/// context. Ctrl-C stops generation and keeps whatever was produced so far, without a
+/// context.
+async fn generate_reply(
+    engine: &dyn InferenceBackend,
+    prompt: String,
+    gen_config: GenerationConfig,
+    stream_mode: bool,
+) -> Result<(String, Option<Vec<i32>>)> {
+    let start = std::time::Instant::now();
+    let mut reply = String::new();
+    let mut context = None;
+    let mut token_count = 0;
+
+    let request = GenerationRequest {
+        prompt,
+        config: gen_config,
+        context: None,
+        images: Vec::new(),
+    };
+
+    if stream_mode {
+        println!();
+    }
+    // Without streaming the tokens are still received one by one, so that Ctrl-C can
+    // drop the receiver, which makes the engine stop decoding
+    let mut rx = engine.generate_stream(request).await?;
+
+    loop {
+        tokio::select! {
+            result = rx.recv() => match result {
+                Some(Ok(StreamEvent::Token(token))) => {
+                    if stream_mode {
+                        print!("{}", token);
+                        stdout().flush()?;
+                    }
+                    reply.push_str(&token);
+                    token_count += 1;
+                }
+                Some(Ok(StreamEvent::Done(response))) => {
+                    if !stream_mode {
+                        println!("\n{}", response.text);
+                        reply = response.text;
+                    }
+                    token_count = response.tokens_generated;
+                    context = Some(response.context);
+                }
+                Some(Ok(StreamEvent::Logprobs(_))) => {}
+                Some(Err(e)) if stream_mode => {
+                    eprintln!("\nError: {}", e);
+                    break;
+                }
+                Some(Err(e)) => return Err(e),
+                None => break,
+            },
+            _ = tokio::signal::ctrl_c() => {
+                println!("\n[interrupted]");
+                break;
+            }
+        }
+    }
+
+    let elapsed = start.elapsed();
+    println!("\n\n[⏱ {:.2}s | {} tokens | {:.1} t/s]\n",
+        elapsed.as_secs_f64(),
+        token_count,
+        token_count as f64 / elapsed.as_secs_f64()
+    );
+
+    Ok((reply, context))
+}
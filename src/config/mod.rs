@@ -47,9 +47,12 @@ impl Config {
 =======
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::env;
 
+use crate::inference::EmbeddingPooling;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub models_dir: PathBuf,
@@ -67,6 +70,14 @@ pub struct Config {
     pub top_k: usize,
     pub repeat_penalty: f32,
     pub stream_mode: bool,
+    /// Files holding system prompts whose KV state is cached to disk on model load
+    pub cached_system_prompts: Vec<PathBuf>,
+    /// Pooling used for embeddings when the request does not choose one
+    pub embedding_pooling: EmbeddingPooling,
+    /// Draft model (`name:tag`) used for speculative decoding, keyed by target model
+    pub draft_models: HashMap<String, String>,
+    /// Most tokens drafted per speculative decoding step
+    pub draft_max_tokens: usize,
 }
 
 impl Default for Config {
@@ -90,6 +101,10 @@ impl Default for Config {
             top_k: 40,
             repeat_penalty: 1.1,
             stream_mode: true,
+            cached_system_prompts: vec![],
+            embedding_pooling: EmbeddingPooling::Mean,
+            draft_models: HashMap::new(),
+            draft_max_tokens: 16,
         }
     }
 }
@@ -118,6 +133,10 @@ impl Config {
             top_k: Self::get_env("RUST_LLM_TOP_K", 40),
             repeat_penalty: Self::get_env("RUST_LLM_REPEAT_PENALTY", 1.1),
             stream_mode: Self::get_env_bool("RUST_LLM_STREAM", true),
+            cached_system_prompts: Self::get_path_list_env("RUST_LLM_CACHED_SYSTEM_PROMPTS"),
+            embedding_pooling: Self::get_env("RUST_LLM_EMBEDDING_POOLING", EmbeddingPooling::Mean),
+            draft_models: Self::get_map_env("RUST_LLM_DRAFT_MODELS"),
+            draft_max_tokens: Self::get_env("RUST_LLM_DRAFT_MAX", 16),
         };
         
         std::fs::create_dir_all(&config.models_dir)?;
@@ -150,6 +169,27 @@ impl Config {
             .unwrap_or(default)
     }
     
+    fn get_path_list_env(key: &str) -> Vec<PathBuf> {
+        env::var(key)
+            .map(|v| v.split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from)
+                .collect())
+            .unwrap_or_default()
+    }
+    
+    /// Parse `key=value,key=value` pairs
+    fn get_map_env(key: &str) -> HashMap<String, String> {
+        env::var(key)
+            .map(|v| v.split(',')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+                .filter(|(k, v)| !k.is_empty() && !v.is_empty())
+                .collect())
+            .unwrap_or_default()
+    }
+    
     pub fn get_model_path(&self, model_name: &str) -> PathBuf {
         self.models_dir.join(model_name)
     }
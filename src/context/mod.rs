@@ -1,44 +1,158 @@
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
+use crate::inference::ChatTurn;
+
+/// A persisted conversation: chat history plus the token state of the last generation.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Session {
+    pub model: String,
+    pub messages: Vec<ChatTurn>,
+    pub context: Vec<i32>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Session store backed by a sled tree, so conversations survive restarts and
+/// can be resumed by any client that knows the session id.
 pub struct ContextManager {
-    contexts: Arc<RwLock<HashMap<String, Vec<i32>>>>,
+    sessions: sled::Tree,
     max_context_size: usize,
 }
 
 impl ContextManager {
-    pub fn new(max_context_size: usize) -> Self {
-        Self {
-            contexts: Arc::new(RwLock::new(HashMap::new())),
+    pub fn new(db: &sled::Db, max_context_size: usize) -> Result<Self> {
+        Ok(Self {
+            sessions: db.open_tree("sessions")?,
             max_context_size,
+        })
+    }
+
+    pub fn get_session(&self, session_id: &str) -> Result<Option<Session>> {
+        match self.sessions.get(session_id.as_bytes())? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Persist `session`, first dropping its oldest exchanges until `count_tokens` of the
+    /// rendered history fits the context size. The system prompt and the newest exchange
+    /// are always kept. A token context that is still too long keeps its first token (BOS)
+    /// and the most recent ones.
+    pub fn save_session(
+        &self,
+        session_id: &str,
+        mut session: Session,
+        count_tokens: impl Fn(&[ChatTurn]) -> usize,
+    ) -> Result<()> {
+        // The history is rendered once; each dropped exchange is counted on its own,
+        // less what the template adds to any rendering (BOS, the assistant prompt)
+        let overhead = count_tokens(&[]);
+        let mut tokens = count_tokens(&session.messages);
+        while tokens > self.max_context_size {
+            let Some(oldest) = session.messages.iter().position(|m| m.role != "system") else {
+                break;
+            };
+            // An exchange is a turn plus the assistant and tool turns answering it
+            let end = session.messages[oldest + 1..].iter()
+                .position(|m| m.role == "user")
+                .map(|i| oldest + 1 + i);
+            let Some(end) = end else {
+                break;
+            };
+            let dropped = count_tokens(&session.messages[oldest..end]).saturating_sub(overhead);
+            tokens = tokens.saturating_sub(dropped);
+            session.messages.drain(oldest..end);
+        }
+
+        // Even the smallest context keeps BOS
+        let max_context = self.max_context_size.max(1);
+        if session.context.len() > max_context {
+            let cut = session.context.len() - max_context + 1;
+            session.context.drain(1..cut);
+        }
+        session.updated_at = Some(Utc::now());
+
+        self.sessions.insert(session_id.as_bytes(), serde_json::to_vec(&session)?)?;
+        self.sessions.flush()?;
+        Ok(())
+    }
+
+    pub fn list_sessions(&self) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        for item in self.sessions.iter() {
+            let (key, _) = item?;
+            ids.push(String::from_utf8_lossy(&key).to_string());
+        }
+        Ok(ids)
+    }
+
+    pub fn get_context(&self, session_id: &str) -> Result<Option<Vec<i32>>> {
+        Ok(self.get_session(session_id)?.map(|s| s.context))
+    }
+
+    pub fn clear_context(&self, session_id: &str) -> Result<()> {
+        self.sessions.remove(session_id.as_bytes())?;
+        self.sessions.flush()?;
+        Ok(())
+    }
+
+    pub fn clear_all(&self) -> Result<()> {
+        self.sessions.clear()?;
+        self.sessions.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One token per character plus one per turn
+    fn count(turns: &[ChatTurn]) -> usize {
+        turns.iter().map(|turn| turn.content.len() + 1).sum()
+    }
+
+    fn manager(max_context_size: usize) -> ContextManager {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        ContextManager::new(&db, max_context_size).unwrap()
+    }
+
+    fn session(turns: &[(&str, &str)], context: Vec<i32>) -> Session {
+        Session {
+            model: "test:latest".to_string(),
+            messages: turns.iter().map(|(role, content)| ChatTurn::new(role, content)).collect(),
+            context,
+            updated_at: None,
         }
     }
-    
-    pub async fn get_context(&self, session_id: &str) -> Option<Vec<i32>> {
-        let contexts = self.contexts.read().await;
-        contexts.get(session_id).cloned()
-    }
-    
-    pub async fn save_context(&self, session_id: &str, context: Vec<i32>) {
-        let mut contexts = self.contexts.write().await;
-        
-        let trimmed_context = if context.len() > self.max_context_size {
-            context[context.len() - self.max_context_size..].to_vec()
-        } else {
-            context
-        };
-        
-        contexts.insert(session_id.to_string(), trimmed_context);
-    }
-    
-    pub async fn clear_context(&self, session_id: &str) {
-        let mut contexts = self.contexts.write().await;
-        contexts.remove(session_id);
-    }
-    
-    pub async fn clear_all(&self) {
-        let mut contexts = self.contexts.write().await;
-        contexts.clear();
+
+    #[test]
+    fn test_drops_oldest_exchanges() {
+        let sessions = manager(10);
+        let turns = [("system", "s"), ("user", "aaaa"), ("assistant", "bbbb"), ("user", "cc"), ("assistant", "dd")];
+        sessions.save_session("a", session(&turns, Vec::new()), count).unwrap();
+
+        let saved = sessions.get_session("a").unwrap().unwrap();
+        let contents: Vec<&str> = saved.messages.iter().map(|turn| turn.content.as_str()).collect();
+        assert_eq!(contents, ["s", "cc", "dd"]);
+    }
+
+    #[test]
+    fn test_keeps_newest_exchange_and_bos() {
+        let sessions = manager(0);
+        let turns = [("user", "aaaa"), ("assistant", "bbbb"), ("user", "cc"), ("assistant", "dd")];
+        sessions.save_session("a", session(&turns, vec![1, 2, 3]), count).unwrap();
+
+        let saved = sessions.get_session("a").unwrap().unwrap();
+        assert_eq!(saved.messages.len(), 2);
+        assert_eq!(saved.context, [1]);
+    }
+
+    #[test]
+    fn test_context_keeps_bos_and_tail() {
+        let sessions = manager(3);
+        sessions.save_session("a", session(&[], vec![1, 2, 3, 4, 5]), count).unwrap();
+        assert_eq!(sessions.get_session("a").unwrap().unwrap().context, [1, 4, 5]);
     }
 }
@@ -135,7 +135,7 @@ impl Accelerator {
     #[cfg(any(target_os = "linux", target_os = "windows"))]
     fn get_cuda_device_count() -> usize {
         std::process::Command::new("nvidia-smi")
-            .args(&["--query-gpu=count", "--format=csv,noheader"])
+            .args(["--query-gpu=count", "--format=csv,noheader"])
             .output()
             .ok()
             .and_then(|output| {
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::mpsc::Receiver;
+
+use crate::config::Config;
+use crate::inference::reasoning::ReasoningFormat;
+use crate::inference::tools::ToolFormat;
+use crate::inference::{
+    ChatTurn, EmbeddingRequest, EmbeddingResponse, GenerationConfig, GenerationRequest, GenerationResponse,
+    LogitBiasToken, PromptCacheInfo, RerankRequest, RerankResponse, StreamEvent,
+};
+use crate::models::metadata::ModelMetadata;
+
+/// A model for `InferenceBackend::load`, resolved from its metadata by `ModelManager`.
+pub struct LoadOptions<'a> {
+    pub metadata: &'a ModelMetadata,
+    /// GPU layers to offload; `None` picks automatically
+    pub num_gpu: Option<u32>,
+    /// Weights of the draft model for speculative decoding
+    pub draft_path: Option<String>,
+}
+
+/// A loaded model as the API and CLI see it. `InferenceEngine` runs llama.cpp;
+/// `MockBackend` scripts replies so the API can be tested without weights.
+///
+/// Only loading, generation, tokenization, embeddings and the chat template are
+/// required. The rest default to the behavior of a model without that feature.
+#[async_trait]
+pub trait InferenceBackend: Send + Sync {
+    fn load(config: Arc<Config>, options: &LoadOptions) -> Result<Self>
+    where
+        Self: Sized;
+
+    async fn generate(&self, request: GenerationRequest) -> Result<GenerationResponse>;
+
+    async fn generate_stream(&self, request: GenerationRequest) -> Result<Receiver<Result<StreamEvent>>>;
+
+    /// Token ids of `text`, with BOS when `add_special` is set
+    fn tokenize(&self, text: &str, add_special: bool) -> Result<Vec<i32>>;
+
+    fn detokenize(&self, tokens: &[i32], render_special: bool) -> Result<String>;
+
+    /// Text of a single token
+    fn token_piece(&self, token: i32, render_special: bool) -> Result<String>;
+
+    async fn embed(&self, request: EmbeddingRequest) -> Result<EmbeddingResponse>;
+
+    fn apply_chat_template(&self, messages: &[ChatTurn]) -> Result<String>;
+
+    /// Identifies the weights and build; seeded outputs only repeat while it is unchanged
+    fn system_fingerprint(&self) -> String;
+
+    /// Number of tokens of `messages` rendered through the chat template, 0 if they
+    /// can't be rendered
+    fn chat_tokens(&self, messages: &[ChatTurn]) -> usize {
+        self.apply_chat_template(messages)
+            .and_then(|prompt| self.tokenize(&prompt, true))
+            .map_or(0, |tokens| tokens.len())
+    }
+
+    /// Load a fine-tune of this model: its adapters on top of these weights.
+    fn load_fine_tune(&self, options: &LoadOptions) -> Result<Arc<dyn InferenceBackend>> {
+        anyhow::bail!("Backend does not support adapters (fine-tune {})", options.metadata.name)
+    }
+
+    /// `n` independent completions of one prompt. The default generates them one after
+    /// another, seeding choice `i` with `seed + i`.
+    async fn generate_choices(&self, request: GenerationRequest, n: usize) -> Result<Vec<GenerationResponse>> {
+        let mut responses = Vec::with_capacity(n.max(1));
+        for i in 0..n.max(1) {
+            let config = GenerationConfig {
+                seed: request.config.seed.map(|seed| seed.wrapping_add(i as u32)),
+                ..request.config.clone()
+            };
+            responses.push(self.generate(GenerationRequest { config, ..request.clone() }).await?);
+        }
+        Ok(responses)
+    }
+
+    /// Streaming counterpart of `generate_choices`; every event carries its choice index.
+    /// With several choices the default sends each one whole once all are done.
+    async fn generate_choices_stream(
+        &self,
+        request: GenerationRequest,
+        n: usize,
+    ) -> Result<Receiver<Result<(usize, StreamEvent)>>> {
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+
+        if n <= 1 {
+            let mut events = self.generate_stream(request).await?;
+            tokio::spawn(async move {
+                while let Some(event) = events.recv().await {
+                    if tx.send(event.map(|e| (0, e))).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            return Ok(rx);
+        }
+
+        let responses = self.generate_choices(request, n).await?;
+        tokio::spawn(async move {
+            for (index, response) in responses.into_iter().enumerate() {
+                let events = [StreamEvent::Token(response.text.clone()), StreamEvent::Done(response)];
+                for event in events {
+                    if tx.send(Ok((index, event))).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    async fn rerank(&self, _request: RerankRequest) -> Result<RerankResponse> {
+        anyhow::bail!("Model does not support reranking")
+    }
+
+    fn tool_format(&self) -> ToolFormat {
+        ToolFormat::Hermes
+    }
+
+    fn reasoning_format(&self) -> Option<ReasoningFormat> {
+        None
+    }
+
+    fn infill_prompt(&self, _prefix: &str, _suffix: &str) -> Result<String> {
+        anyhow::bail!("Model does not support fill-in-the-middle completion")
+    }
+
+    fn validate_grammar(&self, _grammar: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn validate_logit_bias(&self, _entries: &[(LogitBiasToken, f32)]) -> Result<()> {
+        Ok(())
+    }
+
+    async fn save_prompt_cache(&self, _prompt: &str) -> Result<PromptCacheInfo> {
+        anyhow::bail!("Backend does not support prompt caching")
+    }
+
+    async fn load_prompt_cache(&self, _prompt: &str) -> Result<Option<PromptCacheInfo>> {
+        Ok(None)
+    }
+
+    /// Cache the KV state of the configured system prompts after loading
+    async fn warm_system_prompts(&self) {}
+
+    fn gpu_layers(&self) -> u32 {
+        0
+    }
+
+    fn supports_images(&self) -> bool {
+        false
+    }
+}
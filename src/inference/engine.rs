@@ -173,29 +173,163 @@ impl InferenceEngine {
 }
 =======
 use anyhow::Result;
-use std::path::Path;
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
+use tokio::sync::mpsc::Receiver;
 use tokio::sync::Mutex;
-use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::context::params::{LlamaContextParams, LlamaPoolingType};
+use llama_cpp_2::context::LlamaContext;
 use llama_cpp_2::llama_backend::LlamaBackend;
 use llama_cpp_2::llama_batch::LlamaBatch;
 use llama_cpp_2::model::params::LlamaModelParams;
-use llama_cpp_2::model::LlamaModel;
-use llama_cpp_2::token::data_array::LlamaTokenDataArray;
+use llama_cpp_2::model::{AddBos, LlamaChatMessage, LlamaLoraAdapter, LlamaModel};
+use llama_cpp_2::mtmd::{MtmdBitmap, MtmdContext, MtmdContextParams, MtmdInputText};
+use llama_cpp_2::token::logit_bias::LlamaLogitBias;
+use llama_cpp_2::token::LlamaToken;
 use llama_cpp_2::sampling::LlamaSampler;
 use crate::config::Config;
-use crate::inference::{GenerationRequest, GenerationResponse};
+use crate::inference::{
+    ChatTurn, EmbeddingPooling, EmbeddingRequest, EmbeddingResponse, GenerationConfig,
+    GenerationRequest, GenerationResponse, LogitBiasToken, PromptCacheInfo, RerankRequest, RerankResponse,
+    StreamEvent, TokenLogprob, TopLogprob,
+};
+use crate::inference::backend::{InferenceBackend, LoadOptions};
+use crate::inference::reasoning::ReasoningFormat;
+use crate::inference::sampler::DRY_SEQUENCE_BREAKERS;
+use crate::inference::speculative::{DraftModel, Drafter, PromptLookup, Proposer, SpeculativeStats};
+use crate::inference::tokenizer::{token_text, Tokenizer};
+use crate::inference::tools::ToolFormat;
 
 pub struct InferenceEngine {
     model_path: String,
-    _config: Arc<Config>,
+    digest: String,
+    config: Arc<Config>,
     backend: Arc<LlamaBackend>,
     model: Arc<LlamaModel>,
-    context_tokens: Arc<Mutex<Vec<i32>>>,
+    gpu_layers: u32,
+    /// Vision projector (mmproj) of a multimodal model
+    projector: Option<Arc<MtmdContext>>,
+    /// Small model drafting tokens for speculative decoding
+    draft: Option<Arc<DraftModel>>,
+    /// N-gram size for prompt lookup decoding when requests don't choose
+    prompt_lookup: Option<usize>,
+    /// LoRA adapters applied on top of the (possibly shared) base weights
+    adapters: Arc<Vec<Adapter>>,
+    slot: Arc<Mutex<Option<KvSlot>>>,
+    prompt_cache: Arc<Mutex<Vec<CachedPrefix>>>,
+}
+
+/// A LoRA adapter with the scale it is applied at unless a request overrides it.
+struct Adapter {
+    lora: std::sync::Mutex<LoraHandle>,
+    scale: f32,
+}
+
+/// `LlamaLoraAdapter` holds a raw pointer and so isn't `Send`. llama.cpp never mutates
+/// an adapter after loading it, and the mutex above serializes `lora_adapter_set`.
+struct LoraHandle(LlamaLoraAdapter);
+
+unsafe impl Send for LoraHandle {}
+
+/// Upper bound on sequences packed into one embedding batch
+const EMBED_MAX_SEQ: usize = 64;
+
+/// KV state left behind by the last generation, so a follow-up request that
+/// continues the same token sequence only evaluates the new tokens.
+struct KvSlot {
+    n_ctx: usize,
+    /// Adapter scale the state was computed with, see `effective_adapter_scale`
+    adapter_scale: Option<f32>,
+    tokens: Vec<LlamaToken>,
+    state: Vec<u8>,
+}
+
+/// A token prefix whose KV state has been saved to disk with `state_save_file`.
+struct CachedPrefix {
+    /// Adapter scale the state was computed with, see `effective_adapter_scale`
+    adapter_scale: Option<f32>,
+    tokens: Vec<LlamaToken>,
+    path: PathBuf,
+}
+
+/// Text generated by one sequence. Text that may be the start of a stop sequence
+/// is held back until it can be ruled out.
+struct SequenceOutput<'a> {
+    stops: &'a [String],
+    text: String,
+    /// Bytes of `text` already handed out
+    emitted: usize,
+    logprobs: Vec<TokenLogprob>,
+    /// Entries of `logprobs` already handed out
+    logprobs_emitted: usize,
+    tokens: Vec<LlamaToken>,
+}
+
+impl<'a> SequenceOutput<'a> {
+    fn new(stops: &'a [String]) -> Self {
+        Self {
+            stops,
+            text: String::new(),
+            emitted: 0,
+            logprobs: Vec::new(),
+            logprobs_emitted: 0,
+            tokens: Vec::new(),
+        }
+    }
+    
+    /// Append a sampled token. Returns true when a stop sequence ended the output,
+    /// in which case the text is cut right before it.
+    fn push(&mut self, token: LlamaToken, piece: &str, logprob: Option<TokenLogprob>) -> bool {
+        self.text.push_str(piece);
+        self.tokens.push(token);
+        self.logprobs.extend(logprob);
+        
+        match self.stops.iter().filter_map(|s| self.text[self.emitted..].find(s.as_str())).min() {
+            Some(pos) => {
+                self.text.truncate(self.emitted + pos);
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// Text (and its log probabilities) that can no longer turn into a stop sequence
+    fn take_ready(&mut self) -> Option<(String, Vec<TokenLogprob>)> {
+        let ready = self.text.len() - InferenceEngine::partial_stop_len(&self.text[self.emitted..], self.stops);
+        self.take_until(ready)
+    }
+    
+    /// Everything not handed out yet, once generation has ended
+    fn take_rest(&mut self) -> Option<(String, Vec<TokenLogprob>)> {
+        self.take_until(self.text.len())
+    }
+    
+    fn take_until(&mut self, end: usize) -> Option<(String, Vec<TokenLogprob>)> {
+        if end <= self.emitted {
+            return None;
+        }
+        let text = self.text[self.emitted..end].to_string();
+        let logprobs = self.logprobs[self.logprobs_emitted..].to_vec();
+        self.emitted = end;
+        self.logprobs_emitted = self.logprobs.len();
+        Some((text, logprobs))
+    }
 }
 
 impl InferenceEngine {
-    pub fn new(model_path: &str, config: Arc<Config>) -> Result<Self> {
+    /// Load a model, offloading `gpu_layers` layers (auto-detected when `None`), its
+    /// vision projector when the model has one and its draft model when configured.
+    pub fn new(
+        model_path: &str,
+        digest: &str,
+        config: Arc<Config>,
+        gpu_layers: Option<u32>,
+        projector_path: Option<&str>,
+        draft_path: Option<&str>,
+    ) -> Result<Self> {
         if !Path::new(model_path).exists() {
             anyhow::bail!("Model file not found: {}", model_path);
         }
@@ -205,8 +339,8 @@ impl InferenceEngine {
         
         let backend = LlamaBackend::init()?;
         
-        // GPU layers - offload everything to GPU
-        let gpu_layers = Self::detect_gpu_layers();
+        // GPU layers - offload everything to GPU unless the request asked otherwise
+        let gpu_layers = gpu_layers.unwrap_or_else(Self::detect_gpu_layers);
         tracing::info!("GPU layers: {}", gpu_layers);
         
         // Performance-optimized model parameters
@@ -217,183 +351,1274 @@ impl InferenceEngine {
         
         tracing::info!("✓ Model loaded with GPU acceleration + mmap");
         
+        let projector = match projector_path {
+            Some(path) => {
+                tracing::info!("Loading vision projector from: {}", path);
+                let params = MtmdContextParams {
+                    use_gpu: gpu_layers > 0,
+                    ..Default::default()
+                };
+                let projector = MtmdContext::init_from_file(path, &model, &params)
+                    .map_err(|e| anyhow::anyhow!("Failed to load vision projector {}: {}", path, e))?;
+                Some(Arc::new(projector))
+            }
+            None => None,
+        };
+        
+        // A draft model that can't be used only costs speed, so fall back to plain decoding
+        let draft = match draft_path {
+            Some(path) => {
+                tracing::info!("Loading draft model from: {}", path);
+                match DraftModel::load(&backend, &model, path, gpu_layers, config.draft_max_tokens) {
+                    Ok(draft) => Some(Arc::new(draft)),
+                    Err(e) => {
+                        tracing::warn!("Speculative decoding disabled, draft model {} is unusable: {}", path, e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        
         Ok(Self {
             model_path: model_path.to_string(),
-            _config: config,
+            digest: digest.to_string(),
+            config,
             backend: Arc::new(backend),
             model: Arc::new(model),
-            context_tokens: Arc::new(Mutex::new(Vec::new())),
+            gpu_layers,
+            projector,
+            draft,
+            prompt_lookup: None,
+            adapters: Arc::new(Vec::new()),
+            slot: Arc::new(Mutex::new(None)),
+            prompt_cache: Arc::new(Mutex::new(Vec::new())),
         })
     }
     
+    /// An engine for a fine-tune of this model: shares its weights, projector and draft
+    /// model, and applies the LoRA `adapters` (path and scale) to every context.
+    pub fn with_adapters(&self, digest: &str, adapters: &[(&str, f32)]) -> Result<Self> {
+        let adapters = adapters.iter()
+            .map(|&(path, scale)| {
+                tracing::info!("Loading LoRA adapter from: {}", path);
+                let lora = self.model.lora_adapter_init(path)
+                    .map_err(|e| anyhow::anyhow!("Failed to load LoRA adapter {}: {}", path, e))?;
+                Ok(Adapter { lora: std::sync::Mutex::new(LoraHandle(lora)), scale })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        
+        Ok(Self {
+            model_path: self.model_path.clone(),
+            digest: digest.to_string(),
+            config: self.config.clone(),
+            backend: self.backend.clone(),
+            model: self.model.clone(),
+            gpu_layers: self.gpu_layers,
+            projector: self.projector.clone(),
+            draft: self.draft.clone(),
+            prompt_lookup: self.prompt_lookup,
+            adapters: Arc::new(adapters),
+            // KV state computed with other adapters can't be reused
+            slot: Arc::new(Mutex::new(None)),
+            prompt_cache: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+    
+    /// Use prompt lookup decoding with `ngram`-token matches unless a request opts out.
+    pub fn with_prompt_lookup(mut self, ngram: Option<usize>) -> Self {
+        self.prompt_lookup = ngram;
+        self
+    }
+    
     pub async fn generate(&self, request: GenerationRequest) -> Result<GenerationResponse> {
-        let prompt = request.prompt.clone();
-        let gen_config = request.config.clone();
+        tracing::info!("Generating response for prompt (length: {})", request.prompt.len());
+        let request = self.with_model_defaults(request);
+        
+        let model = self.model.clone();
+        let backend = self.backend.clone();
+        let slot = self.slot.clone();
+        let prompt_cache = self.prompt_cache.clone();
+        let projector = self.projector.clone();
+        let adapters = self.adapters.clone();
+        let draft = self.draft.clone();
+        
+        tokio::task::spawn_blocking(move || {
+            Self::run_generation(&model, &backend, &adapters, &slot, &prompt_cache, projector.as_deref(), draft.as_deref(), &request, |_, _| true)
+        }).await?
+    }
+    
+    /// Generate `n` independent completions of one prompt. The prompt is evaluated once
+    /// and its KV cache copied to each sequence, which are then decoded in a shared batch.
+    pub async fn generate_choices(&self, request: GenerationRequest, n: usize) -> Result<Vec<GenerationResponse>> {
+        if n <= 1 {
+            return Ok(vec![self.generate(request).await?]);
+        }
+        
+        let model = self.model.clone();
+        let backend = self.backend.clone();
+        let projector = self.projector.clone();
+        let adapters = self.adapters.clone();
+        
+        tokio::task::spawn_blocking(move || {
+            Self::run_parallel_generation(&model, &backend, &adapters, projector.as_deref(), &request, n, |_, _, _| true)
+        }).await?
+    }
+    
+    /// Streaming counterpart of `generate_choices`; every event carries its choice index.
+    pub async fn generate_choices_stream(
+        &self,
+        request: GenerationRequest,
+        n: usize,
+    ) -> Result<tokio::sync::mpsc::Receiver<Result<(usize, StreamEvent)>>> {
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
         
-        tracing::info!("Generating response for prompt (length: {})", prompt.len());
+        if n <= 1 {
+            let mut events = self.generate_stream(request).await?;
+            tokio::spawn(async move {
+                while let Some(event) = events.recv().await {
+                    if tx.send(event.map(|e| (0, e))).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            return Ok(rx);
+        }
         
         let model = self.model.clone();
-        let max_tokens = gen_config.max_tokens as i32;
-        let temperature = gen_config.temperature;
-        
-        let response_text = tokio::task::spawn_blocking(move || -> Result<String> {
-            // Performance-optimized context parameters
-            let n_threads = Self::get_optimal_threads();
-            let ctx_size = Self::get_env_usize("RUST_LLM_CONTEXT_SIZE", 4096);
-            let batch_size = Self::get_env_usize("RUST_LLM_BATCH_SIZE", 512);
+        let backend = self.backend.clone();
+        let projector = self.projector.clone();
+        let adapters = self.adapters.clone();
+        
+        tokio::task::spawn_blocking(move || {
+            let result = Self::run_parallel_generation(&model, &backend, &adapters, projector.as_deref(), &request, n, |index, piece, logprobs| {
+                if !logprobs.is_empty() && tx.blocking_send(Ok((index, StreamEvent::Logprobs(logprobs)))).is_err() {
+                    return false;
+                }
+                tx.blocking_send(Ok((index, StreamEvent::Token(piece.to_string())))).is_ok()
+            });
             
-            let ctx_params = LlamaContextParams::default()
-                .with_n_ctx(std::num::NonZeroU32::new(ctx_size as u32))
-                .with_n_batch(batch_size as u32)
-                .with_n_threads(n_threads as i32)
-                .with_n_threads_batch(n_threads as i32);
+            match result {
+                Ok(responses) => {
+                    for (index, response) in responses.into_iter().enumerate() {
+                        let _ = tx.blocking_send(Ok((index, StreamEvent::Done(response))));
+                    }
+                }
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(e));
+                }
+            }
+        });
+        
+        Ok(rx)
+    }
+    
+    pub async fn generate_stream(
+        &self,
+        request: GenerationRequest,
+    ) -> Result<tokio::sync::mpsc::Receiver<Result<StreamEvent>>> {
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        let request = self.with_model_defaults(request);
+        
+        let model = self.model.clone();
+        let backend = self.backend.clone();
+        let slot = self.slot.clone();
+        let prompt_cache = self.prompt_cache.clone();
+        let projector = self.projector.clone();
+        let adapters = self.adapters.clone();
+        let draft = self.draft.clone();
+        
+        tokio::task::spawn_blocking(move || {
+            let result = Self::run_generation(&model, &backend, &adapters, &slot, &prompt_cache, projector.as_deref(), draft.as_deref(), &request, |piece, logprobs| {
+                if !logprobs.is_empty() && tx.blocking_send(Ok(StreamEvent::Logprobs(logprobs))).is_err() {
+                    return false;
+                }
+                // A send error means the receiver was dropped (client disconnected or
+                // generation interrupted), which stops decoding
+                tx.blocking_send(Ok(StreamEvent::Token(piece.to_string()))).is_ok()
+            });
             
-            let mut ctx = model.new_context(&LlamaBackend::init()?, ctx_params)?;
+            let _ = tx.blocking_send(result.map(StreamEvent::Done));
+        });
+        
+        Ok(rx)
+    }
+    
+    fn with_model_defaults(&self, mut request: GenerationRequest) -> GenerationRequest {
+        request.config.prompt_lookup = request.config.prompt_lookup.or(self.prompt_lookup);
+        request
+    }
+    
+    /// Evaluate the prompt and sample until EOG, `max_tokens`, or `on_token` returns false.
+    /// `on_token` also receives the log probabilities of the tokens behind each piece.
+    /// With speculative decoding (prompt lookup, else a draft model), each step decodes the
+    /// sampled token together with proposed tokens after it, and keeps the proposals the
+    /// target samples as well.
+    #[allow(clippy::too_many_arguments)]
+    fn run_generation(
+        model: &LlamaModel,
+        backend: &LlamaBackend,
+        adapters: &[Adapter],
+        slot: &Mutex<Option<KvSlot>>,
+        prompt_cache: &Mutex<Vec<CachedPrefix>>,
+        projector: Option<&MtmdContext>,
+        draft: Option<&DraftModel>,
+        request: &GenerationRequest,
+        mut on_token: impl FnMut(&str, Vec<TokenLogprob>) -> bool,
+    ) -> Result<GenerationResponse> {
+        let gen_config = &request.config;
+        let batch_size = Self::batch_size(gen_config);
+        let n_ctx = Self::context_size(gen_config);
+        let mut ctx = model.new_context(backend, Self::context_params(gen_config))?;
+        Self::apply_adapters(&mut ctx, adapters, gen_config.adapter_scale)?;
+        
+        // Clear KV cache before starting new generation
+        ctx.clear_kv_cache();
+        
+        let mut batch = LlamaBatch::new(batch_size, 1);
+        let mut tokens = Vec::new();
+        
+        // Prompts with images have no plain token sequence to reuse, so they are always
+        // evaluated in full; sampling then starts from the last logits (index -1)
+        let prompt_tokens = if request.images.is_empty() {
+            tokens = Self::prompt_tokens(model, request, n_ctx)?;
             
-            // Clear KV cache before starting new generation
-            ctx.clear_kv_cache();
+            // Skip re-evaluating a prefix still held by the slot or cached to disk, as long
+            // as the adapters were applied at the same scale
+            let adapter_scale = Self::effective_adapter_scale(adapters, gen_config.adapter_scale);
+            let n_past = match Self::restore_slot(&mut ctx, slot, n_ctx, adapter_scale, &tokens) {
+                0 => Self::restore_cached_prefix(&mut ctx, prompt_cache, adapter_scale, &tokens),
+                n => n,
+            };
             
-            let tokens = model.str_to_token(&prompt, llama_cpp_2::model::AddBos::Always)?;
-            tracing::debug!("Tokenized prompt into {} tokens", tokens.len());
+            Self::decode_tokens(&mut ctx, &mut batch, &tokens[n_past..], n_past as i32, batch_size)?;
+            tokens.len()
+        } else {
+            Self::eval_multimodal(projector, &mut ctx, request, batch_size)?
+        };
+        
+        let mut output = SequenceOutput::new(&gen_config.stop_sequences);
+        let mut n_cur = prompt_tokens as i32;
+        let max_tokens = gen_config.max_tokens;
+        
+        let mut sampler = Self::build_sampler(model, gen_config)?;
+        
+        // Both proposers only see text: prompts with images have no token history to
+        // look up n-grams in or to prefill the draft model with
+        let mut proposer = match (gen_config.prompt_lookup.filter(|&n| n > 0), draft) {
+            _ if !request.images.is_empty() => None,
+            (Some(ngram), _) => Some(Proposer::PromptLookup(PromptLookup::new(ngram))),
+            (None, Some(draft)) => {
+                let drafter = Drafter::new(draft, backend, Self::context_params(gen_config), batch_size)?;
+                Some(Proposer::Draft(drafter))
+            }
+            _ => None,
+        };
+        let mut stats = SpeculativeStats::default();
+        
+        // Batch indices to sample from in order: the last one, or after a speculative step
+        // one per draft token plus one for the token following them
+        let mut logits_idx = vec![batch.n_tokens() - 1];
+        // Draft tokens decoded in the current batch, kept while the target agrees with them
+        let mut draft_tokens: Vec<LlamaToken> = Vec::new();
+        
+        'generate: while output.tokens.len() < max_tokens {
+            let mut new_token = None;
             
-            let mut batch = LlamaBatch::new(batch_size, 1);
-            for (i, token) in tokens.iter().enumerate() {
-                let is_last = i == tokens.len() - 1;
-                batch.add(*token, i as i32, &[0], is_last)?;
+            for (i, &idx) in logits_idx.iter().enumerate() {
+                let token = sampler.sample(&ctx, idx);
+                sampler.accept(token);
+                
+                if model.is_eog_token(token) {
+                    break 'generate;
+                }
+                
+                let logprob = gen_config.logprobs
+                    .map(|top_n| Self::token_logprob(model, &ctx, idx, token, top_n))
+                    .transpose()?;
+                let piece = token_text(model, token)?;
+                tokens.push(token);
+                
+                if output.push(token, &piece, logprob) {
+                    break 'generate;
+                }
+                
+                if let Some((text, logprobs)) = output.take_ready() {
+                    if !on_token(&text, logprobs) {
+                        break 'generate;
+                    }
+                }
+                
+                if output.tokens.len() >= max_tokens {
+                    break 'generate;
+                }
+                
+                // A draft token the target agrees with is already decoded at `n_cur`
+                if draft_tokens.get(i) == Some(&token) {
+                    stats.accepted += 1;
+                    n_cur += 1;
+                } else {
+                    new_token = Some(token);
+                    break;
+                }
             }
             
-            ctx.decode(&mut batch)?;
+            let Some(new_token) = new_token else {
+                break;
+            };
             
-            let mut output = String::new();
-            let mut n_cur = tokens.len() as i32;
+            if n_cur as usize >= n_ctx {
+                tracing::debug!("Context window of {} tokens is full", n_ctx);
+                break;
+            }
             
-            // High-performance sampler chain
-            let temp = Self::get_env_f32("RUST_LLM_TEMPERATURE", 0.7);
-            let top_p = Self::get_env_f32("RUST_LLM_TOP_P", 0.9);
-            let top_k = Self::get_env_usize("RUST_LLM_TOP_K", 40) as i32;
-            let seed = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as u32;
+            // Positions from `n_cur` on hold draft tokens the target rejected
+            if !draft_tokens.is_empty() {
+                ctx.clear_kv_cache_seq(Some(0), Some(n_cur as u32), None)?;
+            }
             
-            // Build sampler chain: top_k -> top_p -> temp -> dist
-            let mut sampler = LlamaSampler::chain_simple([
-                LlamaSampler::top_k(top_k),
-                LlamaSampler::top_p(top_p, 1),
-                LlamaSampler::temp(temp),
-                LlamaSampler::dist(seed),
-            ]);
+            draft_tokens = match proposer.as_mut() {
+                Some(proposer) => {
+                    let room = (max_tokens - output.tokens.len())
+                        .min(n_ctx - n_cur as usize - 1)
+                        .min(batch_size - 1);
+                    proposer.propose(&tokens, room)?
+                }
+                None => Vec::new(),
+            };
+            stats.drafted += draft_tokens.len();
+            
+            batch.clear();
+            batch.add(new_token, n_cur, &[0], true)?;
+            for (i, &token) in draft_tokens.iter().enumerate() {
+                batch.add(token, n_cur + 1 + i as i32, &[0], true)?;
+            }
+            ctx.decode(&mut batch)?;
+            n_cur += 1;
+            logits_idx = (0..batch.n_tokens()).collect();
+        }
+        
+        if let Some((text, logprobs)) = output.take_rest() {
+            on_token(&text, logprobs);
+        }
+        
+        if proposer.is_some() {
+            tracing::debug!(
+                "Speculative decoding accepted {}/{} draft tokens ({:.0}%)",
+                stats.accepted,
+                stats.drafted,
+                stats.acceptance_rate() * 100.0
+            );
+            // Leave only accepted tokens in the KV cache for the slot
+            ctx.clear_kv_cache_seq(Some(0), Some(n_cur as u32), None)?;
+        }
+        
+        if request.images.is_empty() {
+            let adapter_scale = Self::effective_adapter_scale(adapters, gen_config.adapter_scale);
+            Self::save_slot(&ctx, slot, n_ctx, adapter_scale, &tokens[..n_cur as usize]);
+        }
+        
+        Ok(GenerationResponse {
+            text: output.text,
+            tokens_generated: output.tokens.len(),
+            prompt_tokens,
+            // A prompt with images can't be replayed from tokens, so it has no context
+            context: if request.images.is_empty() {
+                tokens.iter().map(|t| t.0).collect()
+            } else {
+                Vec::new()
+            },
+            logprobs: output.logprobs,
+            speculative: proposer.map(|_| stats),
+        })
+    }
+    
+    /// Like `run_generation`, for `n` sequences sharing one prompt prefill. Each sequence
+    /// samples with its own chain (seeded `seed + index`) until it ends on its own.
+    #[allow(clippy::too_many_arguments)]
+    fn run_parallel_generation(
+        model: &LlamaModel,
+        backend: &LlamaBackend,
+        adapters: &[Adapter],
+        projector: Option<&MtmdContext>,
+        request: &GenerationRequest,
+        n: usize,
+        mut on_token: impl FnMut(usize, &str, Vec<TokenLogprob>) -> bool,
+    ) -> Result<Vec<GenerationResponse>> {
+        let gen_config = &request.config;
+        let batch_size = Self::batch_size(gen_config).max(n);
+        let n_ctx = Self::context_size(gen_config);
+        // The KV cache is shared by all sequences, so each gets a full window of cells
+        let params = Self::context_params(gen_config)
+            .with_n_ctx(std::num::NonZeroU32::new((n_ctx * n) as u32))
+            .with_n_batch(batch_size as u32)
+            .with_n_seq_max(n as u32);
+        let mut ctx = model.new_context(backend, params)?;
+        Self::apply_adapters(&mut ctx, adapters, gen_config.adapter_scale)?;
+        
+        let mut batch = LlamaBatch::new(batch_size, n as i32);
+        let mut tokens = Vec::new();
+        let prompt_tokens = if request.images.is_empty() {
+            tokens = Self::prompt_tokens(model, request, n_ctx)?;
+            Self::decode_tokens(&mut ctx, &mut batch, &tokens, 0, batch_size)?;
+            tokens.len()
+        } else {
+            Self::eval_multimodal(projector, &mut ctx, request, batch_size)?
+        };
+        for seq in 1..n {
+            ctx.copy_kv_cache_seq(0, seq as i32, None, None)?;
+        }
+        
+        let base_seed = Self::sampler_seed(gen_config);
+        let mut samplers = (0..n)
+            .map(|i| {
+                let config = GenerationConfig {
+                    seed: Some(base_seed.wrapping_add(i as u32)),
+                    ..gen_config.clone()
+                };
+                Self::build_sampler(model, &config)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        
+        let mut outputs: Vec<_> = (0..n).map(|_| SequenceOutput::new(&gen_config.stop_sequences)).collect();
+        // Batch index holding the logits of each still-running sequence; all start from the prompt's last token
+        let mut logits_idx = vec![Some(batch.n_tokens() - 1); n];
+        let mut n_cur = prompt_tokens as i32;
+        let mut cancelled = false;
+        
+        for _ in 0..gen_config.max_tokens {
+            let mut next = Vec::with_capacity(n);
             
-            for _ in 0..max_tokens {
-                let new_token = sampler.sample(&ctx, batch.n_tokens() - 1);
-                sampler.accept(new_token);
+            for seq in 0..n {
+                let Some(idx) = logits_idx[seq].take() else {
+                    continue;
+                };
+                
+                let new_token = samplers[seq].sample(&ctx, idx);
+                samplers[seq].accept(new_token);
                 
                 if model.is_eog_token(new_token) {
-                    break;
+                    continue;
                 }
                 
-                let piece = model.token_to_str(new_token, llama_cpp_2::model::Special::Tokenize)?;
-                output.push_str(&piece);
+                let logprob = gen_config.logprobs
+                    .map(|top_n| Self::token_logprob(model, &ctx, idx, new_token, top_n))
+                    .transpose()?;
+                let piece = token_text(model, new_token)?;
+                
+                if outputs[seq].push(new_token, &piece, logprob) {
+                    continue;
+                }
                 
-                batch.clear();
-                batch.add(new_token, n_cur, &[0], true)?;
-                ctx.decode(&mut batch)?;
-                n_cur += 1;
+                if let Some((text, logprobs)) = outputs[seq].take_ready() {
+                    if !on_token(seq, &text, logprobs) {
+                        cancelled = true;
+                        break;
+                    }
+                }
+                
+                next.push((seq, new_token));
             }
             
-            Ok(output)
-        }).await??;
+            if cancelled || next.is_empty() {
+                break;
+            }
+            
+            if n_cur as usize >= n_ctx {
+                tracing::debug!("Context window of {} tokens is full", n_ctx);
+                break;
+            }
+            
+            batch.clear();
+            for (seq, token) in next {
+                batch.add(token, n_cur, &[seq as i32], true)?;
+                logits_idx[seq] = Some(batch.n_tokens() - 1);
+            }
+            ctx.decode(&mut batch)?;
+            n_cur += 1;
+        }
         
-        let tokens_generated = response_text.split_whitespace().count();
+        let mut responses = Vec::with_capacity(n);
+        for (seq, mut output) in outputs.into_iter().enumerate() {
+            if !cancelled {
+                if let Some((text, logprobs)) = output.take_rest() {
+                    on_token(seq, &text, logprobs);
+                }
+            }
+            
+            responses.push(GenerationResponse {
+                tokens_generated: output.tokens.len(),
+                prompt_tokens,
+                // A prompt with images can't be replayed from tokens, so it has no context
+                context: if request.images.is_empty() {
+                    tokens.iter().chain(&output.tokens).map(|t| t.0).collect()
+                } else {
+                    Vec::new()
+                },
+                text: output.text,
+                logprobs: output.logprobs,
+                speculative: None,
+            });
+        }
         
-        Ok(GenerationResponse {
-            text: response_text,
-            tokens_generated,
-            context: vec![],
+        Ok(responses)
+    }
+    
+    /// The scale override a request applies the adapters at, `None` for their own scales.
+    /// KV state computed under one scale is not valid under another.
+    fn effective_adapter_scale(adapters: &[Adapter], scale: Option<f32>) -> Option<f32> {
+        scale.filter(|_| !adapters.is_empty())
+    }
+    
+    /// Apply the engine's LoRA adapters to a new context, at `scale` when the request set one.
+    fn apply_adapters(ctx: &mut LlamaContext, adapters: &[Adapter], scale: Option<f32>) -> Result<()> {
+        for adapter in adapters {
+            let mut lora = adapter.lora.lock().unwrap_or_else(|e| e.into_inner());
+            ctx.lora_adapter_set(&mut lora.0, scale.unwrap_or(adapter.scale))
+                .map_err(|e| anyhow::anyhow!("Failed to apply LoRA adapter: {}", e))?;
+        }
+        Ok(())
+    }
+    
+    /// Evaluate a prompt with images into sequence 0. The text between image markers is
+    /// tokenized as usual and each image is encoded through the vision projector, with
+    /// the chunks decoded in prompt order. Returns the number of positions used.
+    fn eval_multimodal(
+        projector: Option<&MtmdContext>,
+        ctx: &mut LlamaContext,
+        request: &GenerationRequest,
+        batch_size: usize,
+    ) -> Result<usize> {
+        let projector = projector
+            .ok_or_else(|| anyhow::anyhow!("Model has no vision projector (mmproj); images are not supported"))?;
+        
+        let bitmaps = request.images.iter()
+            .map(|image| MtmdBitmap::from_buffer(projector, image))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| anyhow::anyhow!("Failed to decode image: {}", e))?;
+        let bitmap_refs: Vec<&MtmdBitmap> = bitmaps.iter().collect();
+        
+        let text = MtmdInputText {
+            text: request.prompt.clone(),
+            add_special: true,
+            parse_special: true,
+        };
+        let chunks = projector.tokenize(text, &bitmap_refs)
+            .map_err(|e| anyhow::anyhow!("Failed to tokenize prompt with images: {}", e))?;
+        
+        let n_ctx = Self::context_size(&request.config);
+        if chunks.total_tokens() >= n_ctx {
+            anyhow::bail!("Prompt with images takes {} tokens, more than the context of {}", chunks.total_tokens(), n_ctx);
+        }
+        
+        let n_past = chunks.eval_chunks(projector, ctx, 0, 0, batch_size as i32, true)
+            .map_err(|e| anyhow::anyhow!("Failed to evaluate prompt with images: {}", e))?;
+        tracing::debug!("Evaluated prompt with {} images into {} positions", request.images.len(), n_past);
+        Ok(n_past as usize)
+    }
+    
+    /// Tokenize the prompt after any token history, truncated to fit `n_ctx`.
+    fn prompt_tokens(model: &LlamaModel, request: &GenerationRequest, n_ctx: usize) -> Result<Vec<LlamaToken>> {
+        // Continue from a previous token context (e.g. a stored session) when given
+        let history = request.context.as_deref().unwrap_or_default();
+        let mut tokens: Vec<LlamaToken> = history.iter().map(|&t| LlamaToken::new(t)).collect();
+        let add_bos = if tokens.is_empty() { AddBos::Always } else { AddBos::Never };
+        tokens.extend(model.str_to_token(&request.prompt, add_bos)?);
+        tracing::debug!("Tokenized prompt into {} tokens", tokens.len());
+        
+        if tokens.len() >= n_ctx {
+            tokens = Self::truncate_prompt(tokens, n_ctx, request.config.num_keep.unwrap_or(0));
+        }
+        Ok(tokens)
+    }
+    
+    /// Log probability of `token` and of the `top_n` most likely tokens, from a
+    /// softmax over the raw logits at batch index `idx`.
+    fn token_logprob(
+        model: &LlamaModel,
+        ctx: &LlamaContext,
+        idx: i32,
+        token: LlamaToken,
+        top_n: usize,
+    ) -> Result<TokenLogprob> {
+        let logits = ctx.get_logits_ith(idx);
+        let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let log_sum = logits.iter().map(|l| (l - max).exp()).sum::<f32>().ln() + max;
+        
+        let mut ids: Vec<usize> = (0..logits.len()).collect();
+        let top_n = top_n.min(ids.len());
+        if top_n > 0 {
+            ids.select_nth_unstable_by(top_n - 1, |&a, &b| logits[b].total_cmp(&logits[a]));
+        }
+        ids.truncate(top_n);
+        ids.sort_by(|&a, &b| logits[b].total_cmp(&logits[a]));
+        
+        let top_logprobs = ids
+            .into_iter()
+            .map(|id| TopLogprob {
+                token: token_text(model, LlamaToken::new(id as i32)).unwrap_or_default(),
+                logprob: logits[id] - log_sum,
+            })
+            .collect();
+        
+        Ok(TokenLogprob {
+            token: token_text(model, token)?,
+            logprob: logits[token.0 as usize] - log_sum,
+            top_logprobs,
         })
     }
     
-    pub async fn generate_stream(
-        &self,
-        request: GenerationRequest,
-    ) -> Result<tokio::sync::mpsc::Receiver<Result<String>>> {
-        let (tx, rx) = tokio::sync::mpsc::channel(100);
+    /// Restore the slot's KV state if its tokens are a strict prefix of `tokens`.
+    /// Returns the number of tokens that no longer need evaluating.
+    fn restore_slot(
+        ctx: &mut LlamaContext,
+        slot: &Mutex<Option<KvSlot>>,
+        n_ctx: usize,
+        adapter_scale: Option<f32>,
+        tokens: &[LlamaToken],
+    ) -> usize {
+        let slot = slot.blocking_lock();
+        let Some(slot) = slot.as_ref() else {
+            return 0;
+        };
+        
+        // State is only compatible with a context of the same size and adapter scale
+        if slot.n_ctx != n_ctx || slot.adapter_scale != adapter_scale || slot.tokens.is_empty() || slot.tokens.len() >= tokens.len() || !tokens.starts_with(&slot.tokens) {
+            return 0;
+        }
+        
+        // SAFETY: the state was produced by `copy_state_data` on a context created from
+        // the same model with the same context parameters.
+        let read = unsafe { ctx.set_state_data(&slot.state) };
+        if read != slot.state.len() {
+            tracing::warn!("Failed to restore KV slot, evaluating full prompt");
+            ctx.clear_kv_cache();
+            return 0;
+        }
+        
+        tracing::debug!("Reusing {} tokens from KV slot", slot.tokens.len());
+        slot.tokens.len()
+    }
+    
+    /// Keep the KV state of `ctx`, which holds exactly `tokens`, for the next request.
+    fn save_slot(
+        ctx: &LlamaContext,
+        slot: &Mutex<Option<KvSlot>>,
+        n_ctx: usize,
+        adapter_scale: Option<f32>,
+        tokens: &[LlamaToken],
+    ) {
+        let mut state = vec![0u8; ctx.get_state_size()];
+        // SAFETY: `state` is sized with `get_state_size` for this context.
+        let written = unsafe { ctx.copy_state_data(state.as_mut_ptr()) };
+        state.truncate(written);
+        
+        *slot.blocking_lock() = Some(KvSlot {
+            n_ctx,
+            adapter_scale,
+            tokens: tokens.to_vec(),
+            state,
+        });
+    }
+    
+    /// Sampler chain for a request: logit bias -> penalties -> DRY -> grammar ->
+    /// top_k -> typical -> top_p -> min_p -> XTC -> temp -> dist, or mirostat
+    fn build_sampler(model: &LlamaModel, config: &GenerationConfig) -> Result<LlamaSampler> {
+        let seed = Self::sampler_seed(config);
+        let n_ctx = Self::context_size(config);
+        
+        let mut samplers = Vec::new();
+        if !config.logit_bias.is_empty() {
+            let biases = Self::logit_biases(model, &config.logit_bias)?;
+            samplers.push(LlamaSampler::logit_bias(model.n_vocab(), &biases));
+        }
+        
+        samplers.push(LlamaSampler::penalties(
+            Self::penalty_window(config.repeat_last_n, n_ctx),
+            config.repeat_penalty,
+            config.frequency_penalty,
+            config.presence_penalty,
+        ));
+        
+        if let Some(dry_last_n) = Self::dry_window(config, n_ctx) {
+            samplers.push(LlamaSampler::dry(
+                model,
+                config.dry_multiplier,
+                config.dry_base,
+                config.dry_allowed_length as i32,
+                dry_last_n,
+                DRY_SEQUENCE_BREAKERS,
+            ));
+        }
+        
+        // Mask tokens the grammar does not allow before anything else samples from them
+        if let Some(grammar) = &config.grammar {
+            samplers.push(Self::grammar_sampler(model, grammar)?);
+        }
+        
+        match config.mirostat {
+            1 => {
+                samplers.push(LlamaSampler::temp(config.temperature));
+                samplers.push(LlamaSampler::mirostat(
+                    model.n_vocab(),
+                    seed,
+                    config.mirostat_tau,
+                    config.mirostat_eta,
+                    100,
+                ));
+            }
+            2 => {
+                samplers.push(LlamaSampler::temp(config.temperature));
+                samplers.push(LlamaSampler::mirostat_v2(seed, config.mirostat_tau, config.mirostat_eta));
+            }
+            _ => {
+                samplers.push(LlamaSampler::top_k(config.top_k));
+                samplers.push(LlamaSampler::typical(config.typical_p, 1));
+                samplers.push(LlamaSampler::top_p(config.top_p, 1));
+                samplers.push(LlamaSampler::min_p(config.min_p, 1));
+                if config.xtc_probability > 0.0 {
+                    samplers.push(LlamaSampler::xtc(config.xtc_probability, config.xtc_threshold, 1, seed));
+                }
+                samplers.push(LlamaSampler::temp(config.temperature));
+                samplers.push(LlamaSampler::dist(seed));
+            }
+        }
+        
+        Ok(LlamaSampler::chain_simple(samplers))
+    }
+    
+    fn grammar_sampler(model: &LlamaModel, grammar: &str) -> Result<LlamaSampler> {
+        LlamaSampler::grammar(model, grammar, "root")
+            .map_err(|e| anyhow::anyhow!("Invalid grammar: {}", e))
+    }
+    
+    /// Check that a GBNF grammar parses, so callers can reject it before generating.
+    pub fn validate_grammar(&self, grammar: &str) -> Result<()> {
+        Self::grammar_sampler(&self.model, grammar).map(|_| ())
+    }
+    
+    /// Resolve `logit_bias` entries to token biases, tokenizing text entries.
+    fn logit_biases(model: &LlamaModel, entries: &[(LogitBiasToken, f32)]) -> Result<Vec<LlamaLogitBias>> {
+        let mut biases = Vec::new();
+        for (target, bias) in entries {
+            // As with OpenAI, -100 means the token is never sampled
+            let bias = if *bias <= -100.0 { f32::NEG_INFINITY } else { *bias };
+            match target {
+                LogitBiasToken::Id(id) => {
+                    if *id < 0 || *id >= model.n_vocab() {
+                        anyhow::bail!("logit_bias token id {} is outside the vocabulary", id);
+                    }
+                    biases.push(LlamaLogitBias::new(LlamaToken::new(*id), bias));
+                }
+                LogitBiasToken::Text(text) => {
+                    for token in model.str_to_token(text, AddBos::Never)? {
+                        biases.push(LlamaLogitBias::new(token, bias));
+                    }
+                }
+            }
+        }
+        Ok(biases)
+    }
+    
+    /// Check `logit_bias` entries against the vocabulary before generating.
+    pub fn validate_logit_bias(&self, entries: &[(LogitBiasToken, f32)]) -> Result<()> {
+        Self::logit_biases(&self.model, entries).map(|_| ())
+    }
+    
+    /// Resolve a penalty window: llama.cpp clamps negative sizes to 0 (off), while ours
+    /// use -1 for the whole context
+    fn penalty_window(last_n: i32, n_ctx: usize) -> i32 {
+        if last_n < 0 { i32::try_from(n_ctx).unwrap_or(i32::MAX) } else { last_n }
+    }
+    
+    /// Tokens the DRY sampler scans, or `None` when DRY stays out of the chain
+    fn dry_window(config: &GenerationConfig, n_ctx: usize) -> Option<i32> {
+        let last_n = Self::penalty_window(config.dry_penalty_last_n, n_ctx);
+        (config.dry_multiplier > 0.0 && last_n != 0).then_some(last_n)
+    }
+    
+    /// The request's seed, or a fresh one per call so concurrent requests never share it
+    fn sampler_seed(config: &GenerationConfig) -> u32 {
+        static CALLS: AtomicU32 = AtomicU32::new(0);
+        config.seed.unwrap_or_else(|| {
+            let nanos = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH).unwrap().subsec_nanos();
+            nanos ^ CALLS.fetch_add(1, Ordering::Relaxed).wrapping_mul(0x9E37_79B9)
+        })
+    }
+    
+    /// Length of the longest suffix of `text` that is a proper prefix of a stop sequence
+    fn partial_stop_len(text: &str, stops: &[String]) -> usize {
+        stops.iter()
+            .filter_map(|stop| {
+                (1..stop.len()).rev()
+                    .filter(|&k| stop.is_char_boundary(k))
+                    .find(|&k| text.ends_with(&stop[..k]))
+            })
+            .max()
+            .unwrap_or(0)
+    }
+    
+    /// Keep the first `num_keep` tokens and the most recent ones so the prompt leaves a
+    /// quarter of the context free for generation, as Ollama does.
+    fn truncate_prompt(tokens: Vec<LlamaToken>, n_ctx: usize, num_keep: usize) -> Vec<LlamaToken> {
+        tracing::warn!("Prompt of {} tokens exceeds context of {}, truncating", tokens.len(), n_ctx);
+        
+        let budget = n_ctx - n_ctx / 4;
+        let keep = num_keep.min(budget);
+        let tail = budget - keep;
+        
+        let mut truncated = tokens[..keep].to_vec();
+        truncated.extend_from_slice(&tokens[tokens.len() - tail..]);
+        truncated
+    }
+    
+    fn context_size(config: &GenerationConfig) -> usize {
+        config.num_ctx.unwrap_or_else(|| Self::get_env_usize("RUST_LLM_CONTEXT_SIZE", 4096))
+    }
+    
+    fn batch_size(config: &GenerationConfig) -> usize {
+        config.num_batch.unwrap_or_else(|| Self::get_env_usize("RUST_LLM_BATCH_SIZE", 512))
+    }
+    
+    /// Performance-optimized context parameters, overridable per request
+    fn context_params(config: &GenerationConfig) -> LlamaContextParams {
+        let n_threads = config.num_thread.unwrap_or_else(Self::get_optimal_threads);
+        let ctx_size = Self::context_size(config);
+        let batch_size = Self::batch_size(config);
+        
+        LlamaContextParams::default()
+            .with_n_ctx(std::num::NonZeroU32::new(ctx_size as u32))
+            .with_n_batch(batch_size as u32)
+            .with_n_threads(n_threads as i32)
+            .with_n_threads_batch(n_threads as i32)
+    }
+    
+    /// Decode `tokens` at positions starting from `start` in chunks of `batch_size`,
+    /// requesting logits only for the final token.
+    fn decode_tokens(
+        ctx: &mut LlamaContext,
+        batch: &mut LlamaBatch,
+        tokens: &[LlamaToken],
+        start: i32,
+        batch_size: usize,
+    ) -> Result<()> {
+        for (chunk_idx, chunk) in tokens.chunks(batch_size).enumerate() {
+            batch.clear();
+            for (i, token) in chunk.iter().enumerate() {
+                let idx = chunk_idx * batch_size + i;
+                batch.add(*token, start + idx as i32, &[0], idx == tokens.len() - 1)?;
+            }
+            ctx.decode(batch)?;
+        }
+        Ok(())
+    }
+    
+    /// Load the longest cached prefix of `tokens` into `ctx` and return its length.
+    /// At least one token is always left to decode so the context produces logits.
+    fn restore_cached_prefix(
+        ctx: &mut LlamaContext,
+        prompt_cache: &Mutex<Vec<CachedPrefix>>,
+        adapter_scale: Option<f32>,
+        tokens: &[LlamaToken],
+    ) -> usize {
+        let entry = {
+            let cache = prompt_cache.blocking_lock();
+            cache.iter()
+                .filter(|c| c.adapter_scale == adapter_scale)
+                .filter(|c| c.tokens.len() < tokens.len() && tokens.starts_with(&c.tokens))
+                .max_by_key(|c| c.tokens.len())
+                .map(|c| (c.path.clone(), c.tokens.len()))
+        };
+        
+        let Some((path, len)) = entry else {
+            return 0;
+        };
+        
+        match ctx.state_load_file(&path, len) {
+            Ok(restored) if restored.len() == len => {
+                tracing::debug!("Restored {} cached tokens from {}", len, path.display());
+                len
+            }
+            Ok(_) => {
+                tracing::warn!("Prompt cache {} does not match its prefix, ignoring", path.display());
+                ctx.clear_kv_cache();
+                0
+            }
+            Err(e) => {
+                tracing::warn!("Failed to load prompt cache {}: {}", path.display(), e);
+                ctx.clear_kv_cache();
+                0
+            }
+        }
+    }
+    
+    /// Evaluate `prompt` and save the resulting KV state to `cache_dir`, so later
+    /// prompts starting with it skip reprocessing that prefix.
+    pub async fn save_prompt_cache(&self, prompt: &str) -> Result<PromptCacheInfo> {
+        let tokens = self.model.str_to_token(prompt, AddBos::Always)?;
+        let path = self.prompt_cache_path(&tokens);
         
         let model = self.model.clone();
-        let prompt = request.prompt.clone();
-        let gen_config = request.config.clone();
+        let backend = self.backend.clone();
+        let adapters = self.adapters.clone();
+        let cache_tokens = tokens.clone();
+        let cache_path = path.clone();
         
         tokio::task::spawn_blocking(move || -> Result<()> {
-            // Performance-optimized context parameters
-            let n_threads = Self::get_optimal_threads();
-            let ctx_size = Self::get_env_usize("RUST_LLM_CONTEXT_SIZE", 4096);
-            let batch_size = Self::get_env_usize("RUST_LLM_BATCH_SIZE", 512);
-            
-            let ctx_params = LlamaContextParams::default()
-                .with_n_ctx(std::num::NonZeroU32::new(ctx_size as u32))
-                .with_n_batch(batch_size as u32)
-                .with_n_threads(n_threads as i32)
-                .with_n_threads_batch(n_threads as i32);
-            
-            let backend = LlamaBackend::init()?;
-            let mut ctx = model.new_context(&backend, ctx_params)?;
-            
-            // Clear KV cache before starting new generation
-            ctx.clear_kv_cache();
-            
-            let tokens = model.str_to_token(&prompt, llama_cpp_2::model::AddBos::Always)?;
-            
+            let gen_config = GenerationConfig::default();
+            let batch_size = Self::batch_size(&gen_config);
+            let mut ctx = model.new_context(&backend, Self::context_params(&gen_config))?;
+            Self::apply_adapters(&mut ctx, &adapters, None)?;
             let mut batch = LlamaBatch::new(batch_size, 1);
-            for (i, token) in tokens.iter().enumerate() {
-                let is_last = i == tokens.len() - 1;
-                batch.add(*token, i as i32, &[0], is_last)?;
-            }
+            Self::decode_tokens(&mut ctx, &mut batch, &cache_tokens, 0, batch_size)?;
             
-            ctx.decode(&mut batch)?;
-            
-            let mut n_cur = tokens.len() as i32;
-            let max_tokens = gen_config.max_tokens as i32;
-            
-            // High-performance sampler chain
-            let temp = Self::get_env_f32("RUST_LLM_TEMPERATURE", 0.7);
-            let top_p = Self::get_env_f32("RUST_LLM_TOP_P", 0.9);
-            let top_k = Self::get_env_usize("RUST_LLM_TOP_K", 40) as i32;
-            let seed = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as u32;
-            
-            // Build sampler chain: top_k -> top_p -> temp -> dist
-            let mut sampler = LlamaSampler::chain_simple([
-                LlamaSampler::top_k(top_k),
-                LlamaSampler::top_p(top_p, 1),
-                LlamaSampler::temp(temp),
-                LlamaSampler::dist(seed),
-            ]);
+            if let Some(parent) = cache_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            ctx.state_save_file(&cache_path, &cache_tokens)?;
+            Ok(())
+        }).await??;
+        
+        tracing::info!("Saved prompt cache ({} tokens) to {}", tokens.len(), path.display());
+        Ok(self.register_prompt_cache(tokens, path).await)
+    }
+    
+    /// Register a KV state previously saved for `prompt`, if one exists on disk.
+    pub async fn load_prompt_cache(&self, prompt: &str) -> Result<Option<PromptCacheInfo>> {
+        let tokens = self.model.str_to_token(prompt, AddBos::Always)?;
+        let path = self.prompt_cache_path(&tokens);
+        
+        if !path.exists() {
+            return Ok(None);
+        }
+        
+        Ok(Some(self.register_prompt_cache(tokens, path).await))
+    }
+    
+    /// Cache the rendered prefix of each configured system prompt, reusing files on disk.
+    pub async fn warm_system_prompts(&self) {
+        for prompt_file in &self.config.cached_system_prompts {
+            let result = async {
+                let system = tokio::fs::read_to_string(prompt_file).await?;
+                let prefix = self.render_chat(&[ChatTurn::new("system", system.trim())], false)?;
+                match self.load_prompt_cache(&prefix).await? {
+                    Some(info) => Ok(info),
+                    None => self.save_prompt_cache(&prefix).await,
+                }
+            }.await;
             
-            for _ in 0..max_tokens {
-                let new_token = sampler.sample(&ctx, batch.n_tokens() - 1);
-                sampler.accept(new_token);
-                
-                if model.is_eog_token(new_token) {
-                    break;
+            match result {
+                Ok(info) => tracing::info!("System prompt {} cached ({} tokens)", prompt_file.display(), info.tokens),
+                Err(e) => tracing::warn!("Failed to cache system prompt {}: {}", prompt_file.display(), e),
+            }
+        }
+    }
+    
+    async fn register_prompt_cache(&self, tokens: Vec<LlamaToken>, path: PathBuf) -> PromptCacheInfo {
+        let info = PromptCacheInfo {
+            path: path.to_string_lossy().to_string(),
+            tokens: tokens.len(),
+        };
+        
+        let mut cache = self.prompt_cache.lock().await;
+        if !cache.iter().any(|c| c.path == path) {
+            // Cached prefixes are evaluated with the adapters at their own scales
+            cache.push(CachedPrefix { adapter_scale: None, tokens, path });
+        }
+        
+        info
+    }
+    
+    /// Cache files are keyed by model digest and a hash of the token prefix
+    fn prompt_cache_path(&self, tokens: &[LlamaToken]) -> PathBuf {
+        let mut hasher = Sha256::new();
+        for token in tokens {
+            hasher.update(token.0.to_le_bytes());
+        }
+        let prefix_hash = hex::encode(&hasher.finalize()[..16]);
+        let digest = self.digest.replace([':', '/', '\\'], "-");
+        
+        self.config.cache_dir
+            .join("kv")
+            .join(format!("{}-{}.session", digest, prefix_hash))
+    }
+    
+    /// Render a conversation into a prompt using the chat template embedded in the GGUF.
+    /// Falls back to plain `role: content` lines when the model ships no template.
+    pub fn apply_chat_template(&self, messages: &[ChatTurn]) -> Result<String> {
+        self.render_chat(messages, true)
+    }
+    
+    fn render_chat(&self, messages: &[ChatTurn], add_assistant: bool) -> Result<String> {
+        let template = match self.model.chat_template(None) {
+            Ok(template) => template,
+            Err(e) => {
+                tracing::debug!("No chat template in model, using plain format: {}", e);
+                let mut prompt = messages.iter()
+                    .map(|m| format!("{}: {}", m.role, m.content))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if add_assistant {
+                    prompt.push_str("\nassistant:");
                 }
-                
-                let piece = model.token_to_str(new_token, llama_cpp_2::model::Special::Tokenize)?;
-                let _ = tx.blocking_send(Ok(piece));
-                
-                batch.clear();
-                batch.add(new_token, n_cur, &[0], true)?;
-                ctx.decode(&mut batch)?;
-                n_cur += 1;
+                return Ok(prompt);
+            }
+        };
+        
+        let chat = messages.iter()
+            .map(|m| LlamaChatMessage::new(m.role.clone(), m.content.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        
+        Ok(self.model.apply_chat_template(&template, &chat, add_assistant)?)
+    }
+    
+    /// Embed each input as one pooled vector. Inputs are packed into shared batches
+    /// as separate sequences.
+    pub async fn embed(&self, request: EmbeddingRequest) -> Result<EmbeddingResponse> {
+        let model = Arc::clone(&self.model);
+        let backend = Arc::clone(&self.backend);
+        let adapters = Arc::clone(&self.adapters);
+        let pooling = request.pooling.unwrap_or(self.config.embedding_pooling);
+        
+        tokio::task::spawn_blocking(move || Self::run_embedding(&model, &backend, &adapters, &request, pooling)).await?
+    }
+    
+    fn run_embedding(
+        model: &LlamaModel,
+        backend: &LlamaBackend,
+        adapters: &[Adapter],
+        request: &EmbeddingRequest,
+        pooling: EmbeddingPooling,
+    ) -> Result<EmbeddingResponse> {
+        let n_ctx = Self::context_size(&GenerationConfig::default());
+        let pooling = match pooling {
+            EmbeddingPooling::Mean => LlamaPoolingType::Mean,
+            EmbeddingPooling::Cls => LlamaPoolingType::Cls,
+            EmbeddingPooling::Last => LlamaPoolingType::Last,
+        };
+        
+        let mut inputs = Vec::with_capacity(request.inputs.len());
+        for input in &request.inputs {
+            let mut tokens = model.str_to_token(input, AddBos::Always)?;
+            if tokens.len() > n_ctx {
+                if !request.truncate {
+                    anyhow::bail!("Input of {} tokens exceeds the context length of {}", tokens.len(), n_ctx);
+                }
+                tokens.truncate(n_ctx);
+            }
+            inputs.push(tokens);
+        }
+        let prompt_tokens = inputs.iter().map(Vec::len).sum();
+        
+        Ok(EmbeddingResponse {
+            embeddings: Self::run_pooled(model, backend, adapters, &inputs, pooling, request.normalize)?,
+            prompt_tokens,
+        })
+    }
+    
+    /// Score each document's relevance to the query with a reranker (cross-encoder) model.
+    pub async fn rerank(&self, request: RerankRequest) -> Result<RerankResponse> {
+        let model = Arc::clone(&self.model);
+        let backend = Arc::clone(&self.backend);
+        let adapters = Arc::clone(&self.adapters);
+        
+        tokio::task::spawn_blocking(move || Self::run_rerank(&model, &backend, &adapters, &request)).await?
+    }
+    
+    fn run_rerank(
+        model: &LlamaModel,
+        backend: &LlamaBackend,
+        adapters: &[Adapter],
+        request: &RerankRequest,
+    ) -> Result<RerankResponse> {
+        let n_ctx = Self::context_size(&GenerationConfig::default());
+        let eos = model.token_eos();
+        let query = model.str_to_token(&request.query, AddBos::Always)?;
+        if query.len() + 3 > n_ctx {
+            anyhow::bail!("Query of {} tokens exceeds the context length of {}", query.len(), n_ctx);
+        }
+        
+        // Pair encoding of XLM-R based rerankers (bge, jina): <s> query </s></s> document </s>
+        let mut inputs = Vec::with_capacity(request.documents.len());
+        for document in &request.documents {
+            let mut tokens = query.clone();
+            tokens.extend([eos, eos]);
+            tokens.extend(model.str_to_token(document, AddBos::Never)?);
+            // Long documents are cut rather than failing the whole request
+            tokens.truncate(n_ctx - 1);
+            tokens.push(eos);
+            inputs.push(tokens);
+        }
+        let prompt_tokens = inputs.iter().map(Vec::len).sum();
+        
+        // Rank pooling yields the classifier logit; report it as a probability
+        let scores = Self::run_pooled(model, backend, adapters, &inputs, LlamaPoolingType::Rank, false)?
+            .into_iter()
+            .map(|output| {
+                let logit = output.first().copied().unwrap_or(f32::NEG_INFINITY);
+                1.0 / (1.0 + (-logit).exp())
+            })
+            .collect();
+        
+        Ok(RerankResponse {
+            scores,
+            prompt_tokens,
+        })
+    }
+    
+    /// Run tokenized inputs through the model with `pooling`, one output vector per input.
+    /// Inputs are packed into shared batches as separate sequences.
+    fn run_pooled(
+        model: &LlamaModel,
+        backend: &LlamaBackend,
+        adapters: &[Adapter],
+        inputs: &[Vec<LlamaToken>],
+        pooling: LlamaPoolingType,
+        normalize: bool,
+    ) -> Result<Vec<Vec<f32>>> {
+        let gen_config = GenerationConfig::default();
+        let n_ctx = Self::context_size(&gen_config);
+        let n_seq = inputs.len().clamp(1, EMBED_MAX_SEQ);
+        
+        // Each input has to fit in one micro-batch for pooling, so batch == ubatch == context
+        let params = Self::context_params(&gen_config)
+            .with_n_batch(n_ctx as u32)
+            .with_n_ubatch(n_ctx as u32)
+            .with_n_seq_max(n_seq as u32)
+            .with_embeddings(true)
+            .with_pooling_type(pooling);
+        let mut ctx = model.new_context(backend, params)?;
+        Self::apply_adapters(&mut ctx, adapters, None)?;
+        
+        let mut outputs = Vec::with_capacity(inputs.len());
+        let mut batch = LlamaBatch::new(n_ctx, 1);
+        let mut pending = 0;
+        
+        for tokens in inputs {
+            if pending == n_seq || batch.n_tokens() as usize + tokens.len() > n_ctx {
+                Self::decode_embeddings(&mut ctx, &mut batch, pending, normalize, &mut outputs)?;
+                pending = 0;
             }
             
-            Ok(())
-        });
+            for (pos, token) in tokens.iter().enumerate() {
+                batch.add(*token, pos as i32, &[pending as i32], true)?;
+            }
+            pending += 1;
+        }
         
-        Ok(rx)
+        if pending > 0 {
+            Self::decode_embeddings(&mut ctx, &mut batch, pending, normalize, &mut outputs)?;
+        }
+        
+        Ok(outputs)
+    }
+    
+    /// Decode a batch of `n_seq` sequences and collect their pooled embeddings.
+    fn decode_embeddings(
+        ctx: &mut LlamaContext,
+        batch: &mut LlamaBatch,
+        n_seq: usize,
+        normalize: bool,
+        embeddings: &mut Vec<Vec<f32>>,
+    ) -> Result<()> {
+        ctx.clear_kv_cache();
+        ctx.decode(batch)?;
+        
+        for seq in 0..n_seq {
+            let mut embedding = ctx.embeddings_seq_ith(seq as i32)?.to_vec();
+            if normalize {
+                let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+                if norm > 0.0 {
+                    embedding.iter_mut().for_each(|v| *v /= norm);
+                }
+            }
+            embeddings.push(embedding);
+        }
+        
+        batch.clear();
+        Ok(())
+    }
+    
+    /// Tool-call format of the model, judged from its chat template.
+    pub fn tool_format(&self) -> ToolFormat {
+        self.model.chat_template(None).ok()
+            .and_then(|template| template.to_string().ok())
+            .map(|template| ToolFormat::detect(&template))
+            .unwrap_or(ToolFormat::Hermes)
+    }
+    
+    /// `<think>` block format of a reasoning model, from its chat template
+    pub fn reasoning_format(&self) -> Option<ReasoningFormat> {
+        self.model.chat_template(None).ok()
+            .and_then(|template| template.to_string().ok())
+            .and_then(|template| ReasoningFormat::detect(&template))
+    }
+    
+    /// Build a fill-in-the-middle prompt from the FIM tokens in the model's vocabulary.
+    pub fn infill_prompt(&self, prefix: &str, suffix: &str) -> Result<String> {
+        // (prefix, suffix, middle) markers of the common code model families
+        const FIM_MARKERS: [(&str, &str, &str); 3] = [
+            ("<|fim_prefix|>", "<|fim_suffix|>", "<|fim_middle|>"),
+            ("<｜fim▁begin｜>", "<｜fim▁hole｜>", "<｜fim▁end｜>"),
+            ("<PRE>", "<SUF>", "<MID>"),
+        ];
+        
+        for (pre, suf, mid) in FIM_MARKERS {
+            if [pre, suf, mid].iter().all(|marker| self.is_special_token(marker)) {
+                return Ok(format!("{}{}{}{}{}", pre, prefix, suf, suffix, mid));
+            }
+        }
+        
+        anyhow::bail!("Model does not support fill-in-the-middle completion")
+    }
+    
+    fn is_special_token(&self, text: &str) -> bool {
+        matches!(self.model.str_to_token(text, AddBos::Never), Ok(tokens) if tokens.len() == 1)
+    }
+    
+    /// Tokenizer over the model's vocabulary
+    pub fn tokenizer(&self) -> Tokenizer {
+        Tokenizer::new(self.model.clone())
+    }
+    
+    /// OpenAI-style `system_fingerprint`. Seeded outputs are only reproducible while it
+    /// stays the same: it changes with the weights, the runner build and the compute backend.
+    pub fn system_fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.digest.as_bytes());
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.update(Self::backend_build().as_bytes());
+        hasher.update(self.gpu_layers.to_le_bytes());
+        format!("fp_{}", &hex::encode(hasher.finalize())[..10])
+    }
+    
+    /// GPU backend compiled into llama.cpp
+    fn backend_build() -> &'static str {
+        if cfg!(feature = "cuda") {
+            "cuda"
+        } else if cfg!(feature = "metal") {
+            "metal"
+        } else if cfg!(feature = "vulkan") {
+            "vulkan"
+        } else {
+            "cpu"
+        }
     }
     
     pub fn get_model_path(&self) -> &str {
         &self.model_path
     }
     
+    pub fn gpu_layers(&self) -> u32 {
+        self.gpu_layers
+    }
+    
+    /// Whether a vision projector is loaded, so prompts may contain images
+    pub fn supports_images(&self) -> bool {
+        self.projector.is_some()
+    }
+    
     /// Detect available GPU and return optimal number of layers to offload
     fn detect_gpu_layers() -> u32 {
         // First check environment variable override
@@ -467,12 +1692,141 @@ impl InferenceEngine {
             .and_then(|v| v.parse().ok())
             .unwrap_or(default)
     }
+}
+
+#[async_trait]
+impl InferenceBackend for InferenceEngine {
+    fn load(config: Arc<Config>, options: &LoadOptions) -> Result<Self> {
+        let metadata = options.metadata;
+        Ok(Self::new(
+            &metadata.path,
+            &metadata.digest,
+            config,
+            options.num_gpu,
+            metadata.projector.as_deref(),
+            options.draft_path.as_deref(),
+        )?.with_prompt_lookup(metadata.prompt_lookup))
+    }
     
-    fn get_env_f32(key: &str, default: f32) -> f32 {
-        std::env::var(key)
-            .ok()
-            .and_then(|v| v.parse().ok())
-            .unwrap_or(default)
+    fn load_fine_tune(&self, options: &LoadOptions) -> Result<Arc<dyn InferenceBackend>> {
+        let metadata = options.metadata;
+        let adapters: Vec<(&str, f32)> = metadata.adapters.iter()
+            .map(|adapter| (adapter.path.as_str(), adapter.scale))
+            .collect();
+        Ok(Arc::new(self.with_adapters(&metadata.digest, &adapters)?.with_prompt_lookup(metadata.prompt_lookup)))
+    }
+    
+    async fn generate(&self, request: GenerationRequest) -> Result<GenerationResponse> {
+        InferenceEngine::generate(self, request).await
+    }
+    
+    async fn generate_stream(&self, request: GenerationRequest) -> Result<Receiver<Result<StreamEvent>>> {
+        InferenceEngine::generate_stream(self, request).await
+    }
+    
+    async fn generate_choices(&self, request: GenerationRequest, n: usize) -> Result<Vec<GenerationResponse>> {
+        InferenceEngine::generate_choices(self, request, n).await
+    }
+    
+    async fn generate_choices_stream(
+        &self,
+        request: GenerationRequest,
+        n: usize,
+    ) -> Result<Receiver<Result<(usize, StreamEvent)>>> {
+        InferenceEngine::generate_choices_stream(self, request, n).await
+    }
+    
+    fn tokenize(&self, text: &str, add_special: bool) -> Result<Vec<i32>> {
+        self.tokenizer().encode(text, add_special)
+    }
+    
+    fn detokenize(&self, tokens: &[i32], render_special: bool) -> Result<String> {
+        self.tokenizer().decode(tokens, render_special)
+    }
+    
+    fn token_piece(&self, token: i32, render_special: bool) -> Result<String> {
+        self.tokenizer().piece(token, render_special)
+    }
+    
+    async fn embed(&self, request: EmbeddingRequest) -> Result<EmbeddingResponse> {
+        InferenceEngine::embed(self, request).await
+    }
+    
+    async fn rerank(&self, request: RerankRequest) -> Result<RerankResponse> {
+        InferenceEngine::rerank(self, request).await
+    }
+    
+    fn apply_chat_template(&self, messages: &[ChatTurn]) -> Result<String> {
+        InferenceEngine::apply_chat_template(self, messages)
+    }
+    
+    fn system_fingerprint(&self) -> String {
+        InferenceEngine::system_fingerprint(self)
+    }
+    
+    fn tool_format(&self) -> ToolFormat {
+        InferenceEngine::tool_format(self)
+    }
+    
+    fn reasoning_format(&self) -> Option<ReasoningFormat> {
+        InferenceEngine::reasoning_format(self)
+    }
+    
+    fn infill_prompt(&self, prefix: &str, suffix: &str) -> Result<String> {
+        InferenceEngine::infill_prompt(self, prefix, suffix)
+    }
+    
+    fn validate_grammar(&self, grammar: &str) -> Result<()> {
+        InferenceEngine::validate_grammar(self, grammar)
+    }
+    
+    fn validate_logit_bias(&self, entries: &[(LogitBiasToken, f32)]) -> Result<()> {
+        InferenceEngine::validate_logit_bias(self, entries)
+    }
+    
+    async fn save_prompt_cache(&self, prompt: &str) -> Result<PromptCacheInfo> {
+        InferenceEngine::save_prompt_cache(self, prompt).await
+    }
+    
+    async fn load_prompt_cache(&self, prompt: &str) -> Result<Option<PromptCacheInfo>> {
+        InferenceEngine::load_prompt_cache(self, prompt).await
+    }
+    
+    async fn warm_system_prompts(&self) {
+        InferenceEngine::warm_system_prompts(self).await
+    }
+    
+    fn gpu_layers(&self) -> u32 {
+        InferenceEngine::gpu_layers(self)
+    }
+    
+    fn supports_images(&self) -> bool {
+        InferenceEngine::supports_images(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_penalty_window_covers_context() {
+        assert_eq!(InferenceEngine::penalty_window(-1, 2048), 2048);
+        assert_eq!(InferenceEngine::penalty_window(64, 2048), 64);
+        assert_eq!(InferenceEngine::penalty_window(0, 2048), 0);
+    }
+
+    #[test]
+    fn dry_joins_chain_over_whole_context() {
+        let off = GenerationConfig::default();
+        assert_eq!(InferenceEngine::dry_window(&off, 2048), None);
+
+        let on = GenerationConfig { dry_multiplier: 0.8, ..GenerationConfig::default() };
+        assert_eq!(on.dry_penalty_last_n, -1);
+        assert_eq!(InferenceEngine::dry_window(&on, 2048), Some(2048));
+
+        let no_window = GenerationConfig { dry_penalty_last_n: 0, ..on };
+        assert_eq!(InferenceEngine::dry_window(&no_window, 2048), None);
     }
 }
 >>>>>>> bb9577f (20260204_220651)
@@ -0,0 +1,759 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// Whitespace and primitive value rules shared by every generated grammar.
+const PRIMITIVES: &[(&str, &str)] = &[
+    ("space", r#"| " " | "\n" [ \t]{0,20}"#),
+    ("boolean", r#"("true" | "false") space"#),
+    ("null", r#""null" space"#),
+    ("integer", r#"("-"? ([0-9] | [1-9] [0-9]{0,15})) space"#),
+    ("number", r#"("-"? ([0-9] | [1-9] [0-9]{0,15})) ("." [0-9]+)? ([eE] [-+]? [0-9]+)? space"#),
+    ("char", r#"[^"\\\x7F\x00-\x1F] | [\\] (["\\bfnrt] | "u" [0-9a-fA-F]{4})"#),
+    ("string", r#""\"" char* "\"" space"#),
+    ("value", r#"object | array | string | number | boolean | null"#),
+    ("object", r#""{" space (string ":" space value ("," space string ":" space value)*)? "}" space"#),
+    ("array", r#""[" space (value ("," space value)*)? "]" space"#),
+];
+
+/// Requested shape of a JSON response.
+#[derive(Debug, Clone)]
+pub enum JsonOutput {
+    /// Any JSON object (`json_object` / Ollama `format: "json"`)
+    Any,
+    /// A value matching this JSON Schema
+    Schema(Value),
+}
+
+impl JsonOutput {
+    /// GBNF grammar that only admits output of this shape.
+    pub fn grammar(&self) -> Result<String> {
+        match self {
+            JsonOutput::Any => Ok(render_rules(&BTreeMap::from([
+                ("root".to_string(), "object".to_string()),
+            ]))),
+            JsonOutput::Schema(schema) => schema_to_grammar(schema),
+        }
+    }
+
+    /// Parse generated text and check it against the schema.
+    pub fn parse(&self, text: &str) -> Result<Value> {
+        let value: Value = serde_json::from_str(text.trim())
+            .map_err(|e| anyhow::anyhow!("Output is not valid JSON: {}", e))?;
+        match self {
+            JsonOutput::Any => {
+                if !value.is_object() {
+                    anyhow::bail!("Output is not a JSON object");
+                }
+            }
+            JsonOutput::Schema(schema) => validate(schema, &value)
+                .map_err(|e| anyhow::anyhow!("Output does not match the schema: {}", e))?,
+        }
+        Ok(value)
+    }
+}
+
+/// Convert a JSON Schema into a GBNF grammar with start rule `root`.
+///
+/// Supports `type` (including type lists), `properties`/`required`, `items` with
+/// `minItems`/`maxItems`, `enum`, `const`, `anyOf`/`oneOf`, string `pattern`,
+/// `minLength`/`maxLength` and `format` (date, time, date-time, uuid), and local
+/// `$ref`s into `$defs`/`definitions`. Anything else falls back to any JSON value.
+pub fn schema_to_grammar(schema: &Value) -> Result<String> {
+    let mut converter = Converter {
+        root: schema,
+        rules: BTreeMap::new(),
+        refs: HashMap::new(),
+    };
+    let root = converter.visit(schema, "root")?;
+    converter.rules.insert("root".to_string(), root);
+    Ok(render_rules(&converter.rules))
+}
+
+fn render_rules(rules: &BTreeMap<String, String>) -> String {
+    let mut grammar = String::new();
+    for (name, body) in rules {
+        grammar.push_str(&format!("{} ::= {}\n", name, body));
+    }
+    for (name, body) in PRIMITIVES {
+        if !rules.contains_key(*name) {
+            grammar.push_str(&format!("{} ::= {}\n", name, body));
+        }
+    }
+    grammar
+}
+
+struct Converter<'a> {
+    root: &'a Value,
+    rules: BTreeMap<String, String>,
+    /// `$ref` target -> rule name, filled before visiting so recursive schemas terminate
+    refs: HashMap<String, String>,
+}
+
+impl<'a> Converter<'a> {
+    /// Return a GBNF expression for `schema`, adding helper rules named after `name`.
+    fn visit(&mut self, schema: &'a Value, name: &str) -> Result<String> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok("value".to_string()),
+            Value::Bool(false) => anyhow::bail!("Schema `false` admits no value"),
+            Value::Object(schema) => schema,
+            _ => anyhow::bail!("Invalid schema at {}", name),
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            return self.visit_ref(reference);
+        }
+
+        if let Some(value) = schema.get("const") {
+            return Ok(json_literal(value));
+        }
+
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            let alternatives: Vec<String> = values.iter().map(json_literal).collect();
+            return Ok(format!("({})", alternatives.join(" | ")));
+        }
+
+        for key in ["anyOf", "oneOf"] {
+            if let Some(variants) = schema.get(key).and_then(Value::as_array) {
+                let mut alternatives = Vec::with_capacity(variants.len());
+                for (i, variant) in variants.iter().enumerate() {
+                    alternatives.push(self.visit(variant, &format!("{}-{}", name, i))?);
+                }
+                return Ok(format!("({})", alternatives.join(" | ")));
+            }
+        }
+
+        match schema.get("type") {
+            Some(Value::Array(types)) => {
+                let mut alternatives = Vec::with_capacity(types.len());
+                for t in types {
+                    let t = t.as_str().ok_or_else(|| anyhow::anyhow!("Invalid type at {}", name))?;
+                    alternatives.push(self.visit_type(schema, t, name)?);
+                }
+                Ok(format!("({})", alternatives.join(" | ")))
+            }
+            Some(Value::String(t)) => self.visit_type(schema, t, name),
+            Some(_) => anyhow::bail!("Invalid type at {}", name),
+            None if schema.contains_key("properties") => self.visit_type(schema, "object", name),
+            None if schema.contains_key("items") => self.visit_type(schema, "array", name),
+            None => Ok("value".to_string()),
+        }
+    }
+
+    fn visit_type(
+        &mut self,
+        schema: &'a serde_json::Map<String, Value>,
+        schema_type: &str,
+        name: &str,
+    ) -> Result<String> {
+        match schema_type {
+            "object" => self.visit_object(schema, name),
+            "array" => self.visit_array(schema, name),
+            "string" => self.visit_string(schema, name),
+            "integer" => Ok("integer".to_string()),
+            "number" => Ok("number".to_string()),
+            "boolean" => Ok("boolean".to_string()),
+            "null" => Ok("null".to_string()),
+            other => anyhow::bail!("Unsupported type '{}' at {}", other, name),
+        }
+    }
+
+    fn visit_ref(&mut self, reference: &str) -> Result<String> {
+        if let Some(rule) = self.refs.get(reference) {
+            return Ok(rule.clone());
+        }
+
+        let path = reference.strip_prefix("#/")
+            .ok_or_else(|| anyhow::anyhow!("Only local $refs are supported: {}", reference))?;
+        let mut target = self.root;
+        for part in path.split('/') {
+            let part = part.replace("~1", "/").replace("~0", "~");
+            target = target.get(&part)
+                .ok_or_else(|| anyhow::anyhow!("Unresolved $ref: {}", reference))?;
+        }
+
+        let rule = self.unique_name(&format!("ref-{}", path.rsplit('/').next().unwrap_or(path)));
+        self.refs.insert(reference.to_string(), rule.clone());
+        let body = self.visit(target, &rule)?;
+        self.rules.insert(rule.clone(), body);
+        Ok(rule)
+    }
+
+    fn visit_object(&mut self, schema: &'a serde_json::Map<String, Value>, name: &str) -> Result<String> {
+        let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+            return Ok("object".to_string());
+        };
+        let required: Vec<&str> = schema.get("required")
+            .and_then(Value::as_array)
+            .map(|r| r.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+
+        let mut required_kvs = Vec::new();
+        let mut optional_kvs = Vec::new();
+        for (key, property) in properties {
+            let rule = self.unique_name(&format!("{}-{}", name, key));
+            let value = self.visit(property, &rule)?;
+            let kv = format!("{} space \":\" space {}", json_literal_raw(&Value::String(key.clone())), value);
+            self.rules.insert(rule.clone(), kv);
+            if required.contains(&key.as_str()) {
+                required_kvs.push(rule);
+            } else {
+                optional_kvs.push(rule);
+            }
+        }
+
+        // Required properties in schema order, then each optional one may follow
+        let body = if !required_kvs.is_empty() {
+            let mut body = required_kvs.join(" \",\" space ");
+            for kv in &optional_kvs {
+                body.push_str(&format!(" (\",\" space {})?", kv));
+            }
+            body
+        } else if !optional_kvs.is_empty() {
+            let alternatives: Vec<String> = (0..optional_kvs.len())
+                .map(|first| {
+                    let mut alternative = optional_kvs[first].clone();
+                    for kv in &optional_kvs[first + 1..] {
+                        alternative.push_str(&format!(" (\",\" space {})?", kv));
+                    }
+                    alternative
+                })
+                .collect();
+            format!("({})?", alternatives.join(" | "))
+        } else {
+            String::new()
+        };
+
+        Ok(format!("\"{{\" space {} \"}}\" space", body))
+    }
+
+    fn visit_array(&mut self, schema: &'a serde_json::Map<String, Value>, name: &str) -> Result<String> {
+        let item = match schema.get("items") {
+            Some(items) => {
+                let rule = self.unique_name(&format!("{}-item", name));
+                let body = self.visit(items, &rule)?;
+                self.rules.insert(rule.clone(), body);
+                rule
+            }
+            None => "value".to_string(),
+        };
+
+        let min = schema.get("minItems").and_then(Value::as_u64).unwrap_or(0);
+        let max = schema.get("maxItems").and_then(Value::as_u64);
+        let rest = format!("\",\" space {}", item);
+
+        let items = match (min, max) {
+            (_, Some(0)) => String::new(),
+            (0, None) => format!("({} ({})*)?", item, rest),
+            (0, Some(max)) => format!("({} ({}){{0,{}}})?", item, rest, max - 1),
+            (min, None) => format!("{} ({}){{{},}}", item, rest, min - 1),
+            (min, Some(max)) => format!("{} ({}){{{},{}}}", item, rest, min - 1, max.saturating_sub(1)),
+        };
+
+        Ok(format!("\"[\" space {} \"]\" space", items))
+    }
+
+    fn visit_string(&mut self, schema: &'a serde_json::Map<String, Value>, name: &str) -> Result<String> {
+        if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+            let rule = self.unique_name(&format!("{}-pattern", name));
+            let body = regex_to_gbnf(pattern)?;
+            self.rules.insert(rule.clone(), format!("\"\\\"\" {} \"\\\"\" space", body));
+            return Ok(rule);
+        }
+
+        let format = match schema.get("format").and_then(Value::as_str) {
+            Some("date") => Some(DATE),
+            Some("time") => Some(TIME),
+            Some("date-time") => Some(DATE_TIME),
+            Some("uuid") => Some(UUID),
+            _ => None,
+        };
+        if let Some(body) = format {
+            return Ok(format!("\"\\\"\" {} \"\\\"\" space", body));
+        }
+
+        let min = schema.get("minLength").and_then(Value::as_u64);
+        let max = schema.get("maxLength").and_then(Value::as_u64);
+        let chars = match (min, max) {
+            (None, None) => return Ok("string".to_string()),
+            (min, Some(max)) => format!("char{{{},{}}}", min.unwrap_or(0), max),
+            (Some(min), None) => format!("char{{{},}}", min),
+        };
+        Ok(format!("\"\\\"\" {} \"\\\"\" space", chars))
+    }
+
+    fn unique_name(&self, base: &str) -> String {
+        let base: String = base.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '-' })
+            .collect();
+        let mut name = base.clone();
+        let mut i = 1;
+        while self.rules.contains_key(&name) || self.refs.values().any(|r| r == &name) {
+            name = format!("{}{}", base, i);
+            i += 1;
+        }
+        name
+    }
+}
+
+const DATE: &str = r#"[0-9]{4} "-" ("0" [1-9] | "1" [0-2]) "-" ("0" [1-9] | [12] [0-9] | "3" [01])"#;
+const TIME: &str = r#"([01] [0-9] | "2" [0-3]) ":" [0-5] [0-9] ":" [0-5] [0-9] ("." [0-9]{1,6})? ("Z" | [+-] [0-9]{2} ":" [0-9]{2})?"#;
+const DATE_TIME: &str = r#"[0-9]{4} "-" ("0" [1-9] | "1" [0-2]) "-" ("0" [1-9] | [12] [0-9] | "3" [01]) "T" ([01] [0-9] | "2" [0-3]) ":" [0-5] [0-9] ":" [0-5] [0-9] ("." [0-9]{1,6})? ("Z" | [+-] [0-9]{2} ":" [0-9]{2})"#;
+const UUID: &str = r#"[0-9a-fA-F]{8} "-" [0-9a-fA-F]{4} "-" [0-9a-fA-F]{4} "-" [0-9a-fA-F]{4} "-" [0-9a-fA-F]{12}"#;
+
+/// GBNF literal matching the JSON serialization of `value`, followed by optional space.
+fn json_literal(value: &Value) -> String {
+    format!("{} space", json_literal_raw(value))
+}
+
+fn json_literal_raw(value: &Value) -> String {
+    gbnf_string(&value.to_string())
+}
+
+fn gbnf_string(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Translate a JSON Schema `pattern` (an ECMA regex subset: literals, `.`, classes,
+/// `\d \w \s`, groups, alternation and quantifiers) into a GBNF expression.
+fn regex_to_gbnf(pattern: &str) -> Result<String> {
+    let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+    let pattern = pattern.strip_suffix('$').unwrap_or(pattern);
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut pos = 0;
+    let expr = parse_alternation(&chars, &mut pos)?;
+    if pos != chars.len() {
+        anyhow::bail!("Unbalanced ')' in pattern: {}", pattern);
+    }
+    Ok(format!("({})", expr))
+}
+
+fn parse_alternation(chars: &[char], pos: &mut usize) -> Result<String> {
+    let mut alternatives = vec![parse_sequence(chars, pos)?];
+    while *pos < chars.len() && chars[*pos] == '|' {
+        *pos += 1;
+        alternatives.push(parse_sequence(chars, pos)?);
+    }
+    Ok(alternatives.join(" | "))
+}
+
+fn parse_sequence(chars: &[char], pos: &mut usize) -> Result<String> {
+    let mut items = Vec::new();
+    while *pos < chars.len() && chars[*pos] != '|' && chars[*pos] != ')' {
+        let atom = parse_atom(chars, pos)?;
+        let quantifier = parse_quantifier(chars, pos);
+        items.push(format!("{}{}", atom, quantifier));
+    }
+    Ok(if items.is_empty() { "\"\"".to_string() } else { items.join(" ") })
+}
+
+fn parse_atom(chars: &[char], pos: &mut usize) -> Result<String> {
+    let c = chars[*pos];
+    *pos += 1;
+    match c {
+        '(' => {
+            // Non-capturing groups are the same thing in a grammar
+            if chars.get(*pos) == Some(&'?') && chars.get(*pos + 1) == Some(&':') {
+                *pos += 2;
+            }
+            let inner = parse_alternation(chars, pos)?;
+            if chars.get(*pos) != Some(&')') {
+                anyhow::bail!("Unclosed group in pattern");
+            }
+            *pos += 1;
+            Ok(format!("({})", inner))
+        }
+        '[' => {
+            let mut class = String::from("[");
+            if chars.get(*pos) == Some(&'^') {
+                class.push('^');
+                *pos += 1;
+            }
+            while *pos < chars.len() && chars[*pos] != ']' {
+                let c = chars[*pos];
+                *pos += 1;
+                if c == '\\' && *pos < chars.len() {
+                    let escaped = chars[*pos];
+                    *pos += 1;
+                    class.push_str(&class_escape(escaped));
+                } else if c == '"' {
+                    class.push_str("\\\"");
+                } else {
+                    class.push(c);
+                }
+            }
+            if *pos >= chars.len() {
+                anyhow::bail!("Unclosed character class in pattern");
+            }
+            *pos += 1;
+            class.push(']');
+            Ok(class)
+        }
+        '.' => Ok(r#"[^"\\\n]"#.to_string()),
+        '\\' => {
+            let escaped = *chars.get(*pos).ok_or_else(|| anyhow::anyhow!("Trailing '\\' in pattern"))?;
+            *pos += 1;
+            Ok(match escaped {
+                'd' => "[0-9]".to_string(),
+                'w' => "[a-zA-Z0-9_]".to_string(),
+                's' => "[ \\t]".to_string(),
+                'D' => "[^0-9\"\\\\]".to_string(),
+                'W' => "[^a-zA-Z0-9_\"\\\\]".to_string(),
+                'S' => "[^ \\t\"\\\\]".to_string(),
+                'n' => string_char('\n'),
+                'r' => string_char('\r'),
+                't' => string_char('\t'),
+                c => string_char(c),
+            })
+        }
+        c => Ok(string_char(c)),
+    }
+}
+
+/// GBNF literal for `c` as it appears inside a JSON string, e.g. `\"` for a quote.
+fn string_char(c: char) -> String {
+    let json = Value::String(c.to_string()).to_string();
+    gbnf_string(&json[1..json.len() - 1])
+}
+
+fn class_escape(c: char) -> String {
+    match c {
+        'd' => "0-9".to_string(),
+        'w' => "a-zA-Z0-9_".to_string(),
+        's' => " \\t".to_string(),
+        ']' | '\\' | '-' | '^' => format!("\\{}", c),
+        c => c.to_string(),
+    }
+}
+
+fn parse_quantifier(chars: &[char], pos: &mut usize) -> String {
+    let quantifier = parse_greedy_quantifier(chars, pos);
+    // A grammar has no notion of lazy matching, so `+?` is just `+`
+    if !quantifier.is_empty() && chars.get(*pos) == Some(&'?') {
+        *pos += 1;
+    }
+    quantifier
+}
+
+fn parse_greedy_quantifier(chars: &[char], pos: &mut usize) -> String {
+    match chars.get(*pos) {
+        Some(&c @ ('*' | '+' | '?')) => {
+            *pos += 1;
+            c.to_string()
+        }
+        Some('{') => {
+            let end = chars[*pos..].iter().position(|&c| c == '}');
+            match end {
+                Some(end) => {
+                    let quantifier: String = chars[*pos..*pos + end + 1].iter().collect();
+                    *pos += end + 1;
+                    quantifier
+                }
+                None => String::new(),
+            }
+        }
+        _ => String::new(),
+    }
+}
+
+/// Check `value` against `schema` for the keywords the grammar converter understands,
+/// plus numeric bounds. String patterns are enforced by the grammar only.
+pub fn validate(schema: &Value, value: &Value) -> Result<(), String> {
+    validate_at(schema, schema, value, "$")
+}
+
+fn validate_at(root: &Value, schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(format!("{}: no value allowed", path)),
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        let target = reference.strip_prefix("#/")
+            .and_then(|p| p.split('/').try_fold(root, |node, part| node.get(part)))
+            .ok_or_else(|| format!("{}: unresolved $ref {}", path, reference))?;
+        return validate_at(root, target, value, path);
+    }
+
+    if let Some(expected) = schema.get("const") {
+        if value != expected {
+            return Err(format!("{}: expected {}", path, expected));
+        }
+    }
+
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(value) {
+            return Err(format!("{}: {} is not one of the allowed values", path, value));
+        }
+    }
+
+    for key in ["anyOf", "oneOf"] {
+        if let Some(variants) = schema.get(key).and_then(Value::as_array) {
+            if !variants.iter().any(|v| validate_at(root, v, value, path).is_ok()) {
+                return Err(format!("{}: does not match any of the {} schemas", path, key));
+            }
+        }
+    }
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            return Err(format!("{}: expected {}", path, types.join(" or ")));
+        }
+    }
+
+    match value {
+        Value::Object(object) => {
+            if let Some(required) = schema.get("required").and_then(Value::as_array) {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(key) {
+                        return Err(format!("{}: missing required property '{}'", path, key));
+                    }
+                }
+            }
+            if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+                for (key, property) in object {
+                    match properties.get(key) {
+                        Some(property_schema) => {
+                            validate_at(root, property_schema, property, &format!("{}.{}", path, key))?;
+                        }
+                        None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                            return Err(format!("{}: unexpected property '{}'", path, key));
+                        }
+                        None => {}
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                if (items.len() as u64) < min {
+                    return Err(format!("{}: expected at least {} items", path, min));
+                }
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+                if items.len() as u64 > max {
+                    return Err(format!("{}: expected at most {} items", path, max));
+                }
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate_at(root, item_schema, item, &format!("{}[{}]", path, i))?;
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if schema.get("minLength").and_then(Value::as_u64).is_some_and(|min| len < min) {
+                return Err(format!("{}: string is too short", path));
+            }
+            if schema.get("maxLength").and_then(Value::as_u64).is_some_and(|max| len > max) {
+                return Err(format!("{}: string is too long", path));
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if schema.get("minimum").and_then(Value::as_f64).is_some_and(|min| n < min) {
+                return Err(format!("{}: {} is below the minimum", path, n));
+            }
+            if schema.get("maximum").and_then(Value::as_f64).is_some_and(|max| n > max) {
+                return Err(format!("{}: {} is above the maximum", path, n));
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn has_type(value: &Value, schema_type: &str) -> bool {
+    match schema_type {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The rule bodies of the grammar for `schema`, by name.
+    fn rules(schema: Value) -> BTreeMap<String, String> {
+        schema_to_grammar(&schema).unwrap()
+            .lines()
+            .map(|line| {
+                let (name, body) = line.split_once(" ::= ").unwrap();
+                (name.to_string(), body.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_primitives() {
+        for (schema_type, rule) in [("integer", "integer"), ("number", "number"), ("boolean", "boolean"), ("null", "null"), ("string", "string")] {
+            let grammar = rules(json!({ "type": schema_type }));
+            assert_eq!(grammar["root"], rule);
+            // Every primitive is defined once
+            for (name, body) in PRIMITIVES {
+                assert_eq!(grammar[*name], *body);
+            }
+        }
+        assert_eq!(rules(json!({}))["root"], "value");
+        assert_eq!(rules(json!({ "type": ["string", "null"] }))["root"], "(string | null)");
+        assert!(schema_to_grammar(&json!({ "type": "decimal" })).is_err());
+        assert!(schema_to_grammar(&json!(false)).is_err());
+    }
+
+    #[test]
+    fn test_any_object() {
+        let grammar = JsonOutput::Any.grammar().unwrap();
+        assert!(grammar.starts_with("root ::= object\n"));
+    }
+
+    #[test]
+    fn test_required_and_optional_properties() {
+        let object = rules(json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "age": { "type": "integer" },
+                "email": { "type": "string" }
+            },
+            "required": ["name"]
+        }));
+        assert_eq!(object["root-name"], r#""\"name\"" space ":" space string"#);
+        assert_eq!(object["root-age"], r#""\"age\"" space ":" space integer"#);
+        // Properties keep the key order of the schema map, optional ones may follow
+        assert_eq!(
+            object["root"],
+            r#""{" space root-name ("," space root-age)? ("," space root-email)? "}" space"#
+        );
+
+        let all_optional = rules(json!({
+            "properties": { "a": { "type": "integer" }, "b": { "type": "integer" } }
+        }));
+        assert_eq!(
+            all_optional["root"],
+            r#""{" space (root-a ("," space root-b)? | root-b)? "}" space"#
+        );
+    }
+
+    #[test]
+    fn test_enum_and_const() {
+        assert_eq!(rules(json!({ "enum": ["red", 1, null] }))["root"], r#"("\"red\"" space | "1" space | "null" space)"#);
+        assert_eq!(rules(json!({ "const": { "k": "v" } }))["root"], r#""{\"k\":\"v\"}" space"#);
+    }
+
+    #[test]
+    fn test_arrays() {
+        let items = |min: Option<u64>, max: Option<u64>| {
+            let mut schema = json!({ "type": "array", "items": { "type": "integer" } });
+            if let Some(min) = min {
+                schema["minItems"] = json!(min);
+            }
+            if let Some(max) = max {
+                schema["maxItems"] = json!(max);
+            }
+            rules(schema)["root"].clone()
+        };
+        let rest = r#"("," space root-item)"#;
+        assert_eq!(items(None, None), format!(r#""[" space (root-item {}*)? "]" space"#, rest));
+        assert_eq!(items(None, Some(3)), format!(r#""[" space (root-item {}{{0,2}})? "]" space"#, rest));
+        assert_eq!(items(Some(2), None), format!(r#""[" space root-item {}{{1,}} "]" space"#, rest));
+        assert_eq!(items(Some(1), Some(4)), format!(r#""[" space root-item {}{{0,3}} "]" space"#, rest));
+        assert_eq!(items(None, Some(0)), r#""[" space  "]" space"#);
+        assert_eq!(rules(json!({ "type": "array" }))["root"], r#""[" space (value ("," space value)*)? "]" space"#);
+    }
+
+    #[test]
+    fn test_refs() {
+        let node = rules(json!({
+            "$defs": {
+                "node": {
+                    "type": "object",
+                    "properties": { "next": { "anyOf": [{ "$ref": "#/$defs/node" }, { "type": "null" }] } }
+                }
+            },
+            "$ref": "#/$defs/node"
+        }));
+        assert_eq!(node["root"], "ref-node");
+        // The recursive reference resolves to the same rule
+        assert_eq!(node["ref-node-next"], r#""\"next\"" space ":" space (ref-node | null)"#);
+
+        assert!(schema_to_grammar(&json!({ "$ref": "#/$defs/missing" })).is_err());
+        assert!(schema_to_grammar(&json!({ "$ref": "https://example.com/schema" })).is_err());
+    }
+
+    #[test]
+    fn test_patterns() {
+        let pattern = |pattern: &str| regex_to_gbnf(pattern).unwrap();
+        assert_eq!(pattern(r"^\d{3}-[A-Z]+$"), r#"([0-9]{3} "-" [A-Z]+)"#);
+        assert_eq!(pattern("(?:ab|c)?x*"), r#"(("a" "b" | "c")? "x"*)"#);
+        // Quotes and backslashes are matched in their JSON-escaped form
+        assert_eq!(pattern(r#"a"b"#), r#"("a" "\\\"" "b")"#);
+        assert_eq!(pattern(r"\\\n"), r#"("\\\\" "\\n")"#);
+        // Lazy quantifiers are the same as greedy ones in a grammar
+        assert_eq!(pattern("a+?b*?c??d{1,2}?"), r#"("a"+ "b"* "c"? "d"{1,2})"#);
+        assert_eq!(pattern("[^\"]"), r#"([^\"])"#);
+
+        assert!(regex_to_gbnf("(ab").is_err());
+        assert!(regex_to_gbnf("ab)").is_err());
+        assert!(regex_to_gbnf("[ab").is_err());
+
+        let string = rules(json!({ "type": "string", "pattern": "^[a-z]+$" }));
+        assert_eq!(string["root-pattern"], r#""\"" ([a-z]+) "\"" space"#);
+    }
+
+    #[test]
+    fn test_string_formats_and_lengths() {
+        assert_eq!(rules(json!({ "type": "string", "format": "date" }))["root"], format!(r#""\"" {} "\"" space"#, DATE));
+        assert_eq!(rules(json!({ "type": "string", "maxLength": 5 }))["root"], r#""\"" char{0,5} "\"" space"#);
+        assert_eq!(rules(json!({ "type": "string", "minLength": 2 }))["root"], r#""\"" char{2,} "\"" space"#);
+    }
+
+    #[test]
+    fn test_parse_validates() {
+        let output = JsonOutput::Schema(json!({
+            "type": "object",
+            "properties": { "n": { "type": "integer", "minimum": 0 }, "tags": { "type": "array", "maxItems": 1 } },
+            "required": ["n"],
+            "additionalProperties": false
+        }));
+        assert_eq!(output.parse(" {\"n\": 3} ").unwrap(), json!({ "n": 3 }));
+        assert!(output.parse("{\"n\": -1}").is_err());
+        assert!(output.parse("{\"n\": 1.5}").is_err());
+        assert!(output.parse("{}").is_err());
+        assert!(output.parse("{\"n\": 1, \"x\": 0}").is_err());
+        assert!(output.parse("{\"n\": 1, \"tags\": [1, 2]}").is_err());
+        assert!(output.parse("{\"n\": 1").is_err());
+
+        assert!(JsonOutput::Any.parse("[1]").is_err());
+        assert!(JsonOutput::Any.parse("{\"a\": [1]}").is_ok());
+    }
+}
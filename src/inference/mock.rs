@@ -0,0 +1,206 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::mpsc::Receiver;
+
+use crate::config::Config;
+use crate::inference::backend::{InferenceBackend, LoadOptions};
+use crate::inference::{
+    ChatTurn, EmbeddingRequest, EmbeddingResponse, GenerationRequest, GenerationResponse, RerankRequest,
+    RerankResponse, StreamEvent, TokenLogprob,
+};
+
+/// Id of the BOS token; ids below it are bytes
+pub const MOCK_BOS: i32 = 256;
+/// Size of the mock embeddings
+pub const MOCK_EMBEDDING_DIM: usize = 8;
+const DEFAULT_REPLY: &str = "Hello from the mock backend.";
+
+/// Deterministic backend for tests. Replies come from a script instead of weights and
+/// are generated a word at a time; text is tokenized byte by byte (id = byte value).
+#[derive(Debug, Clone, Default)]
+pub struct MockBackend {
+    /// (prompt substring, reply); the first match wins
+    script: Vec<(String, String)>,
+    digest: String,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer prompts containing `trigger` with `reply`.
+    pub fn reply(mut self, trigger: &str, reply: &str) -> Self {
+        self.script.push((trigger.to_string(), reply.to_string()));
+        self
+    }
+
+    fn reply_for(&self, prompt: &str) -> &str {
+        self.script.iter()
+            .find(|(trigger, _)| prompt.contains(trigger.as_str()))
+            .map(|(_, reply)| reply.as_str())
+            .unwrap_or(DEFAULT_REPLY)
+    }
+
+    /// The scripted reply for `request` as generated pieces, honoring `max_tokens` and
+    /// the stop sequences.
+    fn respond(&self, request: &GenerationRequest) -> Result<(Vec<String>, GenerationResponse)> {
+        let config = &request.config;
+        let mut text = String::new();
+        let mut pieces = Vec::new();
+
+        for word in self.reply_for(&request.prompt).split_inclusive(' ').take(config.max_tokens) {
+            let candidate = format!("{}{}", text, word);
+            let stop_at = config.stop_sequences.iter()
+                .filter(|stop| !stop.is_empty())
+                .filter_map(|stop| candidate.find(stop.as_str()))
+                .min();
+            if let Some(at) = stop_at {
+                if at > text.len() {
+                    pieces.push(candidate[text.len()..at].to_string());
+                }
+                text = candidate[..at].to_string();
+                break;
+            }
+            pieces.push(word.to_string());
+            text = candidate;
+        }
+
+        let mut context = match &request.context {
+            Some(context) => context.clone(),
+            None => Vec::new(),
+        };
+        let prompt_tokens = self.tokenize(&request.prompt, context.is_empty())?;
+        context.extend(&prompt_tokens);
+        context.extend(self.tokenize(&text, false)?);
+
+        let logprobs = match config.logprobs {
+            Some(_) => pieces.iter()
+                .map(|piece| TokenLogprob {
+                    token: piece.clone(),
+                    logprob: 0.0,
+                    top_logprobs: Vec::new(),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let response = GenerationResponse {
+            text,
+            tokens_generated: pieces.len(),
+            prompt_tokens: prompt_tokens.len(),
+            context,
+            logprobs,
+            speculative: None,
+        };
+        Ok((pieces, response))
+    }
+
+    fn embedding(text: &str, normalize: bool) -> Vec<f32> {
+        let mut embedding = vec![0.0f32; MOCK_EMBEDDING_DIM];
+        for byte in text.bytes() {
+            embedding[byte as usize % MOCK_EMBEDDING_DIM] += 1.0;
+        }
+        let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if normalize && norm > 0.0 {
+            for v in &mut embedding {
+                *v /= norm;
+            }
+        }
+        embedding
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for MockBackend {
+    fn load(_config: Arc<Config>, options: &LoadOptions) -> Result<Self> {
+        Ok(Self {
+            script: Vec::new(),
+            digest: options.metadata.digest.clone(),
+        })
+    }
+
+    async fn generate(&self, request: GenerationRequest) -> Result<GenerationResponse> {
+        Ok(self.respond(&request)?.1)
+    }
+
+    async fn generate_stream(&self, request: GenerationRequest) -> Result<Receiver<Result<StreamEvent>>> {
+        let (pieces, response) = self.respond(&request)?;
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+
+        tokio::spawn(async move {
+            for (i, piece) in pieces.into_iter().enumerate() {
+                if let Some(logprob) = response.logprobs.get(i) {
+                    if tx.send(Ok(StreamEvent::Logprobs(vec![logprob.clone()]))).await.is_err() {
+                        return;
+                    }
+                }
+                if tx.send(Ok(StreamEvent::Token(piece))).await.is_err() {
+                    return;
+                }
+            }
+            let _ = tx.send(Ok(StreamEvent::Done(response))).await;
+        });
+        Ok(rx)
+    }
+
+    fn tokenize(&self, text: &str, add_special: bool) -> Result<Vec<i32>> {
+        let bos = add_special.then_some(MOCK_BOS);
+        Ok(bos.into_iter().chain(text.bytes().map(i32::from)).collect())
+    }
+
+    fn detokenize(&self, tokens: &[i32], render_special: bool) -> Result<String> {
+        let mut bytes = Vec::new();
+        for &token in tokens {
+            match token {
+                0..=255 => bytes.push(token as u8),
+                MOCK_BOS if render_special => bytes.extend_from_slice(b"<s>"),
+                MOCK_BOS => {}
+                _ => anyhow::bail!("Token id {} is outside the vocabulary of {} tokens", token, MOCK_BOS + 1),
+            }
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    fn token_piece(&self, token: i32, render_special: bool) -> Result<String> {
+        self.detokenize(&[token], render_special)
+    }
+
+    async fn embed(&self, request: EmbeddingRequest) -> Result<EmbeddingResponse> {
+        Ok(EmbeddingResponse {
+            embeddings: request.inputs.iter().map(|input| Self::embedding(input, request.normalize)).collect(),
+            prompt_tokens: request.inputs.iter().map(|input| input.len()).sum(),
+        })
+    }
+
+    /// Score documents by the share of query words they contain.
+    async fn rerank(&self, request: RerankRequest) -> Result<RerankResponse> {
+        let query: Vec<String> = request.query.split_whitespace().map(str::to_lowercase).collect();
+        let scores = request.documents.iter()
+            .map(|document| {
+                let document = document.to_lowercase();
+                let hits = query.iter().filter(|word| document.contains(word.as_str())).count();
+                hits as f32 / query.len().max(1) as f32
+            })
+            .collect();
+
+        Ok(RerankResponse {
+            scores,
+            prompt_tokens: request.query.len() + request.documents.iter().map(String::len).sum::<usize>(),
+        })
+    }
+
+    fn apply_chat_template(&self, messages: &[ChatTurn]) -> Result<String> {
+        let mut prompt = String::new();
+        for message in messages {
+            prompt.push_str(&format!("<|{}|>{}\n", message.role, message.content));
+        }
+        prompt.push_str("<|assistant|>");
+        Ok(prompt)
+    }
+
+    fn system_fingerprint(&self) -> String {
+        format!("fp_mock_{}", self.digest.chars().take(8).collect::<String>())
+    }
+}
@@ -44,22 +44,71 @@ pub struct GenerationResponse {
     pub context: Vec<i32>,
 }
 =======
+pub mod backend;
 pub mod engine;
+pub mod json_schema;
+pub mod mock;
+pub mod reasoning;
 pub mod tokenizer;
 pub mod sampler;
+pub mod speculative;
+pub mod tools;
+pub mod vision;
 
 use serde::{Deserialize, Serialize};
 use std::env;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GenerationConfig {
     pub temperature: f32,
     pub top_p: f32,
     pub top_k: i32,
+    pub min_p: f32,
+    pub typical_p: f32,
+    /// Tail-free sampling (1.0 = off); only the built-in sampler implements it
+    pub tfs_z: f32,
     pub repeat_penalty: f32,
+    /// Number of recent tokens considered by the penalties (-1 = whole context)
+    pub repeat_last_n: i32,
+    pub presence_penalty: f32,
+    pub frequency_penalty: f32,
+    /// DRY repetition penalty strength (0 = off)
+    pub dry_multiplier: f32,
+    pub dry_base: f32,
+    /// Repeats up to this long go unpenalized by DRY
+    pub dry_allowed_length: usize,
+    /// Tokens scanned by DRY (-1 = whole context)
+    pub dry_penalty_last_n: i32,
+    /// Chance that XTC removes the top choices for a token (0 = off)
+    pub xtc_probability: f32,
+    /// XTC only removes tokens above this probability
+    pub xtc_threshold: f32,
+    /// 0 = disabled, 1 = Mirostat, 2 = Mirostat 2.0
+    pub mirostat: u8,
+    pub mirostat_tau: f32,
+    pub mirostat_eta: f32,
+    pub seed: Option<u32>,
     pub max_tokens: usize,
     pub stop_sequences: Vec<String>,
+    /// GBNF grammar the output must match (start rule `root`)
+    pub grammar: Option<String>,
+    /// Bias added to the logits of tokens; -100 or below bans a token
+    pub logit_bias: Vec<(LogitBiasToken, f32)>,
+    /// Report the log probability of each sampled token plus this many alternatives
+    pub logprobs: Option<usize>,
     pub stream: bool,
+    /// Context window for this request; defaults to RUST_LLM_CONTEXT_SIZE
+    pub num_ctx: Option<usize>,
+    pub num_batch: Option<usize>,
+    pub num_thread: Option<usize>,
+    /// Tokens from the start of the prompt kept when it has to be truncated
+    pub num_keep: Option<usize>,
+    /// Prompt lookup decoding: propose tokens following earlier matches of the last
+    /// n tokens (this n). 0 turns it off; `None` uses the model's setting
+    pub prompt_lookup: Option<usize>,
+    /// Scale for the model's LoRA adapters in place of their configured one (0 = base model)
+    pub adapter_scale: Option<f32>,
 }
 
 impl Default for GenerationConfig {
@@ -71,12 +120,85 @@ impl Default for GenerationConfig {
                 .ok().and_then(|v| v.parse().ok()).unwrap_or(0.95),
             top_k: env::var("RUST_LLM_TOP_K")
                 .ok().and_then(|v| v.parse().ok()).unwrap_or(40),
+            min_p: 0.0,
+            typical_p: 1.0,
+            tfs_z: 1.0,
             repeat_penalty: env::var("RUST_LLM_REPEAT_PENALTY")
                 .ok().and_then(|v| v.parse().ok()).unwrap_or(1.1),
+            repeat_last_n: 64,
+            presence_penalty: 0.0,
+            frequency_penalty: 0.0,
+            dry_multiplier: 0.0,
+            dry_base: 1.75,
+            dry_allowed_length: 2,
+            dry_penalty_last_n: -1,
+            xtc_probability: 0.0,
+            xtc_threshold: 0.1,
+            mirostat: 0,
+            mirostat_tau: 5.0,
+            mirostat_eta: 0.1,
+            seed: None,
             max_tokens: env::var("RUST_LLM_MAX_TOKENS")
                 .ok().and_then(|v| v.parse().ok()).unwrap_or(2048),
             stop_sequences: vec![],
+            grammar: None,
+            logit_bias: vec![],
+            logprobs: None,
             stream: false,
+            num_ctx: None,
+            num_batch: None,
+            num_thread: None,
+            num_keep: None,
+            prompt_lookup: None,
+            adapter_scale: None,
+        }
+    }
+}
+
+/// A seed as clients send it: negative means unseeded, and seeds past `u32::MAX` keep
+/// their low 32 bits.
+pub fn request_seed(seed: i64) -> Option<u32> {
+    (seed >= 0).then_some(seed as u32)
+}
+
+/// Target of a `logit_bias` entry: a token id, or text whose tokens all get the bias.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LogitBiasToken {
+    Id(i32),
+    Text(String),
+}
+
+impl From<&str> for LogitBiasToken {
+    fn from(s: &str) -> Self {
+        s.parse().map(Self::Id).unwrap_or_else(|_| Self::Text(s.to_string()))
+    }
+}
+
+/// A single message of a conversation, rendered through the model's chat template.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatTurn {
+    pub role: String,
+    pub content: String,
+    /// Calls made by an assistant turn
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<tools::ToolCall>,
+    /// Call answered by a `tool` turn
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Images referenced by `vision::IMAGE_MARKER`s in `content`, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "vision::base64_images")]
+    pub images: Vec<Vec<u8>>,
+}
+
+impl ChatTurn {
+    pub fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.to_string(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            images: Vec::new(),
         }
     }
 }
@@ -86,12 +208,110 @@ pub struct GenerationRequest {
     pub prompt: String,
     pub config: GenerationConfig,
     pub context: Option<Vec<i32>>,
+    /// Encoded image files, one per `vision::IMAGE_MARKER` in `prompt`
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "vision::base64_images")]
+    pub images: Vec<Vec<u8>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GenerationResponse {
     pub text: String,
     pub tokens_generated: usize,
+    pub prompt_tokens: usize,
+    /// Full token sequence (prompt + output), accepted back as `GenerationRequest::context`
     pub context: Vec<i32>,
+    /// One entry per generated token when `GenerationConfig::logprobs` is set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub logprobs: Vec<TokenLogprob>,
+    /// Draft tokens proposed and accepted, when speculative decoding was used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speculative: Option<speculative::SpeculativeStats>,
+}
+
+/// Log probability of a sampled token and its most likely alternatives.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f32,
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f32,
+}
+
+/// Item produced by `InferenceEngine::generate_stream`.
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// A decoded piece of text
+    Token(String),
+    /// Log probabilities of the tokens behind the next `Token`, when requested
+    Logprobs(Vec<TokenLogprob>),
+    /// Generation finished; carries the token counts and the full context
+    Done(GenerationResponse),
+}
+
+/// How the token embeddings of an input are pooled into a single vector.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingPooling {
+    #[default]
+    Mean,
+    /// First token (CLS) embedding, used by BERT-style models
+    Cls,
+    /// Last token embedding, used by decoder-based embedding models
+    Last,
+}
+
+impl std::str::FromStr for EmbeddingPooling {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mean" => Ok(Self::Mean),
+            "cls" => Ok(Self::Cls),
+            "last" => Ok(Self::Last),
+            _ => anyhow::bail!("Unknown pooling type: {} (expected mean, cls or last)", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingRequest {
+    pub inputs: Vec<String>,
+    /// Falls back to `Config::embedding_pooling`
+    pub pooling: Option<EmbeddingPooling>,
+    /// Scale each embedding to unit L2 norm
+    pub normalize: bool,
+    /// Cut inputs longer than the context instead of failing
+    pub truncate: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingResponse {
+    pub embeddings: Vec<Vec<f32>>,
+    pub prompt_tokens: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RerankRequest {
+    pub query: String,
+    pub documents: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RerankResponse {
+    /// Relevance of each document to the query (0-1), in input order
+    pub scores: Vec<f32>,
+    pub prompt_tokens: usize,
+}
+
+/// A prompt prefix whose KV state is saved on disk for reuse.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptCacheInfo {
+    pub path: String,
+    pub tokens: usize,
 }
 >>>>>>> bb9577f (20260204_220651)
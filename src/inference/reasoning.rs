@@ -0,0 +1,232 @@
+/// Model families that think in a `<think>...</think>` block before answering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReasoningFormat {
+    /// DeepSeek-R1 and its distills: the template opens the block itself, so the
+    /// output starts inside it and only the closing tag appears
+    DeepSeekR1,
+    /// Qwen3 / QwQ: the model writes both tags
+    Qwen3,
+}
+
+const OPEN: &str = "<think>";
+const CLOSE: &str = "</think>";
+
+impl ReasoningFormat {
+    /// Pick the format from the model's chat template; `None` for models that don't reason.
+    pub fn detect(template: &str) -> Option<Self> {
+        if !template.contains(CLOSE) {
+            None
+        } else if template.contains("<｜Assistant｜>") {
+            Some(ReasoningFormat::DeepSeekR1)
+        } else {
+            Some(ReasoningFormat::Qwen3)
+        }
+    }
+
+    /// Whether generation from `prompt` starts inside the thinking block.
+    pub fn starts_in_thinking(self, prompt: &str) -> bool {
+        prompt.trim_end().ends_with(OPEN)
+    }
+
+    /// Prefill an empty thinking block so the model answers right away, which is what
+    /// the Qwen3 template does for `enable_thinking=false`.
+    pub fn disable_thinking(self, prompt: &mut String) {
+        if self.starts_in_thinking(prompt) {
+            prompt.truncate(prompt.trim_end().len());
+            prompt.push_str("\n\n</think>\n\n");
+        } else {
+            prompt.push_str("<think>\n\n</think>\n\n");
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Nothing but whitespace seen yet; an opening tag may still follow
+    Start,
+    Thinking,
+    /// Skipping whitespace between the closing tag and the answer
+    AfterThinking,
+    Content,
+}
+
+/// Splits streamed output into reasoning and content, holding back text that could
+/// be the start of a tag.
+pub struct ReasoningStream {
+    state: State,
+    buffer: String,
+}
+
+impl ReasoningStream {
+    pub fn new(starts_in_thinking: bool) -> Self {
+        Self {
+            state: if starts_in_thinking { State::Thinking } else { State::Start },
+            buffer: String::new(),
+        }
+    }
+
+    /// Add a piece of output and return the `(reasoning, content)` that can be sent on.
+    pub fn push(&mut self, piece: &str) -> (String, String) {
+        self.buffer.push_str(piece);
+        let mut reasoning = String::new();
+        let mut content = String::new();
+
+        loop {
+            match self.state {
+                State::Start => {
+                    let trimmed = self.buffer.trim_start();
+                    if let Some(rest) = trimmed.strip_prefix(OPEN) {
+                        self.buffer = rest.to_string();
+                        self.state = State::Thinking;
+                    } else if OPEN.starts_with(trimmed) {
+                        break;
+                    } else {
+                        self.state = State::Content;
+                    }
+                }
+                State::Thinking => {
+                    if let Some(pos) = self.buffer.find(CLOSE) {
+                        reasoning.push_str(&self.buffer[..pos]);
+                        self.buffer.drain(..pos + CLOSE.len());
+                        self.state = State::AfterThinking;
+                        continue;
+                    }
+
+                    let held = (1..CLOSE.len()).rev()
+                        .find(|&k| self.buffer.ends_with(&CLOSE[..k]))
+                        .unwrap_or(0);
+                    let ready = self.buffer.len() - held;
+                    reasoning.extend(self.buffer.drain(..ready));
+                    break;
+                }
+                State::AfterThinking => {
+                    let trimmed = self.buffer.trim_start();
+                    if trimmed.is_empty() {
+                        self.buffer.clear();
+                        break;
+                    }
+                    self.buffer = trimmed.to_string();
+                    self.state = State::Content;
+                }
+                State::Content => {
+                    content.push_str(&self.buffer);
+                    self.buffer.clear();
+                    break;
+                }
+            }
+        }
+
+        (reasoning, content)
+    }
+
+    /// Finish the stream, returning the `(reasoning, content)` not yet sent. An
+    /// unterminated thinking block counts as reasoning.
+    pub fn finish(self) -> (String, String) {
+        match self.state {
+            State::Thinking => (self.buffer, String::new()),
+            State::AfterThinking => (String::new(), String::new()),
+            State::Start | State::Content => (String::new(), self.buffer),
+        }
+    }
+}
+
+/// Split a complete reply into `(reasoning, content)`.
+pub fn split_reasoning(text: &str, starts_in_thinking: bool) -> (String, String) {
+    let mut stream = ReasoningStream::new(starts_in_thinking);
+    let (mut reasoning, mut content) = stream.push(text);
+    let (rest_reasoning, rest_content) = stream.finish();
+    reasoning.push_str(&rest_reasoning);
+    content.push_str(&rest_content);
+    (reasoning.trim().to_string(), content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `pieces` through a stream, returning all reasoning and content sent.
+    fn stream(starts_in_thinking: bool, pieces: &[&str]) -> (String, String) {
+        let mut stream = ReasoningStream::new(starts_in_thinking);
+        let mut reasoning = String::new();
+        let mut content = String::new();
+        for piece in pieces {
+            let (r, c) = stream.push(piece);
+            reasoning.push_str(&r);
+            content.push_str(&c);
+        }
+        let (r, c) = stream.finish();
+        reasoning.push_str(&r);
+        content.push_str(&c);
+        (reasoning, content)
+    }
+
+    #[test]
+    fn test_tags_split_across_pieces() {
+        let (reasoning, content) = stream(false, &["\n<th", "ink>Let me", " think.</th", "ink>", "\n\nThe answer", " is 4."]);
+        assert_eq!(reasoning, "Let me think.");
+        assert_eq!(content, "The answer is 4.");
+
+        // One character at a time
+        let text = "<think>a < b</think> yes";
+        let pieces: Vec<String> = text.chars().map(String::from).collect();
+        let pieces: Vec<&str> = pieces.iter().map(String::as_str).collect();
+        assert_eq!(stream(false, &pieces), ("a < b".to_string(), "yes".to_string()));
+    }
+
+    #[test]
+    fn test_partial_close_tag_is_held_back() {
+        let mut stream = ReasoningStream::new(true);
+        assert_eq!(stream.push("step one</thi"), ("step one".to_string(), String::new()));
+        // Not a tag after all
+        assert_eq!(stream.push("s is fine"), ("</this is fine".to_string(), String::new()));
+        assert_eq!(stream.push("</think>ok"), (String::new(), "ok".to_string()));
+    }
+
+    #[test]
+    fn test_missing_close_tag() {
+        // Generation stopped while thinking: everything is reasoning
+        assert_eq!(stream(false, &["<think>still", " going"]), ("still going".to_string(), String::new()));
+        assert_eq!(split_reasoning("<think>\nstill going\n", false), ("still going".to_string(), String::new()));
+        // The held-back start of a tag is returned by `finish`
+        assert_eq!(stream(true, &["hmm</th"]), ("hmm</th".to_string(), String::new()));
+    }
+
+    #[test]
+    fn test_no_thinking_block() {
+        assert_eq!(stream(false, &["Hello", " <think> is a tag"]), (String::new(), "Hello <think> is a tag".to_string()));
+        // A reply that is only the start of a tag
+        assert_eq!(stream(false, &["<thi"]), (String::new(), "<thi".to_string()));
+        assert_eq!(split_reasoning("Just an answer.", false), (String::new(), "Just an answer.".to_string()));
+    }
+
+    #[test]
+    fn test_qwen3_implicit_open() {
+        // Qwen3 thinking templates end the prompt with the opening tag, so only the
+        // closing one shows up in the output
+        let template = "{{- '<|im_start|>assistant\\n<think>\\n' }}{{ reasoning_content }}</think>";
+        let format = ReasoningFormat::detect(template).unwrap();
+        assert_eq!(format, ReasoningFormat::Qwen3);
+
+        let prompt = "<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\n<think>\n";
+        assert!(format.starts_in_thinking(prompt));
+        let (reasoning, content) = stream(format.starts_in_thinking(prompt), &["The user says hi.", "\n</think>\n\n", "Hello!"]);
+        assert_eq!(reasoning, "The user says hi.\n");
+        assert_eq!(content, "Hello!");
+        assert_eq!(split_reasoning("The user says hi.\n</think>\n\nHello!", true), ("The user says hi.".to_string(), "Hello!".to_string()));
+    }
+
+    #[test]
+    fn test_detect_and_disable_thinking() {
+        assert_eq!(ReasoningFormat::detect("<|im_start|>assistant\n"), None);
+        assert_eq!(ReasoningFormat::detect("<｜Assistant｜>{{ content }}</think>"), Some(ReasoningFormat::DeepSeekR1));
+
+        let mut prompt = "<|im_start|>assistant\n".to_string();
+        ReasoningFormat::Qwen3.disable_thinking(&mut prompt);
+        assert_eq!(prompt, "<|im_start|>assistant\n<think>\n\n</think>\n\n");
+        assert!(!ReasoningFormat::Qwen3.starts_in_thinking(&prompt));
+
+        let mut prompt = "<｜Assistant｜><think>\n".to_string();
+        ReasoningFormat::DeepSeekR1.disable_thinking(&mut prompt);
+        assert_eq!(prompt, "<｜Assistant｜><think>\n\n</think>\n\n");
+    }
+}
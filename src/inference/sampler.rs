@@ -1,31 +1,603 @@
-use crate::inference::GenerationConfig;
+use std::collections::HashMap;
 
+use crate::inference::{GenerationConfig, LogitBiasToken};
+
+/// Strings that end a DRY repetition match, as in llama.cpp. Backends tokenize
+/// them for `Sampler::with_sequence_breakers`.
+pub const DRY_SEQUENCE_BREAKERS: [&str; 4] = ["\n", ":", "\"", "*"];
+
+/// Tokens considered by Mirostat 1.0 when estimating the distribution's shape
+const MIROSTAT_M: usize = 100;
+
+/// A token id with its logit and, after `softmax`, its probability.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candidate {
+    pub id: usize,
+    pub logit: f32,
+    pub p: f32,
+}
+
+/// Backend-independent sampler over raw logits.
+///
+/// Chain: logit bias -> penalties -> DRY -> top_k -> tail-free -> typical -> top_p -> min_p ->
+/// XTC -> temperature -> dist, or temperature -> Mirostat when it is enabled.
+/// A temperature of 0 or below picks the most likely token after the penalties.
+/// `grammar` is not applied; constrained generation needs a backend sampler.
 pub struct Sampler {
     config: GenerationConfig,
+    rng: Rng,
+    /// Mirostat's maximum surprise, starting at twice the target
+    mu: f32,
+    /// Token ids that end a DRY repetition match
+    sequence_breakers: Vec<usize>,
 }
 
 impl Sampler {
+    /// Only `LogitBiasToken::Id` entries of `config.logit_bias` are applied, since the
+    /// sampler has no tokenizer; backends resolve text entries to ids first.
     pub fn new(config: GenerationConfig) -> Self {
-        Self { config }
-    }
-    
-    pub fn sample(&self, logits: &[f32]) -> usize {
-        let mut max_idx = 0;
-        let mut max_val = logits[0];
-        
-        for (i, &val) in logits.iter().enumerate() {
-            if val > max_val {
-                max_val = val;
-                max_idx = i;
+        let seed = config.seed.map(u64::from).unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64
+        });
+        let mu = 2.0 * config.mirostat_tau;
+        Self {
+            config,
+            rng: Rng::new(seed),
+            mu,
+            sequence_breakers: Vec::new(),
+        }
+    }
+
+    pub fn with_sequence_breakers(mut self, breakers: Vec<usize>) -> Self {
+        self.sequence_breakers = breakers;
+        self
+    }
+
+    /// Pick the next token from `logits`, given the tokens so far (prompt included).
+    pub fn sample(&mut self, logits: &[f32], history: &[usize]) -> usize {
+        let mut logits = logits.to_vec();
+        apply_logit_bias(&mut logits, &self.config.logit_bias);
+        apply_penalties(&mut logits, history, &self.config);
+        apply_dry(&mut logits, history, &self.config, &self.sequence_breakers);
+
+        if self.config.temperature <= 0.0 {
+            return argmax(&logits);
+        }
+
+        let mut candidates: Vec<Candidate> = logits.iter().enumerate()
+            .map(|(id, &logit)| Candidate { id, logit, p: 0.0 })
+            .collect();
+
+        match self.config.mirostat {
+            1 => {
+                apply_temperature(&mut candidates, self.config.temperature);
+                self.mirostat(&mut candidates, logits.len())
+            }
+            2 => {
+                apply_temperature(&mut candidates, self.config.temperature);
+                self.mirostat_v2(&mut candidates)
             }
+            _ => {
+                top_k(&mut candidates, self.config.top_k);
+                tail_free(&mut candidates, self.config.tfs_z);
+                typical(&mut candidates, self.config.typical_p);
+                top_p(&mut candidates, self.config.top_p);
+                min_p(&mut candidates, self.config.min_p);
+                xtc(&mut candidates, self.config.xtc_probability, self.config.xtc_threshold, &mut self.rng);
+                apply_temperature(&mut candidates, self.config.temperature);
+                dist(&mut candidates, &mut self.rng)
+            }
+        }
+    }
+
+    /// Mirostat 1.0: estimate the Zipf exponent of the distribution and keep the top k
+    /// tokens that bring the expected surprise to `mu`.
+    fn mirostat(&mut self, candidates: &mut Vec<Candidate>, n_vocab: usize) -> usize {
+        softmax(candidates);
+
+        let m = MIROSTAT_M.min(candidates.len());
+        let (mut sum_ti_bi, mut sum_ti_sq) = (0.0f32, 0.0f32);
+        for i in 0..m.saturating_sub(1) {
+            let t_i = ((i + 2) as f32 / (i + 1) as f32).ln();
+            let b_i = (candidates[i].p / candidates[i + 1].p).ln();
+            sum_ti_bi += t_i * b_i;
+            sum_ti_sq += t_i * t_i;
+        }
+        let s_hat = sum_ti_bi / sum_ti_sq;
+
+        let epsilon_hat = s_hat - 1.0;
+        let k = ((epsilon_hat * 2f32.powf(self.mu)) / (1.0 - (n_vocab as f32).powf(-epsilon_hat)))
+            .powf(1.0 / s_hat);
+        let k = if k.is_finite() { (k.round() as i32).max(1) } else { 1 };
+
+        top_k(candidates, k);
+        let token = dist(candidates, &mut self.rng);
+        self.update_mu(candidates, token);
+        token
+    }
+
+    /// Mirostat 2.0: drop tokens more surprising than `mu`, then sample.
+    fn mirostat_v2(&mut self, candidates: &mut Vec<Candidate>) -> usize {
+        softmax(candidates);
+        let keep = candidates.iter().take_while(|c| -c.p.log2() <= self.mu).count().max(1);
+        candidates.truncate(keep);
+
+        let token = dist(candidates, &mut self.rng);
+        self.update_mu(candidates, token);
+        token
+    }
+
+    /// Move `mu` toward the target surprise after sampling `token`.
+    fn update_mu(&mut self, candidates: &[Candidate], token: usize) {
+        let p = candidates.iter().find(|c| c.id == token).map(|c| c.p).unwrap_or(1.0);
+        let surprise = -p.log2();
+        self.mu -= self.config.mirostat_eta * (surprise - self.config.mirostat_tau);
+    }
+}
+
+/// Sort candidates by logit, most likely first, and fill in their probabilities.
+pub fn softmax(candidates: &mut [Candidate]) {
+    candidates.sort_by(|a, b| b.logit.total_cmp(&a.logit));
+    let Some(max) = candidates.first().map(|c| c.logit) else {
+        return;
+    };
+
+    let mut sum = 0.0;
+    for c in candidates.iter_mut() {
+        c.p = (c.logit - max).exp();
+        sum += c.p;
+    }
+    for c in candidates.iter_mut() {
+        c.p /= sum;
+    }
+}
+
+fn argmax(logits: &[f32]) -> usize {
+    logits.iter().enumerate()
+        .fold((0, f32::NEG_INFINITY), |best, (id, &l)| if l > best.1 { (id, l) } else { best })
+        .0
+}
+
+/// The `repeat_last_n` most recent tokens; -1 means all of them.
+fn last_n(history: &[usize], n: i32) -> &[usize] {
+    match usize::try_from(n) {
+        Ok(n) => &history[history.len().saturating_sub(n)..],
+        Err(_) => history,
+    }
+}
+
+/// Add each id entry's bias to its logit; -100 or below bans the token. Text entries
+/// are skipped.
+pub fn apply_logit_bias(logits: &mut [f32], entries: &[(LogitBiasToken, f32)]) {
+    for (target, bias) in entries {
+        let LogitBiasToken::Id(id) = target else {
+            continue;
+        };
+        let Some(logit) = usize::try_from(*id).ok().and_then(|id| logits.get_mut(id)) else {
+            continue;
+        };
+        if *bias <= -100.0 {
+            *logit = f32::NEG_INFINITY;
+        } else {
+            *logit += bias;
+        }
+    }
+}
+
+/// Repetition penalty (llama.cpp style: divide positive logits, multiply negative ones),
+/// then frequency and presence penalties over the last `repeat_last_n` tokens.
+pub fn apply_penalties(logits: &mut [f32], history: &[usize], config: &GenerationConfig) {
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    for &token in last_n(history, config.repeat_last_n) {
+        *counts.entry(token).or_default() += 1;
+    }
+
+    for (token, count) in counts {
+        let Some(logit) = logits.get_mut(token) else {
+            continue;
+        };
+        if *logit > 0.0 {
+            *logit /= config.repeat_penalty;
+        } else {
+            *logit *= config.repeat_penalty;
+        }
+        *logit -= count as f32 * config.frequency_penalty + config.presence_penalty;
+    }
+}
+
+/// DRY ("Don't Repeat Yourself"): penalize tokens that would extend a repetition of
+/// an earlier sequence, exponentially in the length of the repeat beyond
+/// `dry_allowed_length`. Sequence breakers stop a match.
+pub fn apply_dry(logits: &mut [f32], history: &[usize], config: &GenerationConfig, breakers: &[usize]) {
+    if config.dry_multiplier <= 0.0 {
+        return;
+    }
+    let window = last_n(history, config.dry_penalty_last_n);
+    let len = window.len();
+
+    // Longest repeat each token would continue
+    let mut longest: HashMap<usize, usize> = HashMap::new();
+    for end in 0..len.saturating_sub(1) {
+        let next = window[end];
+        if breakers.contains(&next) {
+            continue;
+        }
+        let mut n = 0;
+        while n < end
+            && window[end - 1 - n] == window[len - 1 - n]
+            && !breakers.contains(&window[len - 1 - n])
+        {
+            n += 1;
+        }
+        if n >= config.dry_allowed_length {
+            let entry = longest.entry(next).or_default();
+            *entry = (*entry).max(n);
+        }
+    }
+
+    for (token, n) in longest {
+        if let Some(logit) = logits.get_mut(token) {
+            let excess = (n - config.dry_allowed_length) as f32;
+            *logit -= config.dry_multiplier * config.dry_base.powf(excess);
+        }
+    }
+}
+
+pub fn apply_temperature(candidates: &mut [Candidate], temperature: f32) {
+    for c in candidates.iter_mut() {
+        c.logit /= temperature;
+    }
+}
+
+/// Keep the `k` most likely tokens; 0 or less keeps all of them.
+pub fn top_k(candidates: &mut Vec<Candidate>, k: i32) {
+    if k <= 0 {
+        return;
+    }
+    candidates.sort_by(|a, b| b.logit.total_cmp(&a.logit));
+    candidates.truncate(k as usize);
+}
+
+/// Tail-free sampling: cut where the second derivative of the sorted probabilities
+/// has accumulated `z` of its total. 1.0 disables it.
+pub fn tail_free(candidates: &mut Vec<Candidate>, z: f32) {
+    if z >= 1.0 || candidates.len() <= 2 {
+        return;
+    }
+    softmax(candidates);
+
+    let first: Vec<f32> = candidates.windows(2).map(|w| w[0].p - w[1].p).collect();
+    let second: Vec<f32> = first.windows(2).map(|w| (w[0] - w[1]).abs()).collect();
+    let total: f32 = second.iter().sum();
+    if total <= 0.0 {
+        return;
+    }
+
+    let mut cumulative = 0.0;
+    let mut keep = candidates.len();
+    for (i, d) in second.iter().enumerate() {
+        cumulative += d / total;
+        if cumulative > z {
+            keep = i + 1;
+            break;
+        }
+    }
+    candidates.truncate(keep);
+}
+
+/// Locally typical sampling: keep the tokens whose surprise is closest to the
+/// distribution's entropy, up to cumulative probability `p`. 1.0 disables it.
+pub fn typical(candidates: &mut Vec<Candidate>, p: f32) {
+    if p >= 1.0 || candidates.is_empty() {
+        return;
+    }
+    softmax(candidates);
+
+    let entropy: f32 = candidates.iter()
+        .filter(|c| c.p > 0.0)
+        .map(|c| -c.p * c.p.ln())
+        .sum();
+    let distance = |c: &Candidate| (-c.p.ln() - entropy).abs();
+    candidates.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+
+    let mut cumulative = 0.0;
+    let mut keep = candidates.len();
+    for (i, c) in candidates.iter().enumerate() {
+        cumulative += c.p;
+        if cumulative >= p {
+            keep = i + 1;
+            break;
         }
-        
-        max_idx
     }
-    
-    pub fn apply_temperature(&self, logits: &mut [f32]) {
-        for logit in logits.iter_mut() {
-            *logit /= self.config.temperature;
+    candidates.truncate(keep);
+    softmax(candidates);
+}
+
+/// Nucleus sampling: keep the most likely tokens up to cumulative probability `p`.
+pub fn top_p(candidates: &mut Vec<Candidate>, p: f32) {
+    if p >= 1.0 || candidates.is_empty() {
+        return;
+    }
+    softmax(candidates);
+
+    let mut cumulative = 0.0;
+    let mut keep = candidates.len();
+    for (i, c) in candidates.iter().enumerate() {
+        cumulative += c.p;
+        if cumulative >= p {
+            keep = i + 1;
+            break;
         }
     }
+    candidates.truncate(keep);
+}
+
+/// Drop tokens less likely than `p` times the most likely one.
+pub fn min_p(candidates: &mut Vec<Candidate>, p: f32) {
+    if p <= 0.0 || candidates.is_empty() {
+        return;
+    }
+    softmax(candidates);
+
+    let threshold = candidates[0].p * p;
+    candidates.retain(|c| c.p >= threshold);
+}
+
+/// XTC ("Exclude Top Choices"): with the given probability, remove every token above
+/// `threshold` except the least likely of them, steering away from the obvious choice.
+pub fn xtc(candidates: &mut Vec<Candidate>, probability: f32, threshold: f32, rng: &mut Rng) {
+    if probability <= 0.0 || threshold > 0.5 || candidates.len() < 2 {
+        return;
+    }
+    if rng.next_f32() >= probability {
+        return;
+    }
+    softmax(candidates);
+
+    let above = candidates.iter().take_while(|c| c.p >= threshold).count();
+    if above >= 2 {
+        candidates.drain(..above - 1);
+    }
+}
+
+/// Sample a token from the candidates' probabilities.
+pub fn dist(candidates: &mut [Candidate], rng: &mut Rng) -> usize {
+    softmax(candidates);
+
+    let r = rng.next_f32();
+    let mut cumulative = 0.0;
+    for c in candidates.iter() {
+        cumulative += c.p;
+        if r < cumulative {
+            return c.id;
+        }
+    }
+    candidates.last().map(|c| c.id).unwrap_or(0)
+}
+
+/// Small seeded PRNG (SplitMix64), so a seed gives the same tokens on every platform.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> GenerationConfig {
+        GenerationConfig {
+            temperature: 1.0,
+            top_p: 1.0,
+            top_k: 0,
+            min_p: 0.0,
+            typical_p: 1.0,
+            tfs_z: 1.0,
+            repeat_penalty: 1.0,
+            repeat_last_n: 64,
+            presence_penalty: 0.0,
+            frequency_penalty: 0.0,
+            mirostat: 0,
+            seed: Some(42),
+            ..Default::default()
+        }
+    }
+
+    fn candidates(logits: &[f32]) -> Vec<Candidate> {
+        logits.iter().enumerate().map(|(id, &logit)| Candidate { id, logit, p: 0.0 }).collect()
+    }
+
+    fn ids(candidates: &[Candidate]) -> Vec<usize> {
+        candidates.iter().map(|c| c.id).collect()
+    }
+
+    #[test]
+    fn softmax_sorts_and_normalizes() {
+        let mut c = candidates(&[1.0, 3.0, 2.0]);
+        softmax(&mut c);
+        assert_eq!(ids(&c), vec![1, 2, 0]);
+        let sum: f32 = c.iter().map(|c| c.p).sum();
+        assert!((sum - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn zero_temperature_is_greedy() {
+        let mut sampler = Sampler::new(GenerationConfig { temperature: 0.0, ..config() });
+        for _ in 0..10 {
+            assert_eq!(sampler.sample(&[0.1, 2.0, 1.9, -1.0], &[]), 1);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_tokens() {
+        let logits = [1.0, 1.1, 0.9, 1.05, 0.95];
+        let run = || {
+            let mut sampler = Sampler::new(config());
+            (0..32).map(|_| sampler.sample(&logits, &[])).collect::<Vec<_>>()
+        };
+        assert_eq!(run(), run());
+
+        let mut other = Sampler::new(GenerationConfig { seed: Some(7), ..config() });
+        let other: Vec<_> = (0..32).map(|_| other.sample(&logits, &[])).collect();
+        assert_ne!(run(), other);
+    }
+
+    #[test]
+    fn top_k_keeps_k_most_likely() {
+        let mut c = candidates(&[0.0, 5.0, 3.0, 4.0]);
+        top_k(&mut c, 2);
+        assert_eq!(ids(&c), vec![1, 3]);
+    }
+
+    #[test]
+    fn top_p_keeps_nucleus() {
+        // p = 0.5, 0.25, 0.125, 0.125
+        let l2 = std::f32::consts::LN_2;
+        let mut c = candidates(&[3.0 * l2, 2.0 * l2, l2, l2]);
+        top_p(&mut c, 0.7);
+        assert_eq!(ids(&c), vec![0, 1]);
+    }
+
+    #[test]
+    fn min_p_drops_unlikely_tokens() {
+        let l2 = std::f32::consts::LN_2;
+        let mut c = candidates(&[3.0 * l2, 2.0 * l2, l2, 0.0]);
+        min_p(&mut c, 0.3);
+        assert_eq!(ids(&c), vec![0, 1]);
+    }
+
+    #[test]
+    fn typical_prefers_tokens_near_entropy() {
+        let mut c = candidates(&[10.0, 2.0, 2.0, 2.0, 2.0]);
+        typical(&mut c, 0.5);
+        assert_eq!(ids(&c), vec![0]);
+    }
+
+    #[test]
+    fn tail_free_cuts_flat_tail() {
+        let mut c = candidates(&[5.0, 4.0, 0.0, 0.0, 0.0, 0.0]);
+        tail_free(&mut c, 0.5);
+        assert!(c.len() < 6 && !c.is_empty());
+        assert_eq!(c[0].id, 0);
+    }
+
+    #[test]
+    fn logit_bias_applies_before_sampling() {
+        let config = GenerationConfig {
+            temperature: 0.0,
+            logit_bias: vec![
+                (LogitBiasToken::Id(1), -100.0),
+                (LogitBiasToken::Id(2), 0.5),
+                (LogitBiasToken::Text("skipped".to_string()), 10.0),
+                (LogitBiasToken::Id(99), 10.0),
+            ],
+            ..config()
+        };
+        let mut sampler = Sampler::new(config);
+        assert_eq!(sampler.sample(&[0.1, 2.0, 1.9, -1.0], &[]), 2);
+    }
+
+    #[test]
+    fn penalties_lower_repeated_tokens() {
+        let config = GenerationConfig {
+            repeat_penalty: 2.0,
+            frequency_penalty: 0.5,
+            presence_penalty: 0.25,
+            ..config()
+        };
+        let mut logits = vec![2.0, -2.0, 2.0];
+        apply_penalties(&mut logits, &[0, 0, 1], &config);
+        assert_eq!(logits, vec![2.0 / 2.0 - 1.0 - 0.25, -4.0 - 0.5 - 0.25, 2.0]);
+    }
+
+    #[test]
+    fn repeat_last_n_limits_window() {
+        let config = GenerationConfig { repeat_penalty: 2.0, repeat_last_n: 1, ..config() };
+        let mut logits = vec![2.0, 2.0];
+        apply_penalties(&mut logits, &[0, 1], &config);
+        assert_eq!(logits, vec![2.0, 1.0]);
+    }
+
+    #[test]
+    fn dry_penalizes_continuing_a_repeat() {
+        let config = GenerationConfig {
+            dry_multiplier: 1.0,
+            dry_base: 2.0,
+            dry_allowed_length: 2,
+            ..config()
+        };
+        // "1 2 3 4 ... 1 2 3" -> 4 would repeat a sequence of length 3
+        let history = [1, 2, 3, 4, 5, 1, 2, 3];
+        let mut logits = vec![0.0; 6];
+        apply_dry(&mut logits, &history, &config, &[]);
+        assert_eq!(logits[4], -2.0);
+        assert_eq!(logits[5], 0.0);
+
+        // A breaker inside the repeat stops the match
+        let mut logits = vec![0.0; 6];
+        apply_dry(&mut logits, &history, &config, &[2]);
+        assert_eq!(logits[4], 0.0);
+    }
+
+    #[test]
+    fn xtc_removes_top_choices() {
+        let mut c = candidates(&[3.0, 2.9, 2.8, -5.0]);
+        xtc(&mut c, 1.0, 0.1, &mut Rng::new(1));
+        assert_eq!(ids(&c), vec![2, 3]);
+
+        let mut c = candidates(&[3.0, 2.9, 2.8, -5.0]);
+        xtc(&mut c, 0.0, 0.1, &mut Rng::new(1));
+        assert_eq!(c.len(), 4);
+    }
+
+    #[test]
+    fn mirostat_v2_tracks_target_surprise() {
+        let mut sampler = Sampler::new(GenerationConfig {
+            mirostat: 2,
+            mirostat_tau: 3.0,
+            mirostat_eta: 0.1,
+            ..config()
+        });
+        let logits: Vec<f32> = (0..50).map(|i| -(i as f32) * 0.2).collect();
+        for _ in 0..200 {
+            sampler.sample(&logits, &[]);
+        }
+        assert!(sampler.mu > 0.0 && sampler.mu < 6.0);
+    }
+
+    #[test]
+    fn mirostat_v1_samples_valid_tokens() {
+        let mut sampler = Sampler::new(GenerationConfig { mirostat: 1, ..config() });
+        let logits: Vec<f32> = (0..200).map(|i| -(i as f32).sqrt()).collect();
+        for _ in 0..50 {
+            assert!(sampler.sample(&logits, &[]) < logits.len());
+        }
+    }
+
+    #[test]
+    fn rng_is_uniform_enough() {
+        let mut rng = Rng::new(123);
+        let mean: f32 = (0..10_000).map(|_| rng.next_f32()).sum::<f32>() / 10_000.0;
+        assert!((mean - 0.5).abs() < 0.02);
+    }
 }
@@ -0,0 +1,223 @@
+use anyhow::Result;
+use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::context::LlamaContext;
+use llama_cpp_2::llama_backend::LlamaBackend;
+use llama_cpp_2::llama_batch::LlamaBatch;
+use llama_cpp_2::model::params::LlamaModelParams;
+use llama_cpp_2::model::LlamaModel;
+use llama_cpp_2::token::LlamaToken;
+use serde::{Deserialize, Serialize};
+
+use crate::inference::tokenizer::token_text;
+
+/// Vocabularies may differ by this many tokens (usually unused padding at the end)
+const VOCAB_MAX_SIZE_DIFFERENCE: i32 = 128;
+/// Drafting stops once the draft model is less sure than this of its next token
+const DRAFT_MIN_PROB: f32 = 0.75;
+/// Most tokens copied per prompt lookup
+const LOOKUP_MAX_TOKENS: usize = 10;
+/// Shortest n-gram prompt lookup falls back to before giving up
+const LOOKUP_MIN_NGRAM: usize = 2;
+
+/// How many proposed tokens were checked against the target model and how many it kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpeculativeStats {
+    pub drafted: usize,
+    pub accepted: usize,
+}
+
+impl SpeculativeStats {
+    pub fn acceptance_rate(&self) -> f32 {
+        if self.drafted == 0 {
+            0.0
+        } else {
+            self.accepted as f32 / self.drafted as f32
+        }
+    }
+}
+
+/// A small model sharing the target's vocabulary, used to draft tokens.
+pub struct DraftModel {
+    pub model: LlamaModel,
+    /// Most tokens drafted per verification step
+    pub max_tokens: usize,
+}
+
+impl DraftModel {
+    /// Load a draft model for `target`. Fails when the vocabularies don't match, since
+    /// draft tokens are verified by id.
+    pub fn load(
+        backend: &LlamaBackend,
+        target: &LlamaModel,
+        path: &str,
+        gpu_layers: u32,
+        max_tokens: usize,
+    ) -> Result<Self> {
+        let params = LlamaModelParams::default().with_n_gpu_layers(gpu_layers);
+        let model = LlamaModel::load_from_file(backend, path, &params)?;
+        vocab_compatible(target, &model)?;
+        Ok(Self { model, max_tokens })
+    }
+}
+
+/// Check that two models tokenize the same way, like llama.cpp's speculative example:
+/// same special tokens, nearly the same size and the same text for every shared id.
+fn vocab_compatible(target: &LlamaModel, draft: &LlamaModel) -> Result<()> {
+    if target.token_bos() != draft.token_bos() || target.token_eos() != draft.token_eos() {
+        anyhow::bail!("draft model has different BOS/EOS tokens");
+    }
+
+    let (n_target, n_draft) = (target.n_vocab(), draft.n_vocab());
+    if (n_target - n_draft).abs() > VOCAB_MAX_SIZE_DIFFERENCE {
+        anyhow::bail!("vocabulary sizes differ too much ({} vs {})", n_target, n_draft);
+    }
+
+    for id in 0..n_target.min(n_draft) {
+        let token = LlamaToken::new(id);
+        if token_text(target, token).ok() != token_text(draft, token).ok() {
+            anyhow::bail!("token {} differs between the models", id);
+        }
+    }
+    Ok(())
+}
+
+/// Where draft tokens for speculative decoding come from.
+pub enum Proposer<'a> {
+    Draft(Drafter<'a>),
+    PromptLookup(PromptLookup),
+}
+
+impl Proposer<'_> {
+    /// Propose up to `max` tokens to follow `tokens`.
+    pub fn propose(&mut self, tokens: &[LlamaToken], max: usize) -> Result<Vec<LlamaToken>> {
+        match self {
+            Proposer::Draft(drafter) => drafter.draft(tokens, max),
+            Proposer::PromptLookup(lookup) => Ok(lookup.propose(tokens, max)),
+        }
+    }
+}
+
+/// Draft-free speculation: find the latest earlier occurrence of the last n tokens
+/// and propose what followed it. Pays off when the output copies from the prompt,
+/// as in summaries and code edits.
+pub struct PromptLookup {
+    ngram: usize,
+}
+
+impl PromptLookup {
+    pub fn new(ngram: usize) -> Self {
+        Self { ngram }
+    }
+
+    /// Try the configured n-gram first, then shorter ones down to `LOOKUP_MIN_NGRAM`.
+    pub fn propose(&self, tokens: &[LlamaToken], max: usize) -> Vec<LlamaToken> {
+        let max = max.min(LOOKUP_MAX_TOKENS);
+        if max == 0 {
+            return Vec::new();
+        }
+
+        for n in (LOOKUP_MIN_NGRAM.min(self.ngram)..=self.ngram).rev() {
+            if tokens.len() <= n {
+                continue;
+            }
+            let key = &tokens[tokens.len() - n..];
+            // Latest match first; it is most likely to continue the same way
+            let found = (0..tokens.len() - n).rev().find(|&start| &tokens[start..start + n] == key);
+            if let Some(start) = found {
+                let from = start + n;
+                let to = (from + max).min(tokens.len());
+                return tokens[from..to].to_vec();
+            }
+        }
+        Vec::new()
+    }
+}
+
+/// Drafts continuations with a `DraftModel`, keeping its KV cache in step with the
+/// target's tokens so only new tokens are evaluated on each call.
+pub struct Drafter<'a> {
+    model: &'a LlamaModel,
+    ctx: LlamaContext<'a>,
+    batch: LlamaBatch<'a>,
+    batch_size: usize,
+    max_tokens: usize,
+    /// Tokens whose KV entries are in `ctx`
+    tokens: Vec<LlamaToken>,
+}
+
+impl<'a> Drafter<'a> {
+    pub fn new(
+        draft: &'a DraftModel,
+        backend: &LlamaBackend,
+        params: LlamaContextParams,
+        batch_size: usize,
+    ) -> Result<Self> {
+        Ok(Self {
+            model: &draft.model,
+            ctx: draft.model.new_context(backend, params)?,
+            batch: LlamaBatch::new(batch_size, 1),
+            batch_size,
+            max_tokens: draft.max_tokens,
+            tokens: Vec::new(),
+        })
+    }
+
+    /// Greedily draft up to `max` tokens following `tokens`, stopping early when the
+    /// draft model becomes unsure.
+    pub fn draft(&mut self, tokens: &[LlamaToken], max: usize) -> Result<Vec<LlamaToken>> {
+        let max = max.min(self.max_tokens);
+        if max == 0 || tokens.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Drop whatever the target rejected last time; re-evaluate at least the last
+        // token so there are logits to draft from
+        let common = self.tokens.iter().zip(tokens).take_while(|(a, b)| a == b).count().min(tokens.len() - 1);
+        if common < self.tokens.len() {
+            self.ctx.clear_kv_cache_seq(Some(0), Some(common as u32), None)?;
+            self.tokens.truncate(common);
+        }
+
+        let new = &tokens[common..];
+        for (chunk_idx, chunk) in new.chunks(self.batch_size).enumerate() {
+            self.batch.clear();
+            for (i, token) in chunk.iter().enumerate() {
+                let idx = chunk_idx * self.batch_size + i;
+                self.batch.add(*token, (common + idx) as i32, &[0], idx == new.len() - 1)?;
+            }
+            self.ctx.decode(&mut self.batch)?;
+        }
+        self.tokens.extend_from_slice(new);
+
+        let mut draft = Vec::with_capacity(max);
+        let mut idx = self.batch.n_tokens() - 1;
+        while draft.len() < max {
+            let (token, prob) = Self::best_token(&self.ctx, idx);
+            if prob < DRAFT_MIN_PROB {
+                break;
+            }
+            draft.push(token);
+
+            if draft.len() == max || self.model.is_eog_token(token) {
+                break;
+            }
+
+            self.batch.clear();
+            self.batch.add(token, self.tokens.len() as i32, &[0], true)?;
+            self.ctx.decode(&mut self.batch)?;
+            self.tokens.push(token);
+            idx = 0;
+        }
+
+        Ok(draft)
+    }
+
+    /// Most likely token at batch index `idx` and its probability
+    fn best_token(ctx: &LlamaContext, idx: i32) -> (LlamaToken, f32) {
+        let logits = ctx.get_logits_ith(idx);
+        let (best, max) = logits.iter().copied().enumerate()
+            .fold((0, f32::NEG_INFINITY), |acc, (id, l)| if l > acc.1 { (id, l) } else { acc });
+        let sum: f32 = logits.iter().map(|l| (l - max).exp()).sum();
+        (LlamaToken::new(best as i32), 1.0 / sum)
+    }
+}
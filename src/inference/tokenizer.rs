@@ -1,30 +1,63 @@
 use anyhow::Result;
+use llama_cpp_2::model::{AddBos, LlamaModel};
+use llama_cpp_2::token::LlamaToken;
+use llama_cpp_2::TokenToStringError;
+use std::sync::Arc;
 
+/// Converts between text and token ids with a loaded model's vocabulary.
 pub struct Tokenizer {
-    vocab_size: usize,
+    model: Arc<LlamaModel>,
 }
 
 impl Tokenizer {
-    pub fn new(vocab_size: usize) -> Self {
-        Self { vocab_size }
+    pub fn new(model: Arc<LlamaModel>) -> Self {
+        Self { model }
     }
     
-    pub fn encode(&self, text: &str) -> Result<Vec<i32>> {
-        let tokens: Vec<i32> = text
-            .chars()
-            .enumerate()
-            .map(|(i, _)| (i % self.vocab_size) as i32)
-            .collect();
-        Ok(tokens)
+    pub fn vocab_size(&self) -> usize {
+        self.model.n_vocab() as usize
     }
     
-    pub fn decode(&self, tokens: &[i32]) -> Result<String> {
-        Ok(format!("Decoded {} tokens", tokens.len()))
+    /// Tokenize `text`, adding BOS when `add_special` is set. Special tokens written
+    /// out in the text (e.g. `<|im_start|>`) map to their ids.
+    pub fn encode(&self, text: &str, add_special: bool) -> Result<Vec<i32>> {
+        let add_bos = if add_special { AddBos::Always } else { AddBos::Never };
+        Ok(self.model.str_to_token(text, add_bos)?.into_iter().map(|t| t.0).collect())
+    }
+    
+    /// Join the pieces of `tokens`. Special tokens are left out unless `render_special`.
+    pub fn decode(&self, tokens: &[i32], render_special: bool) -> Result<String> {
+        let mut bytes = Vec::new();
+        for &token in tokens {
+            bytes.extend(self.piece_bytes(token, render_special)?);
+        }
+        // Pieces may split multi-byte characters, so only the whole is valid UTF-8
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+    
+    /// Text of a single token; partial UTF-8 sequences are replaced with U+FFFD.
+    pub fn piece(&self, token: i32, render_special: bool) -> Result<String> {
+        Ok(String::from_utf8_lossy(&self.piece_bytes(token, render_special)?).into_owned())
+    }
+    
+    fn piece_bytes(&self, token: i32, render_special: bool) -> Result<Vec<u8>> {
+        if token < 0 || token as usize >= self.vocab_size() {
+            anyhow::bail!("Token id {} is outside the vocabulary of {} tokens", token, self.vocab_size());
+        }
+        let token = LlamaToken::new(token);
+        match self.model.token_to_piece_bytes(token, 8, render_special, None) {
+            // A negative size is the buffer the piece needs
+            Err(TokenToStringError::InsufficientBufferSpace(size)) => {
+                Ok(self.model.token_to_piece_bytes(token, size.unsigned_abs() as usize, render_special, None)?)
+            }
+            piece => Ok(piece?),
+        }
     }
 }
 
-impl Default for Tokenizer {
-    fn default() -> Self {
-        Self::new(32000)
-    }
+/// Text of `token` with special tokens rendered, as generation streams it. A piece
+/// holding only part of a multi-byte character decodes to the empty string.
+pub fn token_text(model: &LlamaModel, token: LlamaToken) -> Result<String> {
+    let mut decoder = encoding_rs::UTF_8.new_decoder();
+    Ok(model.token_to_piece(token, &mut decoder, true, None)?)
 }
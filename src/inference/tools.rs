@@ -0,0 +1,659 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::inference::ChatTurn;
+
+/// A function the model may call, in the OpenAI/Ollama `tools` shape.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tool {
+    #[serde(rename = "type", default = "default_tool_type")]
+    pub tool_type: String,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Value>,
+}
+
+fn default_tool_type() -> String {
+    "function".to_string()
+}
+
+/// A function call made by the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+impl ToolCall {
+    pub fn new(name: &str, arguments: Value) -> Self {
+        Self {
+            id: format!("call_{}", Uuid::new_v4().simple()),
+            name: name.to_string(),
+            arguments,
+        }
+    }
+}
+
+/// How a model family expects tools to be described and emits calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolFormat {
+    /// Hermes / Qwen: `<tool_call>{"name": ..., "arguments": ...}</tool_call>`
+    Hermes,
+    /// Llama 3.x: a bare `{"name": ..., "parameters": ...}` JSON object
+    Llama3,
+    /// Mistral: `[TOOL_CALLS] [{"name": ..., "arguments": ...}]`
+    Mistral,
+}
+
+impl ToolFormat {
+    /// Pick the format from the model's chat template, defaulting to Hermes which
+    /// most instruction-tuned models follow reasonably well.
+    pub fn detect(template: &str) -> Self {
+        if template.contains("<tool_call>") {
+            ToolFormat::Hermes
+        } else if template.contains("[TOOL_CALLS]") || template.contains("[AVAILABLE_TOOLS]") {
+            ToolFormat::Mistral
+        } else if template.contains("<|start_header_id|>") {
+            ToolFormat::Llama3
+        } else {
+            ToolFormat::Hermes
+        }
+    }
+
+    /// Text that opens a tool call in the model output.
+    fn markers(self) -> &'static [&'static str] {
+        match self {
+            ToolFormat::Hermes => &["<tool_call>"],
+            ToolFormat::Llama3 => &["<|python_tag|>"],
+            ToolFormat::Mistral => &["[TOOL_CALLS]"],
+        }
+    }
+}
+
+/// Rewrite a conversation so that tool definitions, assistant tool calls and tool
+/// results become plain messages in the layout the model family was trained on.
+pub fn render_messages(
+    format: ToolFormat,
+    messages: &[ChatTurn],
+    tools: &[Tool],
+    require_tool: bool,
+) -> Vec<ChatTurn> {
+    let mut turns: Vec<ChatTurn> = Vec::with_capacity(messages.len() + 1);
+
+    for message in messages {
+        let turn = match message.role.as_str() {
+            "assistant" if !message.tool_calls.is_empty() => {
+                ChatTurn::new("assistant", &render_calls(format, message))
+            }
+            "tool" => match format {
+                ToolFormat::Hermes => {
+                    let response = format!("<tool_response>\n{}\n</tool_response>", message.content);
+                    // Consecutive results share one user turn, as in the Qwen template
+                    if let Some(last) = turns.last_mut() {
+                        if last.role == "user" && last.content.ends_with("</tool_response>") {
+                            last.content.push('\n');
+                            last.content.push_str(&response);
+                            continue;
+                        }
+                    }
+                    ChatTurn::new("user", &response)
+                }
+                ToolFormat::Llama3 => ChatTurn::new("ipython", &message.content),
+                ToolFormat::Mistral => {
+                    let result = json!({
+                        "call_id": message.tool_call_id.as_deref().unwrap_or_default(),
+                        "content": message.content,
+                    });
+                    ChatTurn::new("user", &format!("[TOOL_RESULTS] {} [/TOOL_RESULTS]", result))
+                }
+            },
+            _ => ChatTurn::new(&message.role, &message.content),
+        };
+        turns.push(turn);
+    }
+
+    if tools.is_empty() {
+        return turns;
+    }
+
+    match format {
+        ToolFormat::Hermes | ToolFormat::Llama3 => {
+            let instructions = tool_instructions(format, tools, require_tool);
+            match turns.first_mut() {
+                Some(first) if first.role == "system" => {
+                    first.content = format!("{}\n\n{}", first.content, instructions);
+                }
+                _ => turns.insert(0, ChatTurn::new("system", &instructions)),
+            }
+        }
+        ToolFormat::Mistral => {
+            let instructions = tool_instructions(format, tools, require_tool);
+            // Tools go before the last message of the user, not before a tool result
+            let last_user = turns.iter_mut().rev()
+                .find(|t| t.role == "user" && !t.content.starts_with("[TOOL_RESULTS]"));
+            match last_user {
+                Some(last_user) => {
+                    last_user.content = format!("{}{}", instructions, last_user.content);
+                }
+                None => turns.insert(0, ChatTurn::new("user", &instructions)),
+            }
+        }
+    }
+
+    turns
+}
+
+fn tool_instructions(format: ToolFormat, tools: &[Tool], require_tool: bool) -> String {
+    let definitions: Vec<String> = tools.iter()
+        .map(|t| serde_json::to_string(t).unwrap_or_default())
+        .collect();
+    let requirement = if require_tool {
+        "\nYou must call at least one of these functions."
+    } else {
+        ""
+    };
+
+    match format {
+        ToolFormat::Hermes => format!(
+            "# Tools\n\nYou may call one or more functions to assist with the user query.\n\n\
+             You are provided with function signatures within <tools></tools> XML tags:\n\
+             <tools>\n{}\n</tools>\n\n\
+             For each function call, return a json object with function name and arguments \
+             within <tool_call></tool_call> XML tags:\n\
+             <tool_call>\n{{\"name\": <function-name>, \"arguments\": <args-json-object>}}\n</tool_call>{}",
+            definitions.join("\n"),
+            requirement,
+        ),
+        ToolFormat::Llama3 => format!(
+            "You have access to the following functions. To call a function, respond with JSON \
+             for a function call in the format {{\"name\": function name, \"parameters\": \
+             dictionary of argument name and its value}}. Do not use variables.\n\n{}{}",
+            definitions.join("\n\n"),
+            requirement,
+        ),
+        ToolFormat::Mistral => format!(
+            "[AVAILABLE_TOOLS] [{}] [/AVAILABLE_TOOLS]{}",
+            definitions.join(", "),
+            requirement,
+        ),
+    }
+}
+
+fn render_calls(format: ToolFormat, message: &ChatTurn) -> String {
+    match format {
+        ToolFormat::Hermes => {
+            let mut content = message.content.clone();
+            for call in &message.tool_calls {
+                let body = json!({ "name": call.name, "arguments": call.arguments });
+                content.push_str(&format!("\n<tool_call>\n{}\n</tool_call>", body));
+            }
+            content.trim_start().to_string()
+        }
+        ToolFormat::Llama3 => message.tool_calls.iter()
+            .map(|call| json!({ "name": call.name, "parameters": call.arguments }).to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        ToolFormat::Mistral => {
+            let calls: Vec<Value> = message.tool_calls.iter()
+                .map(|call| json!({ "name": call.name, "arguments": call.arguments, "id": call.id }))
+                .collect();
+            format!("[TOOL_CALLS] {}", Value::Array(calls))
+        }
+    }
+}
+
+/// Split model output into plain content and the tool calls it contains.
+/// Output that does not parse as a tool call is returned unchanged as content.
+pub fn parse_tool_calls(format: ToolFormat, text: &str) -> (String, Vec<ToolCall>) {
+    let calls = match format {
+        ToolFormat::Hermes => return parse_hermes(text),
+        ToolFormat::Llama3 => {
+            let body = text.trim_start();
+            let body = body.strip_prefix("<|python_tag|>").unwrap_or(body);
+            json_values(body).iter().filter_map(call_from_value).collect::<Vec<_>>()
+        }
+        ToolFormat::Mistral => match text.find("[TOOL_CALLS]") {
+            Some(start) => {
+                let content = text[..start].trim().to_string();
+                let calls: Vec<ToolCall> = json_values(&text[start + "[TOOL_CALLS]".len()..])
+                    .into_iter()
+                    .flat_map(|value| match value {
+                        Value::Array(items) => items,
+                        other => vec![other],
+                    })
+                    .filter_map(|value| call_from_value(&value))
+                    .collect();
+                if !calls.is_empty() {
+                    return (content, calls);
+                }
+                Vec::new()
+            }
+            None => Vec::new(),
+        },
+    };
+
+    if calls.is_empty() {
+        (text.to_string(), calls)
+    } else {
+        (String::new(), calls)
+    }
+}
+
+fn parse_hermes(text: &str) -> (String, Vec<ToolCall>) {
+    let mut content = String::new();
+    let mut calls = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("<tool_call>") {
+        let body_start = start + "<tool_call>".len();
+        let (body, next) = match rest[body_start..].find("</tool_call>") {
+            Some(end) => (&rest[body_start..body_start + end], &rest[body_start + end + "</tool_call>".len()..]),
+            // Generation may stop before the closing tag
+            None => (&rest[body_start..], ""),
+        };
+
+        match json_values(body).first().and_then(call_from_value) {
+            Some(call) => {
+                content.push_str(&rest[..start]);
+                calls.push(call);
+            }
+            None => content.push_str(&rest[..rest.len() - next.len()]),
+        }
+        rest = next;
+    }
+    content.push_str(rest);
+
+    (content.trim().to_string(), calls)
+}
+
+/// All JSON values at the start of `text`, separated by whitespace or `;`.
+fn json_values(text: &str) -> Vec<Value> {
+    let mut values = Vec::new();
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        let mut stream = serde_json::Deserializer::from_str(rest).into_iter::<Value>();
+        match stream.next() {
+            Some(Ok(value)) => {
+                values.push(value);
+                rest = rest[stream.byte_offset()..].trim_start().trim_start_matches(';').trim_start();
+            }
+            _ => break,
+        }
+    }
+
+    values
+}
+
+fn call_from_value(value: &Value) -> Option<ToolCall> {
+    let name = value.get("name")?.as_str()?;
+    let arguments = value.get("arguments")
+        .or_else(|| value.get("parameters"))
+        .cloned()
+        .unwrap_or_else(|| json!({}));
+    // Some models emit the arguments as a JSON-encoded string
+    let arguments = match arguments {
+        Value::String(s) => serde_json::from_str(&s).unwrap_or(Value::String(s)),
+        other => other,
+    };
+    Some(ToolCall::new(name, arguments))
+}
+
+/// Incremental splitter for streamed output: passes plain content through, holds
+/// back everything from the first tool-call marker on and parses each call in it as
+/// soon as it is complete.
+pub struct ToolCallStream {
+    format: ToolFormat,
+    buffer: String,
+    emitted: usize,
+    in_call: bool,
+    /// End of the held-back output already parsed into `calls`
+    parsed: usize,
+    /// Plain text found between the calls
+    content: String,
+    calls: Vec<ToolCall>,
+    /// Number of calls handed out by `take_calls`
+    taken: usize,
+}
+
+impl ToolCallStream {
+    pub fn new(format: ToolFormat) -> Self {
+        Self {
+            format,
+            buffer: String::new(),
+            emitted: 0,
+            in_call: false,
+            parsed: 0,
+            content: String::new(),
+            calls: Vec::new(),
+            taken: 0,
+        }
+    }
+
+    /// Add a piece of output and return the content that can be sent on already.
+    pub fn push(&mut self, piece: &str) -> Option<String> {
+        self.buffer.push_str(piece);
+        if self.in_call {
+            self.parse_calls();
+            return None;
+        }
+
+        let pending = &self.buffer[self.emitted..];
+
+        // Llama 3 calls are bare JSON, recognisable only at the start of the reply
+        if self.format == ToolFormat::Llama3 && self.emitted == 0 {
+            let trimmed = pending.trim_start();
+            if trimmed.is_empty() {
+                return None;
+            }
+            if trimmed.starts_with('{') {
+                self.in_call = true;
+                self.parse_calls();
+                return None;
+            }
+        }
+
+        let markers = self.format.markers();
+        if let Some(pos) = markers.iter().filter_map(|m| pending.find(m)).min() {
+            self.in_call = true;
+            self.parsed = self.emitted + pos;
+            let content = self.take(self.emitted + pos);
+            self.parse_calls();
+            return content;
+        }
+
+        // Hold back a suffix that could be the beginning of a marker
+        let held = markers.iter()
+            .filter_map(|marker| {
+                (1..marker.len()).rev()
+                    .filter(|&k| marker.is_char_boundary(k))
+                    .find(|&k| pending.ends_with(&marker[..k]))
+            })
+            .max()
+            .unwrap_or(0);
+        self.take(self.buffer.len() - held)
+    }
+
+    /// Calls completed since the last call, so they can be streamed as they finish.
+    pub fn take_calls(&mut self) -> Vec<ToolCall> {
+        let calls = self.calls[self.taken..].to_vec();
+        self.taken = self.calls.len();
+        calls
+    }
+
+    /// Finish the stream, returning content not yet sent and all tool calls, including
+    /// those already handed out by `take_calls`.
+    pub fn finish(mut self) -> (String, Vec<ToolCall>) {
+        if !self.in_call {
+            return (self.buffer[self.emitted..].to_string(), Vec::new());
+        }
+
+        // Whatever is left is an unterminated call or text that isn't one
+        let (content, calls) = parse_tool_calls(self.format, &self.buffer[self.parsed..]);
+        self.content.push_str(&content);
+        self.calls.extend(calls);
+        if self.calls.is_empty() {
+            return (self.buffer[self.emitted..].to_string(), Vec::new());
+        }
+        (self.content.trim().to_string(), self.calls)
+    }
+
+    /// Parse the calls completed in the held-back output. Anything that doesn't parse
+    /// as a call is left for `finish`.
+    fn parse_calls(&mut self) {
+        loop {
+            let rest = &self.buffer[self.parsed..];
+            let (end, calls) = match self.format {
+                ToolFormat::Hermes => {
+                    let Some(end) = rest.find("</tool_call>") else {
+                        return;
+                    };
+                    let end = end + "</tool_call>".len();
+                    let (content, calls) = parse_hermes(&rest[..end]);
+                    if calls.is_empty() {
+                        return;
+                    }
+                    self.content.push_str(&content);
+                    (end, calls)
+                }
+                ToolFormat::Llama3 | ToolFormat::Mistral => {
+                    let body = rest.trim_start().trim_start_matches(';').trim_start();
+                    let body = self.format.markers().iter()
+                        .find_map(|marker| body.strip_prefix(marker))
+                        .unwrap_or(body);
+                    let mut stream = serde_json::Deserializer::from_str(body).into_iter::<Value>();
+                    let Some(Ok(value)) = stream.next() else {
+                        return;
+                    };
+                    let calls: Vec<ToolCall> = match value {
+                        Value::Array(items) => items.iter().filter_map(call_from_value).collect(),
+                        other => call_from_value(&other).into_iter().collect(),
+                    };
+                    if calls.is_empty() {
+                        return;
+                    }
+                    (rest.len() - body.len() + stream.byte_offset(), calls)
+                }
+            };
+            self.calls.extend(calls);
+            self.parsed += end;
+        }
+    }
+
+    fn take(&mut self, end: usize) -> Option<String> {
+        if end <= self.emitted {
+            return None;
+        }
+        let text = self.buffer[self.emitted..end].to_string();
+        self.emitted = end;
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(name: &str) -> Tool {
+        Tool {
+            tool_type: default_tool_type(),
+            function: FunctionDefinition {
+                name: name.to_string(),
+                description: None,
+                parameters: Some(json!({ "type": "object" })),
+            },
+        }
+    }
+
+    fn stream(format: ToolFormat, pieces: &[&str]) -> (String, Vec<Vec<ToolCall>>, String, Vec<ToolCall>) {
+        let mut parser = ToolCallStream::new(format);
+        let mut content = String::new();
+        let mut taken = Vec::new();
+        for piece in pieces {
+            content.push_str(&parser.push(piece).unwrap_or_default());
+            taken.push(parser.take_calls());
+        }
+        let (rest, calls) = parser.finish();
+        (content, taken, rest, calls)
+    }
+
+    #[test]
+    fn test_parse_hermes() {
+        let text = "Checking.\n<tool_call>\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}\n</tool_call>\n\
+                    <tool_call>{\"name\": \"get_time\", \"arguments\": \"{\\\"zone\\\": \\\"CET\\\"}\"}</tool_call>";
+        let (content, calls) = parse_tool_calls(ToolFormat::Hermes, text);
+        assert_eq!(content, "Checking.");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].name, "get_weather");
+        assert_eq!(calls[0].arguments, json!({ "city": "Paris" }));
+        // String-encoded arguments are decoded
+        assert_eq!(calls[1].arguments, json!({ "zone": "CET" }));
+        assert_ne!(calls[0].id, calls[1].id);
+    }
+
+    #[test]
+    fn test_parse_hermes_unterminated() {
+        let (content, calls) = parse_tool_calls(ToolFormat::Hermes, "<tool_call>{\"name\": \"f\", \"arguments\": {}}");
+        assert_eq!(content, "");
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].name, "f");
+    }
+
+    #[test]
+    fn test_parse_hermes_malformed_json() {
+        let text = "<tool_call>{\"name\": \"f\", \"arguments\": {</tool_call> done";
+        let (content, calls) = parse_tool_calls(ToolFormat::Hermes, text);
+        assert!(calls.is_empty());
+        assert_eq!(content, text);
+    }
+
+    #[test]
+    fn test_parse_llama3() {
+        let text = "<|python_tag|>{\"name\": \"search\", \"parameters\": {\"q\": \"rust\"}}; {\"name\": \"open\", \"parameters\": {}}";
+        let (content, calls) = parse_tool_calls(ToolFormat::Llama3, text);
+        assert_eq!(content, "");
+        assert_eq!(calls.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), ["search", "open"]);
+        assert_eq!(calls[0].arguments, json!({ "q": "rust" }));
+
+        // JSON that isn't a call, and plain text, stay content
+        let (content, calls) = parse_tool_calls(ToolFormat::Llama3, "{\"answer\": 42}");
+        assert!(calls.is_empty());
+        assert_eq!(content, "{\"answer\": 42}");
+        let (content, calls) = parse_tool_calls(ToolFormat::Llama3, "{\"name\": \"search\", ");
+        assert!(calls.is_empty());
+        assert_eq!(content, "{\"name\": \"search\", ");
+    }
+
+    #[test]
+    fn test_parse_mistral() {
+        let text = "Sure. [TOOL_CALLS] [{\"name\": \"add\", \"arguments\": {\"a\": 1, \"b\": 2}}, {\"name\": \"neg\", \"arguments\": {\"a\": 3}}]";
+        let (content, calls) = parse_tool_calls(ToolFormat::Mistral, text);
+        assert_eq!(content, "Sure.");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1].arguments, json!({ "a": 3 }));
+
+        let text = "[TOOL_CALLS] [{\"name\": \"add\", \"arguments\": ";
+        let (content, calls) = parse_tool_calls(ToolFormat::Mistral, text);
+        assert!(calls.is_empty());
+        assert_eq!(content, text);
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(ToolFormat::detect("{% if tools %}<tool_call>{% endif %}"), ToolFormat::Hermes);
+        assert_eq!(ToolFormat::detect("[AVAILABLE_TOOLS]"), ToolFormat::Mistral);
+        assert_eq!(ToolFormat::detect("<|start_header_id|>user<|end_header_id|>"), ToolFormat::Llama3);
+        assert_eq!(ToolFormat::detect("<|im_start|>"), ToolFormat::Hermes);
+    }
+
+    #[test]
+    fn test_render_messages_hermes() {
+        let mut call = ChatTurn::new("assistant", "");
+        call.tool_calls = vec![ToolCall::new("get_weather", json!({ "city": "Paris" }))];
+        let messages = vec![
+            ChatTurn::new("system", "Be brief."),
+            ChatTurn::new("user", "Weather?"),
+            call,
+            ChatTurn::new("tool", "sunny"),
+            ChatTurn::new("tool", "20C"),
+        ];
+
+        let turns = render_messages(ToolFormat::Hermes, &messages, &[tool("get_weather")], true);
+        let roles: Vec<_> = turns.iter().map(|t| t.role.as_str()).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "user"]);
+        assert!(turns[0].content.starts_with("Be brief.\n\n# Tools"));
+        assert!(turns[0].content.contains("\"name\":\"get_weather\""));
+        assert!(turns[0].content.ends_with("You must call at least one of these functions."));
+        assert_eq!(
+            turns[2].content,
+            "<tool_call>\n{\"arguments\":{\"city\":\"Paris\"},\"name\":\"get_weather\"}\n</tool_call>"
+        );
+        // Consecutive results share a turn
+        assert_eq!(
+            turns[3].content,
+            "<tool_response>\nsunny\n</tool_response>\n<tool_response>\n20C\n</tool_response>"
+        );
+    }
+
+    #[test]
+    fn test_render_messages_llama3_and_mistral() {
+        let mut result = ChatTurn::new("tool", "42");
+        result.tool_call_id = Some("call_1".to_string());
+        let messages = vec![ChatTurn::new("user", "Add."), result];
+
+        let turns = render_messages(ToolFormat::Llama3, &messages, &[tool("add")], false);
+        assert_eq!(turns[0].role, "system");
+        assert!(!turns[0].content.contains("You must call"));
+        assert_eq!(turns[2].role, "ipython");
+
+        let turns = render_messages(ToolFormat::Mistral, &messages, &[tool("add")], false);
+        assert_eq!(turns.len(), 2);
+        assert!(turns[0].content.starts_with("[AVAILABLE_TOOLS] [{"));
+        assert!(turns[0].content.ends_with("[/AVAILABLE_TOOLS]Add."));
+        assert_eq!(turns[1].content, "[TOOL_RESULTS] {\"call_id\":\"call_1\",\"content\":\"42\"} [/TOOL_RESULTS]");
+
+        // Without tools the messages pass through
+        let turns = render_messages(ToolFormat::Hermes, &[ChatTurn::new("user", "Hi")], &[], false);
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].content, "Hi");
+    }
+
+    #[test]
+    fn test_stream_emits_each_call_when_complete() {
+        let (content, taken, rest, calls) = stream(ToolFormat::Hermes, &[
+            "Let me ch", "eck.<tool", "_call>{\"name\": \"a\", ", "\"arguments\": {}}</tool_call>",
+            "\n<tool_call>{\"name\": \"b\", \"arguments\": {}}", "</tool_call>",
+        ]);
+        assert_eq!(content, "Let me check.");
+        assert_eq!(taken.iter().map(Vec::len).collect::<Vec<_>>(), [0, 0, 0, 1, 0, 1]);
+        assert_eq!(taken[3][0].name, "a");
+        assert_eq!(taken[5][0].name, "b");
+        assert_eq!(rest, "");
+        // `finish` returns every call, with the ids already handed out
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, taken[3][0].id);
+    }
+
+    #[test]
+    fn test_stream_llama3_and_mistral() {
+        let (content, taken, rest, calls) = stream(ToolFormat::Llama3, &[
+            " {\"name\": \"a\", \"parameters\": ", "{}}", "; {\"name\": \"b\", \"parameters\": {}}",
+        ]);
+        assert_eq!(content, "");
+        assert_eq!(taken.iter().map(Vec::len).collect::<Vec<_>>(), [0, 1, 1]);
+        assert_eq!(rest, "");
+        assert_eq!(calls.len(), 2);
+
+        let (content, taken, _, calls) = stream(ToolFormat::Mistral, &[
+            "On it. [TOOL_CA", "LLS] [{\"name\": \"a\", \"arguments\": {}}", "]",
+        ]);
+        assert_eq!(content, "On it. ");
+        assert_eq!(taken.iter().map(Vec::len).collect::<Vec<_>>(), [0, 0, 1]);
+        assert_eq!(calls.len(), 1);
+    }
+
+    #[test]
+    fn test_stream_malformed_call_falls_back_to_content() {
+        let (content, taken, rest, calls) = stream(ToolFormat::Hermes, &[
+            "Hi <tool_call>{\"name\": ", "oops}</tool_call>",
+        ]);
+        assert_eq!(content, "Hi ");
+        assert!(taken.iter().all(Vec::is_empty));
+        assert!(calls.is_empty());
+        assert_eq!(rest, "<tool_call>{\"name\": oops}</tool_call>");
+
+        // JSON content from a Llama 3 model is not a call
+        let (_, _, rest, calls) = stream(ToolFormat::Llama3, &["{\"answer\": ", "42}"]);
+        assert!(calls.is_empty());
+        assert_eq!(rest, "{\"answer\": 42}");
+    }
+}
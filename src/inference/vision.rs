@@ -0,0 +1,43 @@
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+
+/// Placeholder for an image in a prompt; the projector replaces each one with the
+/// embeddings of the next image. Matches the llama.cpp multimodal default.
+pub const IMAGE_MARKER: &str = "<__media__>";
+
+/// Decode an image sent as a base64 `data:` URL. Other URLs, including `file://` ones
+/// and plain paths, are rejected: API clients must not be able to read server files.
+pub fn decode_image_url(url: &str) -> Result<Vec<u8>> {
+    let data = url.strip_prefix("data:")
+        .ok_or_else(|| anyhow!("Only base64 data URLs are supported for images"))?;
+    let (header, payload) = data.split_once(',')
+        .ok_or_else(|| anyhow!("Malformed data URL"))?;
+    if !header.ends_with(";base64") {
+        anyhow::bail!("Only base64 data URLs are supported for images");
+    }
+    decode_base64_image(payload)
+}
+
+pub fn decode_base64_image(data: &str) -> Result<Vec<u8>> {
+    STANDARD.decode(data.trim())
+        .map_err(|e| anyhow!("Invalid base64 image: {}", e))
+}
+
+/// Serde helper storing images as base64 strings rather than arrays of numbers.
+pub mod base64_images {
+    use super::STANDARD;
+    use base64::Engine as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(images: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(images.iter().map(|image| STANDARD.encode(image)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|image| STANDARD.decode(image).map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
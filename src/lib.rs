@@ -0,0 +1,8 @@
+pub mod api;
+pub mod config;
+pub mod inference;
+pub mod models;
+pub mod download;
+pub mod hardware;
+pub mod context;
+pub mod cli;
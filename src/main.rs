@@ -86,17 +86,9 @@ async fn main() -> Result<()> {
     Ok(())
 }
 =======
-mod api;
-mod config;
-mod inference;
-mod models;
-mod download;
-mod hardware;
-mod context;
-mod cli;
-
 use anyhow::Result;
 use clap::{Parser, Subcommand};
+use rust_llm_runner::{api, cli, config, inference};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Parser)]
@@ -114,15 +106,49 @@ enum Commands {
         host: Option<String>,
         #[arg(short, long)]
         port: Option<u16>,
+        /// Comma-separated models to load before accepting requests
+        #[arg(long, value_delimiter = ',')]
+        preload: Vec<String>,
     },
     Pull {
         model: String,
+        /// Vision projector (mmproj) to use, as a local path or URL; found automatically for HuggingFace repos
+        #[arg(long)]
+        mmproj: Option<String>,
+        /// Pulled model (name:tag) that drafts tokens for speculative decoding
+        #[arg(long)]
+        draft: Option<String>,
+        /// Use prompt lookup (n-gram) speculative decoding with this n-gram size by default
+        #[arg(long)]
+        prompt_lookup: Option<usize>,
+    },
+    /// Create a model from a Modelfile (FROM a pulled model, plus ADAPTER lines)
+    Create {
+        model: String,
+        #[arg(short, long, default_value = "Modelfile")]
+        file: std::path::PathBuf,
+    },
+    /// Register a GGUF LoRA adapter as a fine-tune of a pulled model
+    Import {
+        model: String,
+        /// LoRA adapter GGUF
+        #[arg(long)]
+        adapter: std::path::PathBuf,
+        /// Pulled base model (name:tag) the adapter was trained on
+        #[arg(long)]
+        base: String,
+        /// Adapter strength (1.0 = as trained)
+        #[arg(long, default_value_t = 1.0)]
+        scale: f32,
     },
     List,
     Run {
         model: String,
         #[arg(short, long)]
         prompt: Option<String>,
+        /// Persist the conversation under this session id (resumes it if it exists)
+        #[arg(short, long)]
+        session: Option<String>,
     },
     Rm {
         model: String,
@@ -131,6 +157,39 @@ enum Commands {
         model: String,
     },
     Ps,
+    Stop {
+        model: String,
+    },
+    /// Embed each line of stdin and print one JSON array per line
+    Embed {
+        model: String,
+        /// Pooling type: mean, cls or last
+        #[arg(long)]
+        pooling: Option<inference::EmbeddingPooling>,
+        /// Skip L2 normalization
+        #[arg(long)]
+        raw: bool,
+    },
+    /// Rank the lines of stdin by relevance to a query with a reranker model
+    Rerank {
+        model: String,
+        #[arg(short, long)]
+        query: String,
+        /// Print only the best N documents
+        #[arg(long)]
+        top_n: Option<usize>,
+    },
+    /// Tokenize text (or stdin) with a model's vocabulary and print the token ids
+    Tokenize {
+        model: String,
+        text: Option<String>,
+        /// Print each token's text next to its id
+        #[arg(long)]
+        pieces: bool,
+        /// Don't add the BOS token
+        #[arg(long)]
+        no_special: bool,
+    },
 }
 
 #[tokio::main]
@@ -152,21 +211,27 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Serve { host, port } => {
+        Commands::Serve { host, port, preload } => {
             // Use CLI args if provided, otherwise fall back to .env/config
             let host = host.unwrap_or(config.server_host);
             let port = port.unwrap_or(config.server_port);
             tracing::info!("Starting server on {}:{}", host, port);
-            api::server::start_server(&host, port).await?;
+            api::server::start_server(&host, port, &preload).await?;
+        }
+        Commands::Pull { model, mmproj, draft, prompt_lookup } => {
+            cli::commands::pull_model(&model, mmproj.as_deref(), draft, prompt_lookup).await?;
         }
-        Commands::Pull { model } => {
-            cli::commands::pull_model(&model).await?;
+        Commands::Create { model, file } => {
+            cli::commands::create_model(&model, &file).await?;
+        }
+        Commands::Import { model, adapter, base, scale } => {
+            cli::commands::import_adapter(&model, &adapter, &base, scale).await?;
         }
         Commands::List => {
             cli::commands::list_models().await?;
         }
-        Commands::Run { model, prompt } => {
-            cli::commands::run_model(&model, prompt).await?;
+        Commands::Run { model, prompt, session } => {
+            cli::commands::run_model(&model, prompt, session).await?;
         }
         Commands::Rm { model } => {
             cli::commands::remove_model(&model).await?;
@@ -177,6 +242,18 @@ async fn main() -> Result<()> {
         Commands::Ps => {
             cli::commands::list_running().await?;
         }
+        Commands::Stop { model } => {
+            cli::commands::stop_model(&model).await?;
+        }
+        Commands::Embed { model, pooling, raw } => {
+            cli::commands::embed_lines(&model, pooling, !raw).await?;
+        }
+        Commands::Rerank { model, query, top_n } => {
+            cli::commands::rerank_lines(&model, &query, top_n).await?;
+        }
+        Commands::Tokenize { model, text, pieces, no_special } => {
+            cli::commands::tokenize(&model, text, pieces, !no_special).await?;
+        }
     }
 
     Ok(())
@@ -4,40 +4,106 @@ use tokio::sync::RwLock;
 use std::collections::HashMap;
 use crate::models::metadata::{ModelMetadata, MetadataStore};
 use crate::config::Config;
+use crate::context::ContextManager;
+use crate::inference::backend::{InferenceBackend, LoadOptions};
 use crate::inference::engine::InferenceEngine;
 
+/// Creates the backend for a model that is not loaded yet.
+pub type BackendLoader = dyn Fn(Arc<Config>, &LoadOptions) -> Result<Arc<dyn InferenceBackend>> + Send + Sync;
+
 pub struct ModelManager {
     config: Arc<Config>,
     metadata_store: Arc<MetadataStore>,
-    loaded_models: Arc<RwLock<HashMap<String, Arc<InferenceEngine>>>>,
+    context_manager: Arc<ContextManager>,
+    loaded_models: Arc<RwLock<HashMap<String, Arc<dyn InferenceBackend>>>>,
+    loader: Arc<BackendLoader>,
 }
 
 impl ModelManager {
+    /// Manager running models with llama.cpp.
     pub fn new(config: Arc<Config>) -> Result<Self> {
+        Self::with_backend::<InferenceEngine>(config)
+    }
+    
+    /// Manager loading models with backend `B`.
+    pub fn with_backend<B: InferenceBackend + 'static>(config: Arc<Config>) -> Result<Self> {
+        Self::with_loader(config, |config, options| {
+            Ok(Arc::new(B::load(config, options)?) as Arc<dyn InferenceBackend>)
+        })
+    }
+    
+    /// Manager loading models with `loader`, e.g. one handing out a scripted mock.
+    pub fn with_loader(
+        config: Arc<Config>,
+        loader: impl Fn(Arc<Config>, &LoadOptions) -> Result<Arc<dyn InferenceBackend>> + Send + Sync + 'static,
+    ) -> Result<Self> {
         let metadata_store = Arc::new(MetadataStore::new(&config.db_path)?);
+        let context_manager = Arc::new(ContextManager::new(
+            metadata_store.db(),
+            config.default_context_size,
+        )?);
         let loaded_models = Arc::new(RwLock::new(HashMap::new()));
         
         Ok(Self {
             config,
             metadata_store,
+            context_manager,
             loaded_models,
+            loader: Arc::new(loader),
         })
     }
     
-    pub async fn load_model(&self, name: &str, tag: &str) -> Result<Arc<InferenceEngine>> {
+    pub async fn load_model(&self, name: &str, tag: &str) -> Result<Arc<dyn InferenceBackend>> {
+        self.load_model_with_gpu(name, tag, None).await
+    }
+    
+    /// Load a model with an explicit number of GPU layers. An already loaded
+    /// instance is reused unless it was loaded with a different layer count.
+    pub async fn load_model_with_gpu(
+        &self,
+        name: &str,
+        tag: &str,
+        num_gpu: Option<u32>,
+    ) -> Result<Arc<dyn InferenceBackend>> {
         let key = format!("{}:{}", name, tag);
         
         {
             let models = self.loaded_models.read().await;
             if let Some(engine) = models.get(&key) {
-                return Ok(Arc::clone(engine));
+                if num_gpu.is_none_or(|layers| layers == engine.gpu_layers()) {
+                    return Ok(Arc::clone(engine));
+                }
             }
         }
         
         let metadata = self.metadata_store.get_model(name, tag)?
             .ok_or_else(|| anyhow::anyhow!("Model not found: {}", key))?;
         
-        let engine = Arc::new(InferenceEngine::new(&metadata.path, self.config.clone())?);
+        // Drop the previous instance first so both never hold GPU memory at once
+        self.loaded_models.write().await.remove(&key);
+        
+        let engine = match &metadata.parent_model {
+            // Fine-tunes share the loaded base model's weights and add their adapters
+            Some(parent) if !metadata.adapters.is_empty() => {
+                let parts: Vec<&str> = parent.split(':').collect();
+                let parent_tag = parts.get(1).unwrap_or(&"latest");
+                let base = Box::pin(self.load_model_with_gpu(parts[0], parent_tag, num_gpu)).await?;
+                base.load_fine_tune(&LoadOptions {
+                    metadata: &metadata,
+                    num_gpu,
+                    draft_path: None,
+                })?
+            }
+            _ => {
+                let draft_path = self.draft_model_path(&key, &metadata)?;
+                (self.loader)(self.config.clone(), &LoadOptions {
+                    metadata: &metadata,
+                    num_gpu,
+                    draft_path,
+                })?
+            }
+        };
+        engine.warm_system_prompts().await;
         
         {
             let mut models = self.loaded_models.write().await;
@@ -54,6 +120,26 @@ impl ModelManager {
         Ok(engine)
     }
     
+    /// File of the draft model configured for `key`, if any. A draft model that isn't
+    /// pulled only disables speculative decoding.
+    fn draft_model_path(&self, key: &str, metadata: &ModelMetadata) -> Result<Option<String>> {
+        let Some(draft) = metadata.draft_model.as_ref().or_else(|| self.config.draft_models.get(key)) else {
+            return Ok(None);
+        };
+        
+        let parts: Vec<&str> = draft.split(':').collect();
+        let name = parts[0].replace(['/', '\\'], "_");
+        let tag = parts.get(1).unwrap_or(&"latest");
+        
+        match self.metadata_store.get_model(&name, tag)? {
+            Some(draft_metadata) => Ok(Some(draft_metadata.path)),
+            None => {
+                tracing::warn!("Draft model {} for {} is not pulled, speculative decoding disabled", draft, key);
+                Ok(None)
+            }
+        }
+    }
+    
     pub async fn unload_model(&self, name: &str, tag: &str) -> Result<()> {
         let key = format!("{}:{}", name, tag);
         let mut models = self.loaded_models.write().await;
@@ -66,6 +152,10 @@ impl ModelManager {
         models.keys().cloned().collect()
     }
     
+    pub fn sessions(&self) -> Arc<ContextManager> {
+        Arc::clone(&self.context_manager)
+    }
+    
     pub fn save_metadata(&self, metadata: &ModelMetadata) -> Result<()> {
         self.metadata_store.save_model(metadata)
     }
@@ -16,6 +16,27 @@ pub struct ModelMetadata {
     pub created_at: DateTime<Utc>,
     pub modified_at: DateTime<Utc>,
     pub path: String,
+    /// Vision projector (mmproj) GGUF of a multimodal model
+    #[serde(default)]
+    pub projector: Option<String>,
+    /// Model (`name:tag`) that drafts tokens for speculative decoding; overrides `Config::draft_models`
+    #[serde(default)]
+    pub draft_model: Option<String>,
+    /// N-gram size for prompt lookup decoding by default
+    #[serde(default)]
+    pub prompt_lookup: Option<usize>,
+    /// Base model (`name:tag`) of a fine-tune; `path` then points at the base weights
+    #[serde(default)]
+    pub parent_model: Option<String>,
+    /// LoRA adapters applied to the base weights
+    #[serde(default)]
+    pub adapters: Vec<LoraAdapter>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoraAdapter {
+    pub path: String,
+    pub scale: f32,
 }
 
 pub struct MetadataStore {
@@ -56,6 +77,11 @@ impl MetadataStore {
         Ok(models)
     }
     
+    /// Underlying database, shared with other stores such as the session store
+    pub fn db(&self) -> &sled::Db {
+        &self.db
+    }
+    
     pub fn delete_model(&self, name: &str, tag: &str) -> Result<()> {
         let key = format!("{}:{}", name, tag);
         self.db.remove(key.as_bytes())?;
@@ -1,6 +1,7 @@
 pub mod registry;
 pub mod metadata;
 pub mod manager;
+pub mod modelfile;
 
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
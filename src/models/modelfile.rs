@@ -0,0 +1,106 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+use crate::models::metadata::ModelMetadata;
+
+/// The parts of an Ollama Modelfile this runner understands: a base model, LoRA
+/// adapters, and the parameters that apply to them.
+#[derive(Debug, Clone, Default)]
+pub struct Modelfile {
+    /// Base model (`name:tag`)
+    pub from: String,
+    /// Adapter GGUF paths, relative ones resolved against the Modelfile's directory
+    pub adapters: Vec<String>,
+    /// `PARAMETER adapter_scale`
+    pub adapter_scale: Option<f32>,
+    /// `PARAMETER prompt_lookup`
+    pub prompt_lookup: Option<usize>,
+}
+
+impl Modelfile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read Modelfile {}: {}", path.display(), e))?;
+        let mut modelfile = Self::parse(&text)?;
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for adapter in &mut modelfile.adapters {
+            if Path::new(adapter.as_str()).is_relative() {
+                *adapter = dir.join(&adapter).to_string_lossy().to_string();
+            }
+        }
+        Ok(modelfile)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut modelfile = Self::default();
+        let mut lines = text.lines().enumerate();
+
+        while let Some((number, line)) = lines.next() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (instruction, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let args = args.trim();
+
+            // Skip the body of multi-line values such as TEMPLATE """..."""
+            if args.starts_with("\"\"\"") && (args.len() < 6 || !args.ends_with("\"\"\"")) {
+                for (_, line) in lines.by_ref() {
+                    if line.trim_end().ends_with("\"\"\"") {
+                        break;
+                    }
+                }
+            }
+
+            match instruction.to_uppercase().as_str() {
+                "FROM" => modelfile.from = args.to_string(),
+                "ADAPTER" => modelfile.adapters.push(args.trim_matches('"').to_string()),
+                "PARAMETER" => {
+                    let (name, value) = args.split_once(char::is_whitespace)
+                        .ok_or_else(|| anyhow!("Line {}: PARAMETER needs a name and a value", number + 1))?;
+                    let value = value.trim();
+                    match name {
+                        "adapter_scale" => modelfile.adapter_scale = Some(value.parse()
+                            .map_err(|_| anyhow!("Line {}: invalid adapter_scale {}", number + 1, value))?),
+                        "prompt_lookup" => modelfile.prompt_lookup = Some(value.parse()
+                            .map_err(|_| anyhow!("Line {}: invalid prompt_lookup {}", number + 1, value))?),
+                        _ => tracing::warn!("Modelfile parameter {} is not supported and was ignored", name),
+                    }
+                }
+                other => tracing::warn!("Modelfile instruction {} is not supported and was ignored", other),
+            }
+        }
+
+        if modelfile.from.is_empty() {
+            anyhow::bail!("Modelfile has no FROM instruction");
+        }
+        Ok(modelfile)
+    }
+}
+
+/// Render a model's settings back as a Modelfile, for `show`.
+pub fn render(metadata: &ModelMetadata) -> String {
+    let mut out = format!("FROM {}\n", metadata.parent_model.as_deref().unwrap_or(&metadata.path));
+
+    // `adapter_scale` applies to every adapter, so adapters with scales of their own
+    // can only be described in comments
+    let shared_scale = match metadata.adapters.split_first() {
+        Some((first, rest)) if rest.iter().all(|a| a.scale == first.scale) => Some(first.scale),
+        _ => None,
+    };
+    for adapter in &metadata.adapters {
+        if shared_scale.is_none() {
+            out.push_str(&format!("# scale {}\n", adapter.scale));
+        }
+        out.push_str(&format!("ADAPTER {}\n", adapter.path));
+    }
+    if let Some(scale) = shared_scale {
+        out.push_str(&format!("PARAMETER adapter_scale {}\n", scale));
+    }
+    if let Some(ngram) = metadata.prompt_lookup {
+        out.push_str(&format!("PARAMETER prompt_lookup {}\n", ngram));
+    }
+    out
+}
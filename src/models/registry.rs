@@ -199,7 +199,11 @@ impl ModelRegistry {
     
     /// Get the best GGUF file URL for a model (prefers Q4_K_M quantization)
     pub async fn get_best_gguf_url(&self, repo: &str) -> Result<(String, String, u64)> {
-        let files = self.discover_gguf_files(repo).await?;
+        // Vision projectors live next to the weights but are not models themselves
+        let files: Vec<_> = self.discover_gguf_files(repo).await?
+            .into_iter()
+            .filter(|(name, _, _)| !name.to_lowercase().contains("mmproj"))
+            .collect();
         
         if files.is_empty() {
             return Err(anyhow!("No GGUF files found in repository: {}", repo));
@@ -218,6 +222,30 @@ impl ModelRegistry {
         Ok(files.into_iter().next().unwrap())
     }
     
+    /// Find the vision projector (mmproj) published alongside a HuggingFace model file.
+    /// Returns `None` when the model doesn't come from HuggingFace or the repo has none.
+    pub async fn resolve_projector_url(&self, model_url: &str) -> Result<Option<String>> {
+        let repo = match model_url
+            .strip_prefix(&format!("{}/", self.registry_url))
+            .and_then(|path| path.split_once("/resolve/"))
+        {
+            Some((repo, _)) => repo,
+            None => return Ok(None),
+        };
+        
+        let projectors: Vec<_> = self.discover_gguf_files(repo).await?
+            .into_iter()
+            .filter(|(name, _, _)| name.to_lowercase().contains("mmproj"))
+            .collect();
+        
+        // Prefer the f16 projector; quantized ones cost accuracy for little memory
+        let best = projectors.iter()
+            .find(|(name, _, _)| name.to_lowercase().contains("f16"))
+            .or_else(|| projectors.first());
+        
+        Ok(best.map(|(_, url, _)| url.clone()))
+    }
+    
     pub fn get_huggingface_url(&self, model_name: &str) -> Result<String> {
         let parts: Vec<&str> = model_name.split(':').collect();
         let name = parts[0];
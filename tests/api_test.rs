@@ -1,10 +1,311 @@
 use axum::http::StatusCode;
-use serde_json::json;
+use chrono::Utc;
+use rust_llm_runner::api::handlers::AppState;
+use rust_llm_runner::api::routes::create_router;
+use rust_llm_runner::api::types::{ChatCompletionRequest, Timings};
+use rust_llm_runner::config::Config;
+use rust_llm_runner::inference::backend::InferenceBackend;
+use rust_llm_runner::inference::mock::{MockBackend, MOCK_BOS, MOCK_EMBEDDING_DIM};
+use rust_llm_runner::inference::speculative::SpeculativeStats;
+use rust_llm_runner::models::manager::ModelManager;
+use rust_llm_runner::models::metadata::ModelMetadata;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Serve the API on a free port with `mock` answering for model `mock:latest`.
+async fn spawn_server(mock: MockBackend) -> String {
+    let dir = std::env::temp_dir().join(format!("rust-llm-runner-test-{}", Uuid::new_v4()));
+    let config = Arc::new(Config {
+        models_dir: dir.join("models"),
+        cache_dir: dir.join("cache"),
+        db_path: dir.join("db"),
+        cached_system_prompts: Vec::new(),
+        ..Config::default()
+    });
+    
+    let manager = ModelManager::with_loader(config, move |_, _| {
+        Ok(Arc::new(mock.clone()) as Arc<dyn InferenceBackend>)
+    }).unwrap();
+    manager.save_metadata(&ModelMetadata {
+        name: "mock".to_string(),
+        tag: "latest".to_string(),
+        size: 0,
+        digest: "0123456789abcdef".to_string(),
+        format: "gguf".to_string(),
+        family: "mock".to_string(),
+        parameter_size: "0B".to_string(),
+        quantization_level: "F32".to_string(),
+        created_at: Utc::now(),
+        modified_at: Utc::now(),
+        path: dir.join("models/mock.gguf").to_string_lossy().to_string(),
+        projector: None,
+        draft_model: None,
+        prompt_lookup: None,
+        parent_model: None,
+        adapters: Vec::new(),
+    }).unwrap();
+    
+    let state = Arc::new(AppState {
+        model_manager: Arc::new(manager),
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, create_router(state)).await.unwrap();
+    });
+    
+    format!("http://{}", addr)
+}
+
+fn client() -> reqwest::Client {
+    reqwest::Client::builder().no_proxy().build().unwrap()
+}
+
+async fn post(url: &str, body: Value) -> (StatusCode, String) {
+    let response = client().post(url).json(&body).send().await.unwrap();
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+    (status, response.text().await.unwrap())
+}
+
+async fn post_json(url: &str, body: Value) -> Value {
+    let (status, text) = post(url, body).await;
+    assert_eq!(status, StatusCode::OK, "{}", text);
+    serde_json::from_str(&text).unwrap()
+}
 
 #[tokio::test]
 async fn test_health_endpoint() {
-    let response = "OK";
-    assert_eq!(response, "OK");
+    let server = spawn_server(MockBackend::new()).await;
+    let response = client().get(format!("{}/health", server)).send().await.unwrap();
+    
+    assert_eq!(response.status().as_u16(), StatusCode::OK.as_u16());
+    assert_eq!(response.text().await.unwrap(), "OK");
+}
+
+#[tokio::test]
+async fn test_list_models() {
+    let server = spawn_server(MockBackend::new()).await;
+    let response: Value = client().get(format!("{}/api/tags", server)).send().await.unwrap().json().await.unwrap();
+    
+    assert_eq!(response["models"][0]["name"], "mock:latest");
+}
+
+#[tokio::test]
+async fn test_generate() {
+    let server = spawn_server(MockBackend::new().reply("sky", "Rayleigh scattering.")).await;
+    let response = post_json(&format!("{}/api/generate", server), json!({
+        "model": "mock",
+        "prompt": "Why is the sky blue?",
+        "stream": false
+    })).await;
+    
+    assert_eq!(response["response"], "Rayleigh scattering.");
+    assert_eq!(response["done"], true);
+    assert_eq!(response["eval_count"], 2);
+}
+
+#[tokio::test]
+async fn test_generate_stream() {
+    let server = spawn_server(MockBackend::new().reply("sky", "Rayleigh scattering makes it blue.")).await;
+    let (status, body) = post(&format!("{}/api/generate", server), json!({
+        "model": "mock",
+        "prompt": "Why is the sky blue?",
+        "stream": true
+    })).await;
+    assert_eq!(status, StatusCode::OK);
+    
+    let chunks: Vec<Value> = body.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let text: String = chunks.iter().filter_map(|c| c["response"].as_str()).collect();
+    assert_eq!(text, "Rayleigh scattering makes it blue.");
+    assert_eq!(chunks.last().unwrap()["done"], true);
+}
+
+#[tokio::test]
+async fn test_generate_options() {
+    let server = spawn_server(MockBackend::new().reply("count", "one two three. four five")).await;
+    let url = format!("{}/api/generate", server);
+    
+    let limited = post_json(&url, json!({
+        "model": "mock",
+        "prompt": "count",
+        "stream": false,
+        "options": {"num_predict": 2}
+    })).await;
+    assert_eq!(limited["response"], "one two ");
+    assert_eq!(limited["done_reason"], "length");
+    
+    let stopped = post_json(&url, json!({
+        "model": "mock",
+        "prompt": "count",
+        "stream": false,
+        "options": {"stop": ["."]}
+    })).await;
+    assert_eq!(stopped["response"], "one two three");
+    assert_eq!(stopped["done_reason"], "stop");
+}
+
+#[tokio::test]
+async fn test_chat_completions() {
+    let server = spawn_server(MockBackend::new().reply("Hello", "Hi there!")).await;
+    let response = post_json(&format!("{}/v1/chat/completions", server), json!({
+        "model": "mock:latest",
+        "messages": [{"role": "user", "content": "Hello"}],
+        "seed": 7
+    })).await;
+    
+    assert_eq!(response["object"], "chat.completion");
+    assert_eq!(response["choices"][0]["message"]["role"], "assistant");
+    assert_eq!(response["choices"][0]["message"]["content"], "Hi there!");
+    assert_eq!(response["choices"][0]["finish_reason"], "stop");
+    assert_eq!(response["usage"]["completion_tokens"], 2);
+    assert!(response["system_fingerprint"].as_str().unwrap().starts_with("fp_mock"));
+}
+
+#[tokio::test]
+async fn test_chat_completions_stream() {
+    let server = spawn_server(MockBackend::new().reply("Hello", "Hi there, how can I help?")).await;
+    let (status, body) = post(&format!("{}/v1/chat/completions", server), json!({
+        "model": "mock",
+        "messages": [{"role": "user", "content": "Hello"}],
+        "stream": true
+    })).await;
+    assert_eq!(status, StatusCode::OK);
+    
+    let data: Vec<&str> = body.lines().filter_map(|line| line.strip_prefix("data: ")).collect();
+    assert_eq!(data.last(), Some(&"[DONE]"));
+    
+    let content: String = data[..data.len() - 1].iter()
+        .map(|chunk| serde_json::from_str::<Value>(chunk).unwrap())
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str().map(str::to_string))
+        .collect();
+    assert_eq!(content, "Hi there, how can I help?");
+}
+
+#[tokio::test]
+async fn test_chat_completions_length() {
+    let server = spawn_server(MockBackend::new().reply("Hello", "Hi there, how can I help?")).await;
+    let url = format!("{}/v1/chat/completions", server);
+    let request = json!({
+        "model": "mock",
+        "messages": [{"role": "user", "content": "Hello"}],
+        "max_tokens": 2
+    });
+    
+    let response = post_json(&url, request.clone()).await;
+    assert_eq!(response["choices"][0]["finish_reason"], "length");
+    
+    let mut streamed = request;
+    streamed["stream"] = json!(true);
+    let (_, body) = post(&url, streamed).await;
+    let finish_reasons: Vec<Value> = body.lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter_map(|chunk| serde_json::from_str::<Value>(chunk).ok())
+        .map(|chunk| chunk["choices"][0]["finish_reason"].clone())
+        .filter(|reason| !reason.is_null())
+        .collect();
+    assert_eq!(finish_reasons, vec![json!("length")]);
+}
+
+#[tokio::test]
+async fn test_completions_choices() {
+    let server = spawn_server(MockBackend::new()).await;
+    let response = post_json(&format!("{}/v1/completions", server), json!({
+        "model": "mock",
+        "prompt": "Say hello",
+        "n": 2,
+        "max_tokens": 3
+    })).await;
+    
+    let choices = response["choices"].as_array().unwrap();
+    assert_eq!(choices.len(), 2);
+    assert_eq!(choices[0]["text"], "Hello from the ");
+    assert_eq!(choices[0]["finish_reason"], "length");
+}
+
+#[tokio::test]
+async fn test_choice_count_bounds() {
+    let server = spawn_server(MockBackend::new()).await;
+    for (path, body) in [
+        ("/v1/completions", json!({"model": "mock", "prompt": "Hi", "n": 0})),
+        ("/v1/completions", json!({"model": "mock", "prompt": "Hi", "n": 129})),
+        ("/v1/completions", json!({"model": "mock", "prompt": "Hi", "best_of": 129})),
+        ("/v1/chat/completions", json!({"model": "mock", "messages": [{"role": "user", "content": "Hi"}], "n": 0})),
+    ] {
+        let (status, text) = post(&format!("{}{}", server, path), body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", text);
+        assert!(text.contains("between 1 and 128"), "{}", text);
+    }
+}
+
+#[tokio::test]
+async fn test_tokenize_roundtrip() {
+    let server = spawn_server(MockBackend::new()).await;
+    
+    let tokenized = post_json(&format!("{}/api/tokenize", server), json!({
+        "model": "mock",
+        "content": "Hi"
+    })).await;
+    assert_eq!(tokenized["tokens"], json!([MOCK_BOS, 72, 105]));
+    
+    let pieces = post_json(&format!("{}/api/tokenize", server), json!({
+        "model": "mock",
+        "content": "Hi",
+        "add_special": false,
+        "with_pieces": true
+    })).await;
+    assert_eq!(pieces["tokens"], json!([{"id": 72, "piece": "H"}, {"id": 105, "piece": "i"}]));
+    
+    let detokenized = post_json(&format!("{}/api/detokenize", server), json!({
+        "model": "mock",
+        "tokens": [MOCK_BOS, 72, 105],
+        "render_special": false
+    })).await;
+    assert_eq!(detokenized["content"], "Hi");
+}
+
+#[tokio::test]
+async fn test_embed() {
+    let server = spawn_server(MockBackend::new()).await;
+    let response = post_json(&format!("{}/api/embed", server), json!({
+        "model": "mock",
+        "input": ["first document", "second document"]
+    })).await;
+    
+    let embeddings = response["embeddings"].as_array().unwrap();
+    assert_eq!(embeddings.len(), 2);
+    for embedding in embeddings {
+        let values: Vec<f64> = embedding.as_array().unwrap().iter().map(|v| v.as_f64().unwrap()).collect();
+        assert_eq!(values.len(), MOCK_EMBEDDING_DIM);
+        let norm = values.iter().map(|v| v * v).sum::<f64>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-4);
+    }
+}
+
+#[tokio::test]
+async fn test_rerank() {
+    let server = spawn_server(MockBackend::new()).await;
+    let response = post_json(&format!("{}/v1/rerank", server), json!({
+        "model": "mock",
+        "query": "reset password",
+        "documents": ["Our office opens at 9am.", "Click Forgot password to reset it."]
+    })).await;
+    
+    assert_eq!(response["results"][0]["index"], 1);
+    assert_eq!(response["results"][0]["document"]["text"], "Click Forgot password to reset it.");
+}
+
+#[tokio::test]
+async fn test_unknown_model() {
+    let server = spawn_server(MockBackend::new()).await;
+    let (status, body) = post(&format!("{}/api/generate", server), json!({
+        "model": "missing",
+        "prompt": "Hello",
+        "stream": false
+    })).await;
+    
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body.contains("Model not found"));
 }
 
 #[test]
@@ -33,3 +334,30 @@ fn test_generate_request_serialization() {
     assert_eq!(request["model"], "qwen3:latest");
     assert_eq!(request["prompt"], "Test prompt");
 }
+
+#[test]
+fn test_seed_deserialization() {
+    let seed = |seed: Value| {
+        let request: ChatCompletionRequest = serde_json::from_value(json!({
+            "model": "mock",
+            "messages": [],
+            "seed": seed
+        })).unwrap();
+        request.seed
+    };
+    
+    assert_eq!(seed(json!(42)), Some(42));
+    assert_eq!(seed(json!(-1)), None);
+    assert_eq!(seed(json!(null)), None);
+    assert_eq!(seed(json!((1i64 << 32) + 7)), Some(7));
+}
+
+#[test]
+fn test_timings_acceptance_rate() {
+    let timings = Timings::from(SpeculativeStats { drafted: 8, accepted: 6 });
+    assert_eq!(serde_json::to_value(&timings).unwrap(), json!({
+        "draft_n": 8,
+        "draft_n_accepted": 6,
+        "draft_acceptance_rate": 0.75
+    }));
+}
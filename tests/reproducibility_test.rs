@@ -0,0 +1,84 @@
+// Seeded generation must give the same output on every run on CPU. These tests need
+// a pulled model, so they are ignored by default; run them with RUST_LLM_TEST_MODEL
+// naming one, e.g.
+//   RUST_LLM_TEST_MODEL=qwen3:latest cargo test --test reproducibility_test -- --ignored
+use rust_llm_runner::config::Config;
+use rust_llm_runner::inference::backend::InferenceBackend;
+use rust_llm_runner::inference::{GenerationConfig, GenerationRequest, StreamEvent};
+use rust_llm_runner::models::manager::ModelManager;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+const PROMPT: &str = "Write a short poem about the sea.";
+
+/// Loaded once for all tests: they run in parallel and the model database can only be
+/// opened by one manager at a time
+static ENGINE: OnceCell<Arc<dyn InferenceBackend>> = OnceCell::const_new();
+
+async fn load_cpu_engine() -> Arc<dyn InferenceBackend> {
+    ENGINE.get_or_init(|| async {
+        let model = std::env::var("RUST_LLM_TEST_MODEL")
+            .expect("RUST_LLM_TEST_MODEL must name a pulled model");
+        let config = Arc::new(Config::load().unwrap());
+        let manager = ModelManager::new(config).unwrap();
+        let (name, tag) = model.split_once(':').unwrap_or((&model, "latest"));
+        // GPU kernels may reduce in a different order from run to run; CPU ones don't
+        manager.load_model_with_gpu(&name.replace('/', "_"), tag, Some(0)).await.unwrap()
+    }).await.clone()
+}
+
+fn seeded_request(seed: u32) -> GenerationRequest {
+    GenerationRequest {
+        prompt: PROMPT.to_string(),
+        config: GenerationConfig {
+            temperature: 1.0,
+            seed: Some(seed),
+            max_tokens: 48,
+            num_thread: Some(4),
+            ..Default::default()
+        },
+        context: None,
+        images: Vec::new(),
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs RUST_LLM_TEST_MODEL"]
+async fn test_same_seed_same_output() {
+    let engine = load_cpu_engine().await;
+
+    let first = engine.generate(seeded_request(1234)).await.unwrap();
+    let second = engine.generate(seeded_request(1234)).await.unwrap();
+    assert_eq!(first.text, second.text);
+    assert_eq!(first.tokens_generated, second.tokens_generated);
+
+    let other = engine.generate(seeded_request(4321)).await.unwrap();
+    assert_ne!(first.text, other.text);
+}
+
+#[tokio::test]
+#[ignore = "needs RUST_LLM_TEST_MODEL"]
+async fn test_stream_matches_generate() {
+    let engine = load_cpu_engine().await;
+
+    let response = engine.generate(seeded_request(99)).await.unwrap();
+
+    let mut rx = engine.generate_stream(seeded_request(99)).await.unwrap();
+    let mut streamed = String::new();
+    while let Some(event) = rx.recv().await {
+        if let StreamEvent::Token(token) = event.unwrap() {
+            streamed.push_str(&token);
+        }
+    }
+    assert_eq!(response.text, streamed);
+}
+
+#[tokio::test]
+#[ignore = "needs RUST_LLM_TEST_MODEL"]
+async fn test_fingerprint_is_stable() {
+    let engine = load_cpu_engine().await;
+
+    let fingerprint = engine.system_fingerprint();
+    assert!(fingerprint.starts_with("fp_"));
+    assert_eq!(fingerprint, engine.system_fingerprint());
+}